@@ -2,6 +2,6 @@ ADD R0, R1
 ADD (R0), R1 ; bon j'peux commenter aussi
 ADD (R1), R3
 ADD -(R3), R4
-ADD (R5), R8
+ADD (R5), R2
 PUSH @0xFA
 MOVE.L R3, @0x22
\ No newline at end of file
@@ -0,0 +1,598 @@
+//! Two passes assembler, from source files to a program.
+//!
+//! The first pass walks the files to give an address to every label, the
+//! second one parses each line again, now that every label can be resolved.
+//! Several files are assembled as a single program : they are laid out one
+//! after the other and share their labels.
+//!
+//! The includes and the macros are expanded first (see `preprocessor`). A line then holds either an
+//! instruction or a directive (see `directive`), a label defined on it gets
+//! the address where its content starts.
+//!
+//! The constants are given their final value between the two passes, once
+//! every label is known. A constant may use the labels defined anywhere, but
+//! only the constants defined before it.
+//!
+//! The sections are laid out one after the other : `.text` from the origin,
+//! then `.data`, then `.bss`. A relocatable object leaves it to the linker
+//! (see `object`) : every section starts at 0, the words holding an address
+//! are listed by relocations.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use crate::ast::{self, Directive, Node, Statement};
+use crate::directive::{self, Declaration, Section};
+use crate::encoder::{self, encode};
+use crate::error::{AsmError, AsmErrorKind, Location, ParseError, Span};
+use crate::expr::{self, Base, Value};
+use crate::object::{Relocation, Target};
+use crate::preprocessor::{self, FileLoader, Loader};
+use crate::parser::{self, Constant, Constants, Context, Dialect, InstructionFormat, SymbolTable, Symbols};
+use crate::symbols::{Kind, Symbol};
+
+/// A source file and its content
+pub struct Source<'a> {
+    pub file: &'a str,
+    pub text: &'a str,
+}
+
+/// How to assemble the sources
+pub struct Settings<'a> {
+    /// Address of the first instruction
+    pub origin: u16,
+    /// Reads the included files
+    pub loader: &'a dyn Loader,
+    /// Constants defined outside of the sources (the `-D` of the command line)
+    pub defines: &'a [(String, i64)],
+    /// Build a relocatable object instead of a program placed at `origin`
+    pub relocatable: bool,
+    pub dialect: Dialect,
+}
+
+/// A file of the program, given to the assembler or included
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceFile {
+    pub name: String,
+    pub text: String,
+    /// The file and the line (0 based) of the `.include`, `None` for the given sources
+    pub included: Option<(usize, usize)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Content {
+    Instruction(InstructionFormat),
+    /// Bytes emitted by a directive
+    Data(Vec<u8>),
+}
+
+impl Content {
+    /// The bytes as they are stored in memory, the low byte of a word first
+    pub fn bytes(&self) -> Vec<u8> {
+        match self {
+            Content::Instruction(instruction) => encode(instruction).iter()
+                .flat_map(|word| word.to_le_bytes())
+                .collect(),
+            Content::Data(bytes) => bytes.clone(),
+        }
+    }
+
+    fn size(&self) -> u16 {
+        match self {
+            Content::Instruction(instruction) => (encoder::length(instruction) * 2) as u16,
+            Content::Data(bytes) => bytes.len() as u16,
+        }
+    }
+}
+
+/// An instruction (or data) along with where it comes from and where it lands
+pub struct Assembled {
+    pub section: Section,
+    /// Index of the file in `Program::files`
+    pub file: usize,
+    /// Index of the source line (0 based), the macro call for an expanded line
+    pub line: usize,
+    /// Index of the line in `Program::expansions` for an expanded line
+    pub expansion: Option<usize>,
+    pub address: u16,
+    pub content: Content,
+}
+
+/// A line expanded from a macro, as it has been assembled
+pub struct ExpandedLine {
+    /// Index of the file in `Program::files` of the outermost call
+    pub file: usize,
+    /// Index of the line (0 based) of the outermost call
+    pub line: usize,
+    /// Number of nested calls the line comes from, 1 for a line of the called macro
+    pub depth: usize,
+    /// The line once its parameters are substituted
+    pub text: String,
+}
+
+/// Where a section has been placed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Placement {
+    pub section: Section,
+    pub start: u16,
+    /// Bytes up to the end of the section
+    pub size: u16,
+    /// What the start has to be a multiple of, for the `.align` of the section (up to 0x10000)
+    pub alignment: u32,
+}
+
+pub struct Program {
+    /// Address of the first instruction
+    pub origin: u16,
+    /// Every section, in the order of `Section::ALL`
+    pub sections: Vec<Placement>,
+    /// The content of `.text` and `.data`, `.bss` has none
+    pub items: Vec<Assembled>,
+    pub symbols: SymbolTable,
+    /// The given sources, then the included files
+    pub files: Vec<SourceFile>,
+    /// Lines expanded from the macros, in the order of the program
+    pub expansions: Vec<ExpandedLine>,
+    /// Every label and constant, with its cross-references
+    pub map: Vec<Symbol>,
+    /// Names given to the other objects by `.global`
+    pub exports: Vec<String>,
+    /// Names taken from the other objects by `.extern`, only for a relocatable object
+    pub imports: Vec<String>,
+    /// Words of a relocatable object holding an address
+    pub relocations: Vec<Relocation>,
+}
+
+impl Program {
+    /// The memory image from `origin`, the holes left by `.org`/`.align` are 0
+    pub fn bytes(&self) -> Vec<u8> {
+        let origin = self.origin as usize;
+        let end = self.items.iter()
+            .map(|item| item.address as usize + item.content.size() as usize)
+            .max()
+            .unwrap_or(origin);
+
+        // The assembler puts every item between the origin and the end of the memory
+        let mut image = vec![0; end.saturating_sub(origin)];
+        for item in &self.items {
+            let Some(start) = (item.address as usize).checked_sub(origin) else {
+                continue;
+            };
+            let bytes = item.content.bytes();
+            image[start..start + bytes.len()].copy_from_slice(&bytes);
+        }
+
+        image
+    }
+
+    /// The machine words of the whole program
+    pub fn words(&self) -> Vec<u16> {
+        encoder::words(&self.bytes())
+    }
+}
+
+/// Parse the instruction or the directive of a line, returns where its content
+/// starts and the content
+fn assemble_statement(statement: Option<&Statement>, context: &Context) -> Result<(u16, Vec<Content>), ParseError> {
+    match statement {
+        Some(Statement::Directive(directive)) => {
+            let placed = directive::parse(directive, context)?;
+            let content = if placed.bytes.is_empty() { Vec::new() } else { vec![Content::Data(placed.bytes)] };
+
+            Ok((placed.address, content))
+        },
+        Some(Statement::Instruction(instruction)) => {
+            let encoded = parser::parse(instruction, context)?;
+            if !context.address.is_multiple_of(2) {
+                return Err(ParseError::new(instruction.mnemonic.span, AsmErrorKind::MisalignedInstruction(context.address)));
+            }
+
+            Ok((context.address, vec![Content::Instruction(encoded)]))
+        },
+        Some(Statement::Label(_)) | None => Ok((context.address, Vec::new())),
+    }
+}
+
+/// Whether `directive` only reserves space, the only thing .bss holds
+fn reserves(directive: &Directive) -> bool {
+    directive.name.text.eq_ignore_ascii_case(".space") && directive.arguments.len() == 1
+}
+
+/// The name, the expression and the value of the constant defined by
+/// `directive`, `None` when it doesn't define one
+fn define_constant<'a>(directive: &Directive<'a>, context: &Context) -> Option<Result<(Node<'a>, Node<'a>, Value), ParseError>> {
+    directive::constant(directive).map(|definition| {
+        let (name, expression) = definition?;
+        let value = expr::evaluate(expression, context)?;
+        value.bits(expression, 16)?;
+
+        Ok((name, expression, value))
+    })
+}
+
+/// The labels and the constants, along with the lines (given by their index)
+/// defining and using them
+fn symbol_map(
+    symbols: &Symbols,
+    constants: &Constants,
+    mut references: HashMap<String, Vec<usize>>,
+    location: impl Fn(usize) -> Location,
+) -> Vec<Symbol> {
+    let mut used_by = |name: &str| {
+        let mut lines = references.remove(name).unwrap_or_default();
+        lines.sort();
+        // The lines expanded from a macro are located at its call
+        let mut locations: Vec<Location> = lines.into_iter().map(&location).collect();
+        locations.dedup();
+        locations
+    };
+
+    let mut map = Vec::new();
+    for (name, address) in &symbols.addresses {
+        map.push(Symbol {
+            name: name.clone(),
+            value: *address,
+            kind: Kind::Label,
+            section: Some(symbols.sections[name]),
+            defined: Some(location(symbols.lines[name])),
+            references: used_by(name),
+        });
+    }
+    for (name, constant) in constants {
+        map.push(Symbol {
+            name: name.clone(),
+            value: constant.value.value as u16,
+            kind: Kind::Constant,
+            section: match constant.value.base {
+                Base::Section(section) => Some(section),
+                _ => None,
+            },
+            defined: constant.line.map(&location),
+            references: used_by(name),
+        });
+    }
+    for (name, line) in &symbols.imports {
+        map.push(Symbol {
+            name: name.clone(),
+            value: 0,
+            kind: Kind::Import,
+            section: None,
+            defined: Some(location(*line)),
+            references: used_by(name),
+        });
+    }
+
+    map
+}
+
+/// Assemble `source`, its first instruction being placed at `origin`.
+///
+/// `file` is only used to locate the errors, every error of the file is
+/// reported instead of stopping at the first one.
+pub fn assemble(file: &str, source: &str, origin: u16) -> Result<Program, Vec<AsmError>> {
+    assemble_all(&[Source { file, text: source }], origin)
+}
+
+/// Assemble several files as a single program starting at `origin`, the
+/// included files are looked for next to the including one
+pub fn assemble_all(sources: &[Source], origin: u16) -> Result<Program, Vec<AsmError>> {
+    assemble_with(sources, &Settings { origin, loader: &FileLoader::default(), defines: &[], relocatable: false, dialect: Dialect::default() })
+}
+
+/// Same as `assemble_all`, with more settings
+pub fn assemble_with(sources: &[Source], settings: &Settings) -> Result<Program, Vec<AsmError>> {
+    let origin = if settings.relocatable { 0 } else { settings.origin };
+    let preprocessor::Preprocessed { lines, files, mut errors } = preprocessor::preprocess(sources, settings);
+
+    // The errors are kept along with the index of their line, to be sorted
+    let error_at = |n: usize, span: Span, kind: AsmErrorKind| (n, lines[n].error(&files, span, kind));
+    let to_error = |n: usize, error: ParseError| error_at(n, error.span, error.kind);
+    let duplicate = |n: usize, name: Node, previous: Option<usize>| {
+        let previous = previous.map(|previous| lines[previous].location(&files));
+        error_at(n, name.span, AsmErrorKind::DuplicateLabel { name: name.text.to_string(), previous })
+    };
+
+    // The label, then the instruction or the directive of each line
+    let statements: Vec<Vec<Statement>> = lines.iter().map(|line| ast::parse_line(&line.text, settings.dialect)).collect();
+    let label = |n: usize| statements[n].iter().find_map(Statement::label);
+    let body = |n: usize| statements[n].iter().find(|statement| statement.label().is_none());
+    let directive = |n: usize| body(n).and_then(Statement::directive);
+    // The instruction or directive of a line, for its errors
+    let span = |n: usize| match body(n) {
+        Some(Statement::Directive(directive)) => directive.span,
+        Some(Statement::Instruction(instruction)) => instruction.span,
+        _ => Span { start: 0, end: lines[n].text.len() },
+    };
+    // Where a statement placed at `start` begins, its section being at `counter`:
+    // only an .org moves it, forward, and the counter may be the end of the memory
+    let position = |counter: u32, start: u16| if start == counter as u16 { counter } else { start as u32 };
+    // Where its contents end, `None` past the end of the memory
+    let end_of = |position: u32, contents: &[Content]| {
+        Some(contents.iter().fold(position, |address, content| address + content.size() as u32))
+            .filter(|end| *end <= 0x10000)
+    };
+
+    // The global label each line belongs to, the labels of the macros don't count
+    let scopes: Vec<Option<&str>> = lines.iter()
+        .enumerate()
+        .scan(None, |scope, (n, line)| {
+            match label(n) {
+                Some(label) if parser::is_global(label.text) && line.calls.is_empty() => *scope = Some(label.text),
+                _ => { },
+            }
+            Some(*scope)
+        })
+        .collect();
+
+    // The section of each line, a line changing it is already in the new one
+    let sections: Vec<Section> = (0..lines.len())
+        .scan(Section::Text, |section, n| {
+            if let Some(Ok(Declaration::Section(next))) = directive(n).and_then(directive::declaration) {
+                *section = next;
+            }
+            Some(*section)
+        })
+        .collect();
+
+    // First pass: give an address to every label. Only `.text` knows where it
+    // starts, the other sections are moved once their predecessors are done
+    let mut symbols = Symbols::default();
+    let mut constants: Constants = settings.defines.iter()
+        .map(|(name, value)| (name.clone(), Constant { value: Value::number(*value), line: None }))
+        .collect();
+    // Up to 0x10000, the end of the memory
+    let mut counters: HashMap<Section, u32> = HashMap::from([(Section::Text, origin as u32), (Section::Data, 0), (Section::Bss, 0)]);
+    // Lines already reported by the first pass, no need to parse them again
+    let mut failed = vec![false; lines.len()];
+    // Lines defining a constant, along with their address
+    let mut definitions = Vec::new();
+    // Lines exporting names, along with the names
+    let mut exports = Vec::new();
+    // What the start of each section is a multiple of, for its `.align` to hold once moved
+    let mut alignments: HashMap<Section, u32> = Section::ALL.into_iter().map(|section| (section, 2)).collect();
+
+    for n in 0..lines.len() {
+        let label = label(n);
+        let section = sections[n];
+        let address = counters[&section] as u16;
+
+        let context = Context {
+            scope: scopes[n],
+            section,
+            dialect: settings.dialect,
+            address,
+            line: n,
+            first_pass: true,
+            ..Context::new(&symbols, &constants)
+        };
+        let start = if let Some(declaration) = directive(n).and_then(directive::declaration) {
+            match declaration {
+                Ok(Declaration::Section(_)) => { },
+                Ok(Declaration::Global(names)) => exports.push((n, names)),
+                // The sources of a program share their labels, nothing to import
+                Ok(Declaration::Extern(_)) if !settings.relocatable => { },
+                Ok(Declaration::Extern(names)) => for name in names {
+                    let previous = match constants.get(name.text) {
+                        Some(constant) => Some(constant.line),
+                        None => symbols.import(name.text, n).err().map(Some),
+                    };
+                    if let Some(previous) = previous {
+                        errors.push(duplicate(n, name, previous));
+                    }
+                },
+                Err(error) => {
+                    errors.push(to_error(n, error));
+                    failed[n] = true;
+                },
+            }
+            address
+        } else if let Some(definition) = directive(n).and_then(|directive| define_constant(directive, &context)) {
+            match definition {
+                Ok((name, _, _)) if symbols.line(name.text).is_some() || constants.contains_key(name.text) => {
+                    let previous = symbols.line(name.text).or_else(|| constants[name.text].line);
+                    errors.push(duplicate(n, name, previous));
+                    failed[n] = true;
+                },
+                Ok((name, _, value)) => {
+                    constants.insert(name.text.to_string(), Constant { value, line: Some(n) });
+                    definitions.push(n);
+                },
+                Err(error) => {
+                    errors.push(to_error(n, error));
+                    failed[n] = true;
+                },
+            }
+            address
+        } else {
+            match assemble_statement(body(n), &context) {
+                Ok((start, contents)) => {
+                    if let Some(Ok(alignment)) = directive(n).and_then(|directive| directive::alignment(directive, &context)) {
+                        alignments.insert(section, directive::common_alignment(alignments[&section], alignment as u32));
+                    }
+                    match end_of(position(counters[&section], start), &contents) {
+                        Some(end) => {
+                            counters.insert(section, end);
+                        },
+                        None => {
+                            errors.push(error_at(n, span(n), AsmErrorKind::Overflow));
+                            failed[n] = true;
+                        },
+                    }
+                    start
+                },
+                Err(error) => {
+                    errors.push(to_error(n, error));
+                    failed[n] = true;
+                    address
+                },
+            }
+        };
+
+        if let Some(label) = label {
+            let previous = match constants.get(label.text) {
+                Some(constant) => Some(constant.line),
+                None => symbols.define(label.text, scopes[n], n, section, start).err().map(Some),
+            };
+            if let Some(previous) = previous {
+                errors.push(duplicate(n, label, previous));
+            }
+        }
+    }
+
+    // Each section follows the previous one, on a multiple of its alignments
+    let mut placements = Vec::new();
+    let mut starts = Vec::new();
+    let mut end = origin as u32;
+    for section in Section::ALL {
+        let start = match section {
+            Section::Text => origin as u32,
+            _ if settings.relocatable => 0,
+            _ => end.next_multiple_of(alignments[&section]),
+        };
+        let size = match section {
+            Section::Text => counters[&section] - origin as u32,
+            _ => counters[&section],
+        };
+
+        // A section going past the memory is reported by the second pass, on its lines
+        if section != Section::Text {
+            symbols.relocate(section, start as u16);
+        }
+        placements.push(Placement { section, start: start as u16, size: size as u16, alignment: alignments[&section] });
+        starts.push((section, start));
+        end = start + size;
+    }
+    let base = |section: Section| placements.iter().find(|placement| placement.section == section).unwrap().start;
+
+    for (n, names) in &exports {
+        for name in names {
+            if !symbols.lines.contains_key(name.text) && !constants.contains_key(name.text) {
+                errors.push(error_at(*n, name.span, AsmErrorKind::UndefinedExport(name.text.to_string())));
+            }
+        }
+    }
+
+    // Names used by the line being parsed, then by every line for the cross-references
+    let used = RefCell::new(Vec::new());
+    let mut references: HashMap<String, Vec<usize>> = HashMap::new();
+    let mut record = |n: usize| for name in used.take() {
+        references.entry(name).or_default().push(n);
+    };
+
+    // Every label is known, the constants get their final value
+    for n in definitions {
+        let context = Context {
+            scope: scopes[n],
+            section: sections[n],
+            dialect: settings.dialect,
+            line: n,
+            references: Some(&used),
+            ..Context::new(&symbols, &constants)
+        };
+        let definition = directive(n).and_then(|directive| define_constant(directive, &context));
+        record(n);
+        match definition {
+            Some(Ok((_, expression, value))) if value.forward => {
+                errors.push(error_at(n, expression.span, AsmErrorKind::ForwardReference(expression.text.to_string())));
+                failed[n] = true;
+            },
+            Some(Ok((name, _, value))) => {
+                constants.insert(name.text.to_string(), Constant { value, line: Some(n) });
+            },
+            Some(Err(error)) => {
+                errors.push(to_error(n, error));
+                failed[n] = true;
+            },
+            None => unreachable!("line {} defines a constant", n),
+        }
+    }
+
+    // Second pass: every label is known, build the instructions
+    let mut items = Vec::new();
+    let mut expansions = Vec::new();
+    let mut relocations = Vec::new();
+    let fixups = RefCell::new(Vec::new());
+    counters.extend(starts);
+
+    for (n, line) in lines.iter().enumerate() {
+        let (file, i) = line.site();
+        let section = sections[n];
+
+        let expansion = (!line.calls.is_empty()).then(|| {
+            expansions.push(ExpandedLine { file, line: i, depth: line.calls.len(), text: line.text.to_string() });
+            expansions.len() - 1
+        });
+
+        let declared = directive(n).is_some_and(|directive| directive::constant(directive).is_some() || directive::declaration(directive).is_some());
+        if failed[n] || declared {
+            continue;
+        }
+
+        let context = Context {
+            scope: scopes[n],
+            section,
+            dialect: settings.dialect,
+            address: counters[&section] as u16,
+            line: n,
+            references: Some(&used),
+            fixups: settings.relocatable.then_some(&fixups),
+            ..Context::new(&symbols, &constants)
+        };
+        let assembled = assemble_statement(body(n), &context);
+        record(n);
+        match assembled {
+            // The space of .bss is only reserved, by .space without a fill
+            Ok((_, contents)) if section == Section::Bss && !contents.is_empty() && !directive(n).is_some_and(reserves) => {
+                errors.push(error_at(n, span(n), AsmErrorKind::DataInBss));
+            },
+            Ok((start, contents)) if end_of(position(counters[&section], start), &contents).is_none() => {
+                errors.push(error_at(n, span(n), AsmErrorKind::Overflow));
+            },
+            Ok((start, contents)) => {
+                for fixup in fixups.take() {
+                    let target = match fixup.base {
+                        Base::Section(section) => Target::Section(section),
+                        Base::Import(index) => Target::Symbol(symbols.imports[index].0.clone()),
+                        base => unreachable!("{:?} isn't relocated", base),
+                    };
+                    let offset = start.wrapping_add(fixup.offset).wrapping_sub(base(section));
+                    relocations.push(Relocation { section, offset, pc_relative: fixup.pc_relative, target });
+                }
+
+                let mut address = position(counters[&section], start);
+                for content in contents {
+                    let size = content.size() as u32;
+                    if section != Section::Bss {
+                        items.push(Assembled { section, file, line: i, expansion, address: address as u16, content });
+                    }
+                    address += size;
+                }
+                counters.insert(section, address);
+            },
+            Err(error) => errors.push(to_error(n, error)),
+        }
+    }
+
+    if !errors.is_empty() {
+        // In the order of the lines, whatever the pass finding them
+        errors.sort_by_key(|(n, _)| *n);
+        return Err(errors.into_iter().map(|(_, error)| error).collect());
+    }
+
+    let map = symbol_map(&symbols, &constants, references, |n| lines[n].location(&files));
+    let exports = exports.into_iter().flat_map(|(_, names)| names).map(|name| name.text.to_string()).collect();
+    let imports = symbols.imports.iter().map(|(name, _)| name.clone()).collect();
+
+    Ok(Program {
+        origin,
+        sections: placements,
+        items,
+        symbols: symbols.addresses,
+        files,
+        expansions,
+        map,
+        exports,
+        imports,
+        relocations,
+    })
+}
@@ -0,0 +1,328 @@
+//! Syntax tree of a line of assembly, built from the tokens of the `lexer`.
+//!
+//! A line holds an optional label, then an instruction or a directive :
+//!
+//! ```text
+//! LOOP: ADD #1, (R0)+   | Label(LOOP), Instruction { ADD, [Immediate(1), PostIncrement(R0)] }
+//! .word 1, LABEL + 2    | Directive { .word, [1, LABEL + 2] }
+//! SIZE = 4              | Directive { =, [SIZE, 4] }
+//! ```
+//!
+//! The operands are told apart by their shape, their registers are read
+//! through the dialect. The encoder (`parser`) evaluates the expressions and
+//! checks the addressing modes. Every node knows where it is in the line, the
+//! errors point at it.
+
+use crate::error::Span;
+use crate::lexer::{Lexer, Token, TokenKind};
+use crate::parser::{self, Dialect};
+
+/// A part of a line : a name, an expression, an argument...
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Node<'a> {
+    pub text: &'a str,
+    pub span: Span,
+}
+
+impl<'a> Node<'a> {
+    /// The whole of `text`, given apart from any line (`-D NAME=value`...)
+    pub fn whole(text: &'a str) -> Node<'a> {
+        Node { text, span: Span { start: 0, end: text.len() } }
+    }
+}
+
+impl<'a> From<&Token<'a>> for Node<'a> {
+    fn from(token: &Token<'a>) -> Node<'a> {
+        Node { text: token.text, span: token.span }
+    }
+}
+
+/// An operand, by its addressing mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OperandExpr<'a> {
+    /// `R0`, by the number of the register
+    Register(u8),
+    /// `(R0)`
+    Indirect(u8),
+    /// `(R0)+`
+    PostIncrement(u8),
+    /// `-(R0)`
+    PreDecrement(u8),
+    /// `#expression`, the expression without its `#`
+    Immediate(Node<'a>),
+    /// `@expression`, the expression without its `@`
+    Address(Node<'a>),
+    /// Anything else, `+(R0)` and the unknown registers included
+    Invalid,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Operand<'a> {
+    /// The whole operand
+    pub text: &'a str,
+    pub span: Span,
+    pub expr: OperandExpr<'a>,
+}
+
+impl<'a> Operand<'a> {
+    pub fn node(&self) -> Node<'a> {
+        Node { text: self.text, span: self.span }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction<'a> {
+    pub mnemonic: Node<'a>,
+    pub operands: Vec<Operand<'a>>,
+    /// The whole instruction, without the comment
+    pub text: &'a str,
+    pub span: Span,
+}
+
+/// A directive, or the `=` of a constant whose arguments are the name and the expression
+#[derive(Debug, Clone, PartialEq)]
+pub struct Directive<'a> {
+    pub name: Node<'a>,
+    pub arguments: Vec<Node<'a>>,
+    /// The whole directive, without the comment
+    pub text: &'a str,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement<'a> {
+    Label(Node<'a>),
+    Instruction(Instruction<'a>),
+    Directive(Directive<'a>),
+}
+
+impl<'a> Statement<'a> {
+    pub fn label(&self) -> Option<Node<'a>> {
+        match self {
+            Statement::Label(label) => Some(*label),
+            _ => None,
+        }
+    }
+
+    pub fn directive(&self) -> Option<&Directive<'a>> {
+        match self {
+            Statement::Directive(directive) => Some(directive),
+            _ => None,
+        }
+    }
+
+    /// The name of the directive or the mnemonic of the instruction, along
+    /// with its arguments or its operands. `None` for a label
+    pub fn keyword(&self) -> Option<(Node<'a>, Vec<Node<'a>>)> {
+        match self {
+            Statement::Label(_) => None,
+            Statement::Instruction(instruction) => Some((instruction.mnemonic, instruction.operands.iter().map(Operand::node).collect())),
+            Statement::Directive(directive) => Some((directive.name, directive.arguments.clone())),
+        }
+    }
+}
+
+/// Whether `token` can be defined as a label : a name, maybe local (`.loop`), or a number
+fn is_label(token: &Token) -> bool {
+    match token.kind {
+        TokenKind::Identifier => parser::is_identifier(token.text.strip_prefix('.').unwrap_or(token.text)),
+        TokenKind::Number => token.text.bytes().all(|c| c.is_ascii_digit()),
+        _ => false,
+    }
+}
+
+/// The node from the first of `tokens` to the last one, empty at `at` when there is none
+fn node<'a>(line: &'a str, tokens: &[Token], at: usize) -> Node<'a> {
+    let span = match (tokens.first(), tokens.last()) {
+        (Some(first), Some(last)) => Span { start: first.span.start, end: last.span.end },
+        _ => Span::at(at),
+    };
+
+    Node { text: &line[span.start..span.end], span }
+}
+
+/// Split `tokens` on the commas outside of the parentheses, each group along
+/// with where it starts : `at` for the first one, after its comma for the others
+fn split<'t, 'a>(tokens: &'t [Token<'a>], at: usize) -> Vec<(usize, &'t [Token<'a>])> {
+    if tokens.is_empty() {
+        return Vec::new();
+    }
+
+    let mut groups = Vec::new();
+    let (mut start, mut at) = (0, at);
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        match token.kind {
+            TokenKind::Open => depth += 1,
+            TokenKind::Close => depth -= 1,
+            TokenKind::Comma if depth == 0 => {
+                groups.push((at, &tokens[start..i]));
+                (start, at) = (i + 1, token.span.end);
+            },
+            _ => { },
+        }
+    }
+    groups.push((at, &tokens[start..]));
+
+    groups
+}
+
+/// The arguments (or operands) in `tokens`, separated by commas
+fn arguments<'a>(line: &'a str, tokens: &[Token<'a>], at: usize) -> Vec<Node<'a>> {
+    split(tokens, at).into_iter()
+        .map(|(at, group)| node(line, group, at))
+        .collect()
+}
+
+/// The addressing mode of the operand made of `tokens`, its register has to
+/// be one of `dialect`
+fn operand_expr<'a>(line: &'a str, tokens: &[Token<'a>], dialect: Dialect) -> OperandExpr<'a> {
+    use TokenKind::*;
+
+    let register = |token: &Token| if token.kind == Identifier { dialect.register(token.text) } else { None };
+    let operator = |token: &Token, text: &str| token.kind == Operator && token.text == text;
+
+    match tokens {
+        [hash, rest @ ..] if hash.kind == Hash && !rest.is_empty() => OperandExpr::Immediate(node(line, rest, hash.span.end)),
+        [at, rest @ ..] if at.kind == At && !rest.is_empty() => OperandExpr::Address(node(line, rest, at.span.end)),
+        [name] => register(name).map_or(OperandExpr::Invalid, OperandExpr::Register),
+        [open, name, close] if open.kind == Open && close.kind == Close => {
+            register(name).map_or(OperandExpr::Invalid, OperandExpr::Indirect)
+        },
+        [open, name, close, plus] if open.kind == Open && close.kind == Close && operator(plus, "+") => {
+            register(name).map_or(OperandExpr::Invalid, OperandExpr::PostIncrement)
+        },
+        [minus, open, name, close] if operator(minus, "-") && open.kind == Open && close.kind == Close => {
+            register(name).map_or(OperandExpr::Invalid, OperandExpr::PreDecrement)
+        },
+        _ => OperandExpr::Invalid,
+    }
+}
+
+/// The statements of `line` : its label, then its instruction or its directive
+pub fn parse_line(line: &str, dialect: Dialect) -> Vec<Statement<'_>> {
+    // Enough for most lines, without growing
+    let mut tokens: Vec<Token> = Vec::with_capacity(16);
+    tokens.extend(Lexer::new(line).take_while(|token| token.kind != TokenKind::Comment));
+    let mut statements = Vec::with_capacity(2);
+    let mut rest = &tokens[..];
+
+    if let [label, colon, ..] = rest {
+        if colon.kind == TokenKind::Colon && is_label(label) {
+            statements.push(Statement::Label(label.into()));
+            rest = &rest[2..];
+        }
+    }
+
+    let Some(first) = rest.first() else {
+        return statements;
+    };
+    let Node { text, span } = node(line, rest, 0);
+
+    match rest {
+        [name, ..] if name.kind == TokenKind::Identifier && name.text.starts_with('.') => {
+            let arguments = arguments(line, &rest[1..], name.span.end);
+            statements.push(Statement::Directive(Directive { name: name.into(), arguments, text, span }));
+        },
+        [name, equal, expression @ ..] if name.kind == TokenKind::Identifier && equal.kind == TokenKind::Operator && equal.text == "=" => {
+            let arguments = vec![name.into(), node(line, expression, equal.span.end)];
+            statements.push(Statement::Directive(Directive { name: equal.into(), arguments, text, span }));
+        },
+        _ => {
+            let operands = split(&rest[1..], first.span.end).into_iter()
+                .map(|(at, tokens)| {
+                    let Node { text, span } = node(line, tokens, at);
+                    Operand { text, span, expr: operand_expr(line, tokens, dialect) }
+                })
+                .collect();
+            statements.push(Statement::Instruction(Instruction { mnemonic: first.into(), operands, text, span }));
+        },
+    }
+
+    statements
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(text: &str, start: usize) -> Node<'_> {
+        Node { text, span: Span { start, end: start + text.len() } }
+    }
+
+    fn instruction(line: &str, dialect: Dialect) -> Instruction<'_> {
+        match parse_line(line, dialect).pop() {
+            Some(Statement::Instruction(instruction)) => instruction,
+            statement => panic!("{:?} isn't an instruction", statement),
+        }
+    }
+
+    #[test]
+    fn statements() {
+        let statements = parse_line("LOOP: add #STEP * 2, -( r1 ) ; comment", Dialect::default());
+        assert_eq!(statements, vec![
+            Statement::Label(at("LOOP", 0)),
+            Statement::Instruction(Instruction {
+                mnemonic: at("add", 6),
+                operands: vec![
+                    Operand { text: "#STEP * 2", span: Span { start: 10, end: 19 }, expr: OperandExpr::Immediate(at("STEP * 2", 11)) },
+                    Operand { text: "-( r1 )", span: Span { start: 21, end: 28 }, expr: OperandExpr::PreDecrement(1) },
+                ],
+                text: "add #STEP * 2, -( r1 )",
+                span: Span { start: 6, end: 28 },
+            }),
+        ]);
+
+        assert_eq!(parse_line("1: .word 1, , ';'", Dialect::default()), vec![
+            Statement::Label(at("1", 0)),
+            Statement::Directive(Directive {
+                name: at(".word", 3),
+                arguments: vec![at("1", 9), at("", 11), at("';'", 14)],
+                text: ".word 1, , ';'",
+                span: Span { start: 3, end: 17 },
+            }),
+        ]);
+        assert_eq!(parse_line("SIZE = 4*2", Dialect::default())[0], Statement::Directive(Directive {
+            name: at("=", 5),
+            arguments: vec![at("SIZE", 0), at("4*2", 7)],
+            text: "SIZE = 4*2",
+            span: Span { start: 0, end: 10 },
+        }));
+        assert_eq!(parse_line("  ; only a comment", Dialect::default()), vec![]);
+        assert_eq!(parse_line(".loop: RTS", Dialect::default())[0].label(), Some(at(".loop", 0)));
+        assert_eq!(parse_line("MOVE.L: RTS", Dialect::default())[0].label(), None);
+    }
+
+    #[test]
+    fn operands() {
+        let exprs: Vec<OperandExpr> = instruction("X R0, (R1), (R2)+, -(R3), #1, @(A + 1), +(R4), (R5+), R6 R7, #, R8", Dialect::default())
+            .operands.into_iter()
+            .map(|operand| operand.expr)
+            .collect();
+        assert_eq!(exprs, vec![
+            OperandExpr::Register(0),
+            OperandExpr::Indirect(1),
+            OperandExpr::PostIncrement(2),
+            OperandExpr::PreDecrement(3),
+            OperandExpr::Immediate(at("1", 27)),
+            OperandExpr::Address(at("(A + 1)", 31)),
+            // Only (Rn)+ increments
+            OperandExpr::Invalid,
+            OperandExpr::Invalid,
+            OperandExpr::Invalid,
+            OperandExpr::Invalid,
+            OperandExpr::Invalid,
+        ]);
+
+        // The registers are the ones of the dialect
+        let exprs: Vec<OperandExpr> = instruction("MOVE r0, SP", Dialect::STRICT)
+            .operands.into_iter()
+            .map(|operand| operand.expr)
+            .collect();
+        assert_eq!(exprs, vec![OperandExpr::Invalid, OperandExpr::Invalid]);
+
+        // A missing operand is empty, after its comma
+        let operands = instruction("ADD R0,", Dialect::default()).operands;
+        assert_eq!((operands[1].text, operands[1].span), ("", Span::at(7)));
+    }
+}
@@ -0,0 +1,103 @@
+//! Measure the throughput of the assembler on a generated program.
+//!
+//! ```text
+//! bench [--lines <count>] [--runs <count>]
+//! ```
+//!
+//! The program (100000 lines by default) mixes instructions of every format,
+//! local and numeric labels, constants, directives and macro calls. It is
+//! assembled `--runs` times (5 by default), the best run is reported in lines
+//! per second.
+
+use std::process::exit;
+use std::time::{Duration, Instant};
+
+use proco_test_4::assembler;
+use proco_test_4::game;
+use proco_test_4::utils::{fail, log};
+
+const USAGE: &str = "usage: bench [--lines <count>] [--runs <count>]";
+
+/// Defined once at the top of the program
+const PRELUDE: &str = "\
+SIZE = 4
+        .macro SAVE first, second
+            PUSH \\first
+            PUSH \\second
+        .endm
+";
+
+/// Repeated up to the number of lines, `{}` being the number of the block
+const BLOCK: &str = "\
+L{}:    MOVE #0x1F, R0
+        add #SIZE * 2, r1
+.loop:  SUB #1, R0
+        BNE #.loop
+1:      CMP @L{} + 2, R2
+        move.l (r0)+, r3
+        BEQ #1b
+        SAVE R0, R1 ; two pushes
+        .word L{}, 'A' | 0x80
+        JSR #L{}
+";
+
+fn generate(lines: usize) -> String {
+    let block_lines = BLOCK.lines().count();
+    let mut program = String::from(PRELUDE);
+
+    for block in 0..lines.saturating_sub(PRELUDE.lines().count()).div_ceil(block_lines) {
+        program.push_str(&BLOCK.replace("{}", &block.to_string()));
+    }
+
+    program
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<(usize, usize), String> {
+    let (mut lines, mut runs) = (100_000, 5);
+
+    while let Some(arg) = args.next() {
+        let mut count = |option: &str| {
+            let value = args.next().ok_or(format!("{} needs a value", option))?;
+            value.parse::<usize>().ok().filter(|count| *count > 0).ok_or(format!("bad count {}", value))
+        };
+
+        match arg.as_str() {
+            "--lines" => lines = count(&arg)?,
+            "--runs" => runs = count(&arg)?,
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+
+    Ok((lines, runs))
+}
+
+fn main() {
+    let (lines, runs) = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(error) => {
+            fail(&error);
+            eprintln!("{}", USAGE);
+            exit(2);
+        },
+    };
+
+    let source = generate(lines);
+    let lines = source.lines().count();
+    let mut best = Duration::MAX;
+
+    for _ in 0..runs {
+        let start = Instant::now();
+        if let Err(errors) = assembler::assemble("bench.asm", &source, game::RESET_ADDR) {
+            errors.iter().take(10).for_each(|error| fail(&error.to_string()));
+            exit(1);
+        }
+        best = best.min(start.elapsed());
+    }
+
+    log(&format!(
+        "{} lines in {:.1} ms, {:.0} lines/s",
+        lines,
+        best.as_secs_f64() * 1000.0,
+        lines as f64 / best.as_secs_f64(),
+    ));
+}
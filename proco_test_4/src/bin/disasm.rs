@@ -0,0 +1,139 @@
+//! Print the instructions of a binary image.
+//!
+//! ```text
+//! disasm <image.bin> [--origin <address>] [--symbols <file.sym>] [--fields] [--fold] [--color=auto|always|never]
+//! ```
+//!
+//! The image is loaded at RESET_ADDR unless `--origin` says otherwise. The
+//! symbols are read from `--symbols`, or from the `.sym` file next to the image
+//! when there is one. `--fold` writes the expansions of the
+//! pseudo-instructions (`XOR R0, R0`...) as the pseudo-instruction (`CLR R0`).
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+use proco_test_4::disasm::{self, Syntax};
+use proco_test_4::error::FileError;
+use proco_test_4::utils::{self, alert, report, ColorMode, Diagnostic};
+use proco_test_4::{decoder, game, symbols};
+
+const USAGE: &str = "usage: disasm <image.bin> [--origin <address>] [--symbols <file.sym>] [--fields] [--fold] [--color=auto|always|never]";
+
+struct Options {
+    image: PathBuf,
+    origin: u16,
+    symbols: Option<PathBuf>,
+    /// Also print the fields of every instruction
+    fields: bool,
+    /// Fold the expansions back into their pseudo-instruction
+    fold: bool,
+}
+
+fn parse_address(value: &str) -> Option<u16> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut image = None;
+    let mut origin = game::RESET_ADDR;
+    let mut symbols = None;
+    let mut fields = false;
+    let mut fold = false;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--origin" => {
+                let value = args.next().ok_or("--origin needs an address")?;
+                origin = parse_address(&value).ok_or(format!("bad address {}", value))?;
+            },
+            "--symbols" => symbols = Some(PathBuf::from(args.next().ok_or("--symbols needs a file")?)),
+            "--fields" => fields = true,
+            "--fold" => fold = true,
+            "--color" => utils::set_color_mode(ColorMode::parse(&args.next().ok_or("--color needs a mode")?)?),
+            _ if arg.starts_with("--color=") => utils::set_color_mode(ColorMode::parse(&arg["--color=".len()..])?),
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ if image.is_none() => image = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+
+    let image = image.ok_or("missing image")?;
+
+    // The symbol file written by the assembler is used when it exists
+    let symbols = symbols.or_else(|| {
+        let path = image.with_extension("sym");
+        Path::exists(&path).then_some(path)
+    });
+
+    Ok(Options { image, origin, symbols, fields, fold })
+}
+
+/// The error reading `path`, along with its code when the file is wrong
+fn file_error(path: &Path, error: &io::Error) -> Diagnostic<'static> {
+    let code = FileError::of(error).map(FileError::code);
+    Diagnostic { code, message: format!("{}: {}", path.display(), error), snippet: None, help: None, notes: Vec::new() }
+}
+
+fn main() {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(error) => {
+            alert(&error);
+            eprintln!("{}", USAGE);
+            exit(2);
+        },
+    };
+
+    let words = match decoder::read_binary(&options.image) {
+        Ok(words) => words,
+        Err(error) => {
+            report(&file_error(&options.image, &error));
+            exit(1);
+        },
+    };
+
+    let symbols = match &options.symbols {
+        Some(path) => match symbols::read_symbol_file(path) {
+            Ok(symbols) => Some(symbols),
+            Err(error) => {
+                report(&file_error(path, &error));
+                exit(1);
+            },
+        },
+        None => None,
+    };
+    let labels = symbols.as_ref().map(disasm::labels);
+
+    let lines = disasm::disassemble(&words, options.origin);
+    let mut i = 0;
+
+    while i < lines.len() {
+        let line = &lines[i];
+        if let Some(label) = labels.as_ref().and_then(|labels| labels.get(&line.address)) {
+            println!("{}:", label);
+        }
+
+        let folded = if options.fold { disasm::fold(&lines[i..], labels.as_ref()) } else { None };
+        let (syntax, count) = folded.unwrap_or_else(|| (Syntax { line, labels: labels.as_ref() }.to_string(), 1));
+
+        let raw: Vec<String> = lines[i..i + count].iter()
+            .flat_map(|line| &line.words)
+            .map(|word| format!("{:04x}", word))
+            .collect();
+
+        println!("{:04x}  {:<14} {}", line.address, raw.join(" "), syntax);
+
+        if options.fields {
+            for line in &lines[i..i + count] {
+                if let Some(instruction) = &line.instruction {
+                    println!("{:20}; {}", "", instruction);
+                }
+            }
+        }
+        i += count;
+    }
+}
@@ -0,0 +1,10 @@
+//! Print the instruction set reference generated from the ISA table, kept in
+//! `ISA.md` :
+//!
+//! ```text
+//! cargo run --bin isadoc > ISA.md
+//! ```
+
+fn main() {
+    print!("{}", proco_test_4::isa::reference());
+}
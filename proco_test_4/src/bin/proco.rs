@@ -0,0 +1,404 @@
+//! Command line driver of the assembler.
+//!
+//! ```text
+//! proco asm <file.asm>... [-I <directory>]... [-D <name>[=<value>]]... [--strict] [--star-comments] [-o <output>] [--listing <file.lst> [--listing-symbols]] [--format bin|hex] [--origin <address>]
+//! proco asm -c <file.asm>... [-I <directory>]... [-D <name>[=<value>]]... [--strict] [--star-comments] [-o <file.o>] [--listing <file.lst> [--listing-symbols]]
+//! proco link <file.o>... [-o <output>] [--format bin|hex] [--text <address>] [--data <address>] [--bss <address>]
+//! proco explain <code>
+//! ```
+//!
+//! Every command also takes `--color=auto|always|never` and `--lang=en|fr`.
+//!
+//! The input files are assembled as a single program, the `.include` files are
+//! looked for next to the including file then in the `-I` directories. `-D`
+//! defines a constant (1 when it has no value) for the sources and their
+//! conditional blocks. Mnemonics and registers are read in any case, `PC`
+//! and `SP` being R6 and R7, unless `--strict` asks for upper case R0-R7
+//! only. `--star-comments` makes the lines starting with `*` comments.
+//! `--listing` writes every line along with its address and its words,
+//! `--listing-symbols` adds the labels at its end. `-c` writes a
+//! relocatable object instead of an image, `link` puts the objects together:
+//! `.text` at `--text` (the reset address by default), `.data` and `.bss` at
+//! their address or after the previous section. The errors are shown along
+//! with their line, colored on a terminal unless NO_COLOR is set or `--color`
+//! says otherwise. Every error has a code, `explain` describes it at length.
+//! The messages are in English or in French, after `--lang` or the locale
+//! (`LC_ALL`, `LC_MESSAGES`, `LANG`). The exit status is 0 on success, 1 when the sources don't
+//! assemble (or a file can't be read/written, or the code is unknown) and 2 when the command line
+//! itself is wrong.
+
+use core::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::PathBuf;
+use std::process::exit;
+
+use proco_test_4::assembler::{self, Program, Settings, Source};
+use proco_test_4::linker::{self, Layout, Linked};
+use proco_test_4::object::{self, Object};
+use proco_test_4::parser::{self, Constants, Context, Dialect, Symbols};
+use proco_test_4::preprocessor::FileLoader;
+use proco_test_4::catalog::{self, Language};
+use proco_test_4::error::FileError;
+use proco_test_4::utils::{self, fail, log, report, ColorMode, Diagnostic};
+use proco_test_4::{ast, encoder, expr, game, listing, symbols};
+
+const USAGE: &str = "usage: proco asm <file.asm>... [-I <directory>]... [-D <name>[=<value>]]... [--strict] [--star-comments] [-o <output>] [--listing <file.lst> [--listing-symbols]] [--format bin|hex] [--origin <address>]
+       proco asm -c <file.asm>... [-I <directory>]... [-D <name>[=<value>]]... [--strict] [--star-comments] [-o <file.o>] [--listing <file.lst> [--listing-symbols]]
+       proco link <file.o>... [-o <output>] [--format bin|hex] [--text <address>] [--data <address>] [--bss <address>]
+       proco explain <code>
+every command takes --color=auto|always|never and --lang=en|fr";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Bin,
+    Hex,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Bin => "bin",
+            Format::Hex => "hex",
+        }
+    }
+}
+
+enum Command {
+    Asm,
+    Link,
+    Explain,
+}
+
+struct Options {
+    command: Command,
+    inputs: Vec<PathBuf>,
+    include: Vec<PathBuf>,
+    defines: Vec<(String, i64)>,
+    output: Option<PathBuf>,
+    listing: Option<PathBuf>,
+    /// List the labels at the end of the listing
+    listing_symbols: bool,
+    format: Format,
+    origin: u16,
+    /// Write a relocatable object
+    relocatable: bool,
+    dialect: Dialect,
+    /// Where the linker puts `.data` and `.bss`
+    data: Option<u16>,
+    bss: Option<u16>,
+    color: ColorMode,
+    /// `None` to follow the locale
+    language: Option<Language>,
+}
+
+/// A wrong command line, the messages come from the `catalog`
+enum UsageError {
+    UnknownCommand(String),
+    MissingCommand,
+    UnknownOption(String),
+    MissingValue(String),
+    BadAddress(String),
+    BadConstantName(String),
+    BadDefine { name: String, error: String },
+    /// `expected` lists the values the option takes
+    BadChoice { option: String, value: String, expected: &'static str },
+    MisplacedOption { option: &'static str, command: &'static str },
+    NoInput,
+    ExplainCount,
+}
+
+impl UsageError {
+    /// Stable code of the error, see `catalog`
+    fn code(&self) -> &'static str {
+        match self {
+            UsageError::UnknownCommand(_) => "E0040",
+            UsageError::MissingCommand => "E0041",
+            UsageError::UnknownOption(_) => "E0042",
+            UsageError::MissingValue(_) => "E0043",
+            UsageError::BadAddress(_) => "E0044",
+            UsageError::BadConstantName(_) => "E0045",
+            UsageError::BadDefine { .. } => "E0046",
+            UsageError::BadChoice { .. } => "E0047",
+            UsageError::MisplacedOption { .. } => "E0048",
+            UsageError::NoInput => "E0049",
+            UsageError::ExplainCount => "E0050",
+        }
+    }
+}
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let code = self.code();
+        let message = match self {
+            UsageError::UnknownCommand(command) => catalog::text(code, &[("command", command)]),
+            UsageError::UnknownOption(option) | UsageError::MissingValue(option) => catalog::text(code, &[("option", option)]),
+            UsageError::BadAddress(value) => catalog::text(code, &[("value", value)]),
+            UsageError::BadConstantName(name) => catalog::text(code, &[("name", name)]),
+            UsageError::BadDefine { name, error } => catalog::text(code, &[("name", name), ("error", error)]),
+            UsageError::BadChoice { option, value, expected } => catalog::text(code, &[("option", option), ("value", value), ("expected", expected)]),
+            UsageError::MisplacedOption { option, command } => catalog::text(code, &[("option", option), ("command", command)]),
+            UsageError::MissingCommand | UsageError::NoInput | UsageError::ExplainCount => catalog::text(code, &[]),
+        };
+
+        f.write_str(&message)
+    }
+}
+
+fn parse_address(value: &str) -> Option<u16> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/// `NAME=value` or `NAME` (worth 1), the value being an expression of numbers
+fn parse_define(define: &str) -> Result<(String, i64), UsageError> {
+    let (name, value) = define.split_once('=').unwrap_or((define, "1"));
+    if !parser::is_identifier(name) {
+        return Err(UsageError::BadConstantName(name.to_string()));
+    }
+
+    let (symbols, constants) = (Symbols::default(), Constants::new());
+    let context = Context::new(&symbols, &constants);
+    match expr::evaluate(ast::Node::whole(value), &context) {
+        Ok(value) => Ok((name.to_string(), value.value)),
+        Err(error) => Err(UsageError::BadDefine { name: name.to_string(), error: error.kind.to_string() }),
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, UsageError> {
+    let command = match args.next().as_deref() {
+        Some("asm") => Command::Asm,
+        Some("link") => Command::Link,
+        Some("explain") => Command::Explain,
+        Some(command) => return Err(UsageError::UnknownCommand(command.to_string())),
+        None => return Err(UsageError::MissingCommand),
+    };
+
+    let mut options = Options {
+        command,
+        inputs: Vec::new(),
+        include: Vec::new(),
+        defines: Vec::new(),
+        output: None,
+        listing: None,
+        listing_symbols: false,
+        format: Format::Bin,
+        origin: game::RESET_ADDR,
+        relocatable: false,
+        dialect: Dialect::default(),
+        data: None,
+        bss: None,
+        color: ColorMode::Auto,
+        language: None,
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = |option: &str| args.next().ok_or(UsageError::MissingValue(option.to_string()));
+        let address = |value: String| parse_address(&value).ok_or(UsageError::BadAddress(value));
+        // The value of an option taking one of `expected`
+        let choice = |option: &str, value: &str, expected: &'static str| UsageError::BadChoice { option: option.to_string(), value: value.to_string(), expected };
+
+        match arg.as_str() {
+            "-I" => options.include.push(PathBuf::from(value(&arg)?)),
+            _ if arg.starts_with("-I") => options.include.push(PathBuf::from(&arg[2..])),
+            "-D" => options.defines.push(parse_define(&value(&arg)?)?),
+            _ if arg.starts_with("-D") => options.defines.push(parse_define(&arg[2..])?),
+            "-o" | "--output" => options.output = Some(PathBuf::from(value(&arg)?)),
+            "--listing" => options.listing = Some(PathBuf::from(value(&arg)?)),
+            "--listing-symbols" => options.listing_symbols = true,
+            "--format" => options.format = match value(&arg)?.as_str() {
+                "bin" => Format::Bin,
+                "hex" => Format::Hex,
+                format => return Err(choice(&arg, format, "bin|hex")),
+            },
+            "--origin" | "--text" => options.origin = address(value(&arg)?)?,
+            "--data" => options.data = Some(address(value(&arg)?)?),
+            "--bss" => options.bss = Some(address(value(&arg)?)?),
+            "-c" => options.relocatable = true,
+            "--strict" => options.dialect = Dialect { star_comments: options.dialect.star_comments, ..Dialect::STRICT },
+            "--star-comments" => options.dialect.star_comments = true,
+            "--color" => {
+                let mode = value(&arg)?;
+                options.color = ColorMode::parse(&mode).map_err(|_| choice(&arg, &mode, "auto|always|never"))?;
+            },
+            _ if arg.starts_with("--color=") => {
+                let mode = &arg["--color=".len()..];
+                options.color = ColorMode::parse(mode).map_err(|_| choice("--color", mode, "auto|always|never"))?;
+            },
+            "--lang" => {
+                let language = value(&arg)?;
+                options.language = Some(Language::parse(&language).map_err(|_| choice(&arg, &language, "en|fr"))?);
+            },
+            _ if arg.starts_with("--lang=") => {
+                let language = &arg["--lang=".len()..];
+                options.language = Some(Language::parse(language).map_err(|_| choice("--lang", language, "en|fr"))?);
+            },
+            _ if arg.starts_with('-') => return Err(UsageError::UnknownOption(arg)),
+            _ => options.inputs.push(PathBuf::from(arg)),
+        }
+    }
+
+    if let Command::Explain = options.command {
+        return match options.inputs.len() {
+            1 => Ok(options),
+            _ => Err(UsageError::ExplainCount),
+        };
+    }
+    if options.inputs.is_empty() {
+        return Err(UsageError::NoInput);
+    }
+
+    if options.relocatable && !matches!(options.command, Command::Asm) {
+        return Err(UsageError::MisplacedOption { option: "-c", command: "asm" });
+    }
+    if (options.data.is_some() || options.bss.is_some()) && !matches!(options.command, Command::Link) {
+        let option = if options.data.is_some() { "--data" } else { "--bss" };
+        return Err(UsageError::MisplacedOption { option, command: "link" });
+    }
+
+    Ok(options)
+}
+
+/// Sum up the `count` errors just reported and exit
+fn fail_with_errors(count: usize) -> ! {
+    fail(&catalog::text("summary", &[("count", &count)]));
+    eprintln!("{}", catalog::text("explain.hint", &[]));
+    exit(1);
+}
+
+/// Print the long explanation of an error code
+fn explain(code: &str) {
+    match catalog::explain(code, catalog::language()) {
+        Some(explanation) => println!("{}", explanation),
+        None => {
+            fail(&catalog::text("explain.unknown", &[("code", &code)]));
+            exit(1);
+        },
+    }
+}
+
+/// Read and assemble every input, reports the errors and exits when it fails
+fn assemble(options: &Options) -> Program {
+    let mut files = Vec::new();
+    for input in &options.inputs {
+        match fs::read_to_string(input) {
+            Ok(text) => files.push((input.display().to_string(), text)),
+            Err(error) => {
+                fail(&format!("{}: {}", input.display(), error));
+                exit(1);
+            },
+        }
+    }
+
+    let sources: Vec<Source> = files.iter()
+        .map(|(file, text)| Source { file, text })
+        .collect();
+
+    let loader = FileLoader { directories: options.include.clone() };
+    let settings = Settings { origin: options.origin, loader: &loader, defines: &options.defines, relocatable: options.relocatable, dialect: options.dialect };
+    match assembler::assemble_with(&sources, &settings) {
+        Ok(program) => program,
+        Err(errors) => {
+            errors.iter()
+                .for_each(|error| report(&error.diagnostic()));
+            fail_with_errors(errors.len());
+        },
+    }
+}
+
+fn write_image(options: &Options, output: &PathBuf, words: &[u16]) -> io::Result<()> {
+    match options.format {
+        Format::Bin => encoder::write_image(output, words)?,
+        Format::Hex => encoder::write_hex(&mut BufWriter::new(File::create(output)?), words)?,
+    }
+    log(&format!("{} word(s) written to {}", words.len(), output.display()));
+
+    Ok(())
+}
+
+fn write_outputs(options: &Options, program: &Program) -> io::Result<()> {
+    let extension = if options.relocatable { "o" } else { options.format.extension() };
+    let output = options.output.clone()
+        .unwrap_or_else(|| options.inputs[0].with_extension(extension));
+
+    if options.relocatable {
+        object::write_object_file(&output, &Object::new(program))?;
+        log(&format!("Object written to {}", output.display()));
+    } else {
+        write_image(options, &output, &program.words())?;
+    }
+    symbols::write_symbol_file(output.with_extension("sym"), &program.map)?;
+
+    if let Some(path) = &options.listing {
+        listing::write_listing(&mut BufWriter::new(File::create(path)?), program, options.listing_symbols)?;
+        log(&format!("Listing written to {}", path.display()));
+    }
+
+    Ok(())
+}
+
+/// Read and link every object, reports the errors and exits when it fails
+fn link(options: &Options) -> Linked {
+    let mut objects = Vec::new();
+    for input in &options.inputs {
+        match object::read_object_file(input) {
+            Ok(object) => objects.push((input.display().to_string(), object)),
+            Err(error) => {
+                let code = FileError::of(&error).map(FileError::code);
+                report(&Diagnostic { code, message: format!("{}: {}", input.display(), error), snippet: None, help: None, notes: Vec::new() });
+                exit(1);
+            },
+        }
+    }
+
+    let layout = Layout { text: options.origin, data: options.data, bss: options.bss };
+    match linker::link(&objects, &layout) {
+        Ok(linked) => linked,
+        Err(errors) => {
+            errors.iter()
+                .for_each(|error| report(&Diagnostic { code: Some(error.code()), message: error.to_string(), snippet: None, help: None, notes: Vec::new() }));
+            fail_with_errors(errors.len());
+        },
+    }
+}
+
+fn write_linked(options: &Options, linked: &Linked) -> io::Result<()> {
+    let output = options.output.clone()
+        .unwrap_or_else(|| options.inputs[0].with_extension(options.format.extension()));
+
+    write_image(options, &output, &linked.words())?;
+    let mut writer = BufWriter::new(File::create(output.with_extension("sym"))?);
+    symbols::write_labels(&mut writer, &linked.symbols)
+}
+
+fn main() {
+    // The errors of the command line follow the locale, --lang isn't read yet
+    catalog::set_language(Language::from_env());
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(error) => {
+            report(&Diagnostic { code: Some(error.code()), message: error.to_string(), snippet: None, help: None, notes: Vec::new() });
+            eprintln!("{}", USAGE);
+            exit(2);
+        },
+    };
+    utils::set_color_mode(options.color);
+    catalog::set_language(options.language.unwrap_or_else(Language::from_env));
+
+    let written = match options.command {
+        Command::Asm => write_outputs(&options, &assemble(&options)),
+        Command::Link => write_linked(&options, &link(&options)),
+        Command::Explain => {
+            explain(&options.inputs[0].to_string_lossy());
+            Ok(())
+        },
+    };
+
+    if let Err(error) = written {
+        fail(&error.to_string());
+        exit(1);
+    }
+}
+
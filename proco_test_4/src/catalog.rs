@@ -0,0 +1,780 @@
+//! Texts of the errors, in English and in French.
+//!
+//! Every error of the assembler, of the linker and of the decoder has a stable
+//! code (`E0001`...), its message is looked up here by that code, its help by
+//! `<code>.help` and its long explanation (`proco explain E0007`) by
+//! `<code>.explain`. The arguments of a text are written between braces :
+//!
+//! ```text
+//! "E0001" | "unknown mnemonic {mnemonic}" | "mnémonique inconnu {mnemonic}"
+//! ```
+//!
+//! The language is English until `set_language` is called, the command line
+//! tools take it from `--lang` or from the environment (`LANG`).
+
+use std::cell::Cell;
+use std::fmt::Display;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Language {
+    En,
+    Fr,
+}
+
+impl Language {
+    /// `en` or `fr`
+    pub fn parse(value: &str) -> Result<Language, String> {
+        match value {
+            "en" => Ok(Language::En),
+            "fr" => Ok(Language::Fr),
+            _ => Err(format!("unknown language {}, expected en or fr", value)),
+        }
+    }
+
+    /// The language of the locale (`LC_ALL`, `LC_MESSAGES` then `LANG`),
+    /// English unless it is a French one
+    pub fn from_env() -> Language {
+        let locale = ["LC_ALL", "LC_MESSAGES", "LANG"].iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|value| !value.is_empty());
+
+        match locale {
+            Some(locale) if locale.starts_with("fr") => Language::Fr,
+            _ => Language::En,
+        }
+    }
+}
+
+thread_local! {
+    // Per thread, the tests check both languages side by side
+    static LANGUAGE: Cell<Language> = const { Cell::new(Language::En) };
+}
+
+/// Write the next texts of the thread in `language`
+pub fn set_language(language: Language) {
+    LANGUAGE.with(|current| current.set(language));
+}
+
+pub fn language() -> Language {
+    LANGUAGE.with(Cell::get)
+}
+
+/// The text `key` in `language`, `None` when there is no such text
+pub fn lookup(key: &str, language: Language) -> Option<&'static str> {
+    TEXTS.iter()
+        .find(|(name, _, _)| *name == key)
+        .map(|(_, en, fr)| match language {
+            Language::En => *en,
+            Language::Fr => *fr,
+        })
+}
+
+/// `template` with every `{name}` replaced by the value of its argument
+fn fill(template: &str, arguments: &[(&str, &dyn Display)]) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let argument = after.find('}')
+            .and_then(|end| arguments.iter().find(|(name, _)| *name == &after[..end]).map(|(_, value)| (end, value)));
+
+        match argument {
+            Some((end, value)) => {
+                result.push_str(&value.to_string());
+                rest = &after[end + 1..];
+            },
+            None => {
+                result.push('{');
+                rest = after;
+            },
+        }
+    }
+
+    result.push_str(rest);
+    result
+}
+
+/// The text `key` in the current language, filled with `arguments`. A missing
+/// text is replaced by its key
+pub fn text(key: &str, arguments: &[(&str, &dyn Display)]) -> String {
+    match lookup(key, language()) {
+        Some(template) => fill(template, arguments),
+        None => key.to_string(),
+    }
+}
+
+/// Same as `text`, `None` when there is no such text
+pub fn try_text(key: &str, arguments: &[(&str, &dyn Display)]) -> Option<String> {
+    lookup(key, language()).map(|template| fill(template, arguments))
+}
+
+/// Every error code, in order
+pub fn codes() -> impl Iterator<Item = &'static str> {
+    TEXTS.iter()
+        .map(|(key, _, _)| *key)
+        .filter(|key| key.len() == 5 && key.starts_with('E'))
+}
+
+/// The long explanation of the error `code` in `language`
+pub fn explain(code: &str, language: Language) -> Option<&'static str> {
+    lookup(&format!("{}.explain", code.to_ascii_uppercase()), language)
+}
+
+/// (key, English, French)
+static TEXTS: &[(&str, &str, &str)] = &[
+    // Words of the diagnostics
+    ("error", "error", "erreur"),
+    ("help", "help", "aide"),
+    ("note", "note", "note"),
+    ("expansion", "in the expansion of {name}", "dans l'expansion de {name}"),
+    ("expansion.at", "in the expansion of {name} at {location}", "dans l'expansion de {name} en {location}"),
+    ("summary", "{count} error(s), nothing written", "{count} erreur(s), rien n'a été écrit"),
+    ("explain.hint", "for more information about an error, try `proco explain <code>`", "pour en savoir plus sur une erreur, essayez `proco explain <code>`"),
+    ("explain.unknown", "unknown error code {code}", "code d'erreur inconnu {code}"),
+    // Roles of the operands, see `isa::OperandSpec`
+    ("role.source", "source", "source"),
+    ("role.destination", "destination", "destination"),
+    ("role.target", "target", "cible"),
+    ("role.vector", "vector", "vecteur"),
+
+    ("E0001", "unknown mnemonic {mnemonic}", "mnémonique inconnu {mnemonic}"),
+    ("E0001.help", "did you mean {suggestion}?", "vouliez-vous dire {suggestion} ?"),
+    ("E0001.explain",
+"The first word of the line is neither an instruction of the ISA, nor a
+pseudo-instruction, nor a macro defined before the line, nor a directive.
+
+    MVOE R0, R1     ; MOVE was meant
+
+The mnemonics are read in any case (`move`, `Move`) unless --strict is given.
+`cargo run --bin isadoc` lists the instructions.",
+"Le premier mot de la ligne n'est ni une instruction de l'ISA, ni une
+pseudo-instruction, ni une macro définie avant la ligne, ni une directive.
+
+    MVOE R0, R1     ; MOVE était voulu
+
+Les mnémoniques sont lus quelle que soit leur casse (`move`, `Move`) sauf avec
+--strict. `cargo run --bin isadoc` liste les instructions."),
+
+    ("E0002", "bad operand {operand}", "opérande invalide {operand}"),
+    ("E0002.explain",
+"The operand has none of the shapes of the addressing modes, or names a
+register that doesn't exist.
+
+    ADD R8, R0      ; the registers are R0 to R7 (PC and SP for R6 and R7)
+    ADD +(R1), R0   ; only -(Rn) and (Rn)+ move the register
+
+The modes are Rn, -(Rn), (Rn), (Rn)+, #expression and @expression.",
+"L'opérande n'a la forme d'aucun mode d'adressage, ou désigne un registre qui
+n'existe pas.
+
+    ADD R8, R0      ; les registres vont de R0 à R7 (PC et SP pour R6 et R7)
+    ADD +(R1), R0   ; seuls -(Rn) et (Rn)+ modifient le registre
+
+Les modes sont Rn, -(Rn), (Rn), (Rn)+, #expression et @expression."),
+
+    ("E0003", "value {value} doesn't fit in {bits} bits", "la valeur {value} ne tient pas sur {bits} bits"),
+    ("E0003.help", "a {bits} bits field holds {min} to {max}", "un champ de {bits} bits va de {min} à {max}"),
+    ("E0003.explain",
+"The value doesn't fit in the field it goes to. A field of n bits holds the
+signed values from -2^(n-1) and the unsigned ones up to 2^n - 1, the
+negative ones are stored in two's complement.
+
+    MOVE #0x10000, R0   ; a word holds 0xFFFF at most
+    .byte 256           ; a byte holds -128 to 255",
+"La valeur ne tient pas dans le champ où elle va. Un champ de n bits contient
+les valeurs signées à partir de -2^(n-1) et les valeurs non signées jusqu'à
+2^n - 1, les négatives sont stockées en complément à deux.
+
+    MOVE #0x10000, R0   ; un mot contient au plus 0xFFFF
+    .byte 256           ; un octet va de -128 à 255"),
+
+    ("E0004", "{mnemonic} can't use {operand} as {role}, its {role} has to be one of {allowed}", "{mnemonic} ne peut pas utiliser {operand} comme {role}, les modes permis sont {allowed}"),
+    ("E0004.help", "{mnemonic} only accepts a register {role}", "{mnemonic} n'accepte qu'un registre comme {role}"),
+    ("E0004.help.move", "{mnemonic} only accepts a register {role}; did you mean MOVE?", "{mnemonic} n'accepte qu'un registre comme {role} ; vouliez-vous dire MOVE ?"),
+    ("E0004.explain",
+"The operand uses an addressing mode the instruction doesn't have. The
+instructions with two operands (ADD, SUB, CMP...) only write to a register,
+MOVE is the one writing to the memory, and nothing writes to an immediate.
+
+    ADD R0, @0x10       ; load the word in a register first
+    MOVE @0x10, R1
+    ADD R0, R1
+
+`cargo run --bin isadoc` gives the modes of every operand.",
+"L'opérande utilise un mode d'adressage que l'instruction n'a pas. Les
+instructions à deux opérandes (ADD, SUB, CMP...) n'écrivent que dans un
+registre, MOVE est celle qui écrit en mémoire, et rien n'écrit dans une
+valeur immédiate.
+
+    ADD R0, @0x10       ; charger le mot dans un registre d'abord
+    MOVE @0x10, R1
+    ADD R0, R1
+
+`cargo run --bin isadoc` donne les modes de chaque opérande."),
+
+    ("E0005", "{mnemonic} takes {expected} operand(s), found {found}", "{mnemonic} prend {expected} opérande(s), {found} trouvé(s)"),
+    ("E0005.explain",
+"The instruction, the directive or the macro is given too many or too few
+operands. The operands are separated by commas.
+
+    RTS R0          ; RTS has no operand
+    PUSH            ; PUSH needs one",
+"L'instruction, la directive ou la macro reçoit trop ou pas assez d'opérandes.
+Les opérandes sont séparés par des virgules.
+
+    RTS R0          ; RTS n'a pas d'opérande
+    PUSH            ; PUSH en veut un"),
+
+    ("E0006", "undefined label {label}", "étiquette non définie {label}"),
+    ("E0006.explain",
+"The name is neither a label nor a constant of the program. A local label
+(`.loop`) only exists up to the next global label, `1b` and `1f` need a `1:`
+before or after the line.
+
+    BRA #.loop      ; .loop belongs to another routine
+    BEQ #2f         ; no 2: after this line
+
+Assembling with -c leaves the names declared by .extern to the linker.",
+"Le nom n'est ni une étiquette ni une constante du programme. Une étiquette
+locale (`.loop`) n'existe que jusqu'à l'étiquette globale suivante, `1b` et
+`1f` demandent un `1:` avant ou après la ligne.
+
+    BRA #.loop      ; .loop appartient à une autre routine
+    BEQ #2f         ; pas de 2: après cette ligne
+
+Assembler avec -c laisse à l'éditeur de liens les noms déclarés par .extern."),
+
+    ("E0007", "{name} is already defined at {previous}", "{name} est déjà défini en {previous}"),
+    ("E0007.explain",
+"A label, a constant, a macro or an imported name can only be defined once in
+the program, whatever the file defining it. Only the numeric labels (`1:`)
+can be defined many times.
+
+    LOOP: SUB #1, R0
+    LOOP: BNE #LOOP     ; rename one of them, or use .loop or 1:
+
+The local labels (`.loop`) belong to the last global label, two routines
+can both have their own.",
+"Une étiquette, une constante, une macro ou un nom importé ne peut être défini
+qu'une fois dans le programme, quel que soit le fichier qui le définit. Seules
+les étiquettes numériques (`1:`) peuvent être définies plusieurs fois.
+
+    LOOP: SUB #1, R0
+    LOOP: BNE #LOOP     ; renommer l'une d'elles, ou utiliser .loop ou 1:
+
+Les étiquettes locales (`.loop`) appartiennent à la dernière étiquette
+globale, deux routines peuvent chacune avoir la leur."),
+
+    ("E0008", "{name} is already the name of an instruction", "{name} est déjà le nom d'une instruction"),
+    ("E0008.explain",
+"A macro can't be named like an instruction of the ISA, its calls couldn't be
+told apart from the instruction.
+
+    .macro PUSH reg     ; call it SAVE instead
+        ...
+    .endm",
+"Une macro ne peut pas porter le nom d'une instruction de l'ISA, ses appels ne
+pourraient pas être distingués de l'instruction.
+
+    .macro PUSH reg     ; l'appeler SAVE plutôt
+        ...
+    .endm"),
+
+    ("E0009", "unknown directive {directive}", "directive inconnue {directive}"),
+    ("E0009.explain",
+"The word starting with a `.` isn't a directive. The directives are .org,
+.word, .byte, .string, .space, .align, .equ, .text, .data, .bss, .global,
+.extern, .include, .macro/.endm and .if/.ifdef/.ifndef/.elif/.else/.endif.
+
+    .wrod 1     ; .word was meant",
+"Le mot commençant par un `.` n'est pas une directive. Les directives sont
+.org, .word, .byte, .string, .space, .align, .equ, .text, .data, .bss,
+.global, .extern, .include, .macro/.endm et .if/.ifdef/.ifndef/.elif/.else/.endif.
+
+    .wrod 1     ; .word était voulu"),
+
+    ("E0010", "expected a string between double quotes", "chaîne entre guillemets attendue"),
+    ("E0010.explain",
+"The argument has to be a string between double quotes, closed on the same
+line. A quote inside the string is written \\\".
+
+    .string \"Hello    ; the closing quote is missing
+    .include lib.asm   ; .include \"lib.asm\"",
+"L'argument doit être une chaîne entre guillemets doubles, fermée sur la même
+ligne. Un guillemet dans la chaîne s'écrit \\\".
+
+    .string \"Hello    ; le guillemet fermant manque
+    .include lib.asm   ; .include \"lib.asm\""),
+
+    ("E0011", ".org can't move backwards, from {from} to {to}", ".org ne peut pas reculer, de {from} à {to}"),
+    ("E0011.explain",
+".org only moves the address forward, the bytes already placed would be
+overwritten otherwise.
+
+    .org 0x100
+    RTS
+    .org 0x80       ; move this part before the .org 0x100",
+".org ne fait qu'avancer l'adresse, sinon les octets déjà placés seraient
+écrasés.
+
+    .org 0x100
+    RTS
+    .org 0x80       ; déplacer cette partie avant le .org 0x100"),
+
+    ("E0012", "instruction at the odd address {address}, use .align 2", "instruction à l'adresse impaire {address}, utilisez .align 2"),
+    ("E0012.explain",
+"The instructions are made of words and have to start on an even address.
+A .byte or a .string can leave the address odd.
+
+    .byte 1
+    .align 2        ; needed before the instruction
+    RTS",
+"Les instructions sont faites de mots et doivent commencer à une adresse
+paire. Un .byte ou un .string peut laisser l'adresse impaire.
+
+    .byte 1
+    .align 2        ; nécessaire avant l'instruction
+    RTS"),
+
+    ("E0013", "{label} has to be defined before this line", "{label} doit être défini avant cette ligne"),
+    ("E0013.help", "a constant can only use the constants and labels defined before it", "une constante ne peut utiliser que les constantes et étiquettes définies avant elle"),
+    ("E0013.explain",
+"A constant is computed where it is defined, it can't use a constant or a
+label defined on a later line.
+
+    SIZE = END - START  ; move it after END
+    START: ...
+    END:",
+"Une constante est calculée là où elle est définie, elle ne peut pas utiliser
+une constante ou une étiquette définie sur une ligne suivante.
+
+    SIZE = END - START  ; la déplacer après END
+    START: ...
+    END:"),
+
+    ("E0014", "division by zero", "division par zéro"),
+    ("E0014.explain",
+"The right side of a `/` or of a `%` is worth 0.
+
+    .word 10 / (SIZE - 4)   ; SIZE is 4",
+"Le côté droit d'un `/` ou d'un `%` vaut 0.
+
+    .word 10 / (SIZE - 4)   ; SIZE vaut 4"),
+
+    ("E0015", "overflow, the value doesn't fit in 64 bits or goes past the end of the memory",
+        "dépassement, la valeur ne tient pas sur 64 bits ou dépasse la fin de la mémoire"),
+    ("E0015.explain",
+"The expressions are computed on 64 bits signed integers, this one (or one of
+its numbers) goes past them. Its result would not fit a word anyway.
+
+    .word 1 << 70
+
+The same goes for an instruction or some data ending past 0xFFFF, the last
+address of the memory: the addresses don't wrap around to 0.
+
+    .org 0xFFFE
+    MOVE #1, R0",
+"Les expressions sont calculées sur des entiers signés de 64 bits, celle-ci
+(ou l'un de ses nombres) les dépasse. Son résultat ne tiendrait de toute
+façon pas dans un mot.
+
+    .word 1 << 70
+
+De même pour une instruction ou des données finissant après 0xFFFF, la
+dernière adresse de la mémoire : les adresses ne repartent pas de 0.
+
+    .org 0xFFFE
+    MOVE #1, R0"),
+
+    ("E0016", "macro {name} has no .endm", "la macro {name} n'a pas de .endm"),
+    ("E0016.help", "end the body of the macro with .endm", "terminez le corps de la macro par .endm"),
+    ("E0016.explain",
+"The body of a macro goes from its .macro up to the matching .endm, which is
+missing: every line up to the end of the file would be part of the macro.
+
+    .macro SAVE reg
+        PUSH \\reg
+                    ; .endm is missing",
+"Le corps d'une macro va de son .macro jusqu'au .endm correspondant, qui
+manque : toutes les lignes jusqu'à la fin du fichier feraient partie de la
+macro.
+
+    .macro SAVE reg
+        PUSH \\reg
+                    ; .endm manque"),
+
+    ("E0017", ".endm without .macro", ".endm sans .macro"),
+    ("E0017.explain",
+"The .endm doesn't close any macro, remove it or add the missing .macro.",
+"Le .endm ne ferme aucune macro, le supprimer ou ajouter le .macro manquant."),
+
+    ("E0018", "macro {name} expands itself", "la macro {name} s'appelle elle-même"),
+    ("E0018.explain",
+"The macro calls itself, directly or through other macros, and its expansion
+never ends. The calls are limited to 32 levels.
+
+    .macro LOOP
+        LOOP
+    .endm",
+"La macro s'appelle elle-même, directement ou à travers d'autres macros, et
+son expansion ne finit jamais. Les appels sont limités à 32 niveaux.
+
+    .macro LOOP
+        LOOP
+    .endm"),
+
+    ("E0019", "can't include {path}: {reason}", "impossible d'inclure {path} : {reason}"),
+    ("E0019.explain",
+"The included file can't be read. It is looked for next to the file including
+it, then in the directories given by -I.
+
+    proco asm main.asm -I lib/",
+"Le fichier inclus ne peut pas être lu. Il est cherché à côté du fichier qui
+l'inclut, puis dans les répertoires donnés par -I.
+
+    proco asm main.asm -I lib/"),
+
+    ("E0020", "{file} is already being included", "{file} est déjà en cours d'inclusion"),
+    ("E0020.explain",
+"The file includes itself, directly or through the files it includes, and the
+inclusion would never end.
+
+    ; a.asm
+    .include \"b.asm\"
+    ; b.asm
+    .include \"a.asm\"",
+"Le fichier s'inclut lui-même, directement ou à travers les fichiers qu'il
+inclut, et l'inclusion ne finirait jamais.
+
+    ; a.asm
+    .include \"b.asm\"
+    ; b.asm
+    .include \"a.asm\""),
+
+    ("E0021", "{directive} has no .endif", "{directive} n'a pas de .endif"),
+    ("E0021.explain",
+"A conditional block goes from its .if (.ifdef, .ifndef) up to the matching
+.endif, which is missing in the file.
+
+    .ifdef DEBUG
+        TRAP #1
+                ; .endif is missing",
+"Un bloc conditionnel va de son .if (.ifdef, .ifndef) jusqu'au .endif
+correspondant, qui manque dans le fichier.
+
+    .ifdef DEBUG
+        TRAP #1
+                ; .endif manque"),
+
+    ("E0022", "{directive} without .if", "{directive} sans .if"),
+    ("E0022.explain",
+"The .elif, .else or .endif doesn't belong to any conditional block, remove it
+or add the missing .if.",
+"Le .elif, .else ou .endif n'appartient à aucun bloc conditionnel, le
+supprimer ou ajouter le .if manquant."),
+
+    ("E0023", "{directive} after .else", "{directive} après .else"),
+    ("E0023.explain",
+"The .else is the last branch of a conditional block, a .elif or another .else
+can't follow it.
+
+    .if MODE == 1
+    .else
+    .elif MODE == 2     ; move it before the .else",
+"Le .else est la dernière branche d'un bloc conditionnel, un .elif ou un autre
+.else ne peut pas le suivre.
+
+    .if MODE == 1
+    .else
+    .elif MODE == 2     ; le placer avant le .else"),
+
+    ("E0024", "{expression} can't be relocated by the linker", "{expression} ne peut pas être relogé par l'éditeur de liens"),
+    ("E0024.help", "the linker only moves an address plus or minus a number", "l'éditeur de liens ne déplace qu'une adresse plus ou moins un nombre"),
+    ("E0024.explain",
+"In a relocatable object (-c), the linker adds the address of a section or of
+an imported symbol to the words using it. It can't do that for a product or
+a sum of two addresses, whose value depends on where the sections go.
+
+    .word START * 2
+    .word START + END   ; START - END is fine, it doesn't move",
+"Dans un objet relogeable (-c), l'éditeur de liens ajoute l'adresse d'une
+section ou d'un symbole importé aux mots qui l'utilisent. Il ne peut pas le
+faire pour un produit ou une somme de deux adresses, dont la valeur dépend
+de l'endroit où vont les sections.
+
+    .word START * 2
+    .word START + END   ; START - END convient, cela ne bouge pas"),
+
+    ("E0025", ".org can only be used in .text", ".org ne s'utilise que dans .text"),
+    ("E0025.explain",
+"Only .text has a fixed address, .data and .bss are placed after it (or where
+the linker puts them): an address inside them means nothing. Use .space or
+.align to move forward in them.",
+"Seule .text a une adresse fixe, .data et .bss sont placées après elle (ou là
+où l'éditeur de liens les met) : une adresse en leur sein n'a pas de sens.
+Utiliser .space ou .align pour y avancer."),
+
+    ("E0026", ".bss can only reserve space (.space without a fill, .align)", ".bss ne peut que réserver de la place (.space sans valeur, .align)"),
+    ("E0026.help", "put the initialized data in .data", "mettez les données initialisées dans .data"),
+    ("E0026.explain",
+"The bytes of .bss are not part of the image, they are only reserved. The
+instructions and the initialized data go to .text or .data.
+
+    .bss
+    BUFFER: .space 64       ; fine
+    COUNT: .word 0          ; move it to .data",
+"Les octets de .bss ne font pas partie de l'image, ils sont seulement
+réservés. Les instructions et les données initialisées vont dans .text ou .data.
+
+    .bss
+    BUFFER: .space 64       ; convient
+    COUNT: .word 0          ; le déplacer dans .data"),
+
+    ("E0027", "{name} is exported but never defined", "{name} est exporté mais jamais défini"),
+    ("E0027.explain",
+"A name given to .global has to be a label or a constant of the file, the
+other objects can't use it otherwise.
+
+    .global PRINT       ; PRINT: is missing",
+"Un nom donné à .global doit être une étiquette ou une constante du fichier,
+les autres objets ne peuvent pas l'utiliser sinon.
+
+    .global PRINT       ; PRINT: manque"),
+
+    ("E0028", "undefined symbol {name}, imported by {object}", "symbole non défini {name}, importé par {object}"),
+    ("E0028.explain",
+"An object imports the name with .extern, but none of the linked objects
+exports it with .global. Link the object defining it, or export it.
+
+    proco link main.o lib.o",
+"Un objet importe le nom avec .extern, mais aucun des objets liés ne l'exporte
+avec .global. Lier l'objet qui le définit, ou l'exporter.
+
+    proco link main.o lib.o"),
+
+    ("E0029", "{name} is exported by both {first} and {second}", "{name} est exporté à la fois par {first} et {second}"),
+    ("E0029.explain",
+"Two of the linked objects export the same name with .global, the linker
+can't tell which one the imports refer to. Rename one of them.",
+"Deux des objets liés exportent le même nom avec .global, l'éditeur de liens
+ne peut pas savoir auquel les imports se rapportent. Renommer l'un d'eux."),
+
+    ("E0030", ".{section} goes past the end of the memory", ".{section} dépasse la fin de la mémoire"),
+    ("E0030.explain",
+"Once placed at its address, the section goes past 0xFFFF. Give it a lower
+address (--text, --data, --bss) or make it smaller.",
+"Une fois placée à son adresse, la section dépasse 0xFFFF. Lui donner une
+adresse plus basse (--text, --data, --bss) ou la réduire."),
+
+    ("E0031", ".{first} and .{second} overlap", ".{first} et .{second} se chevauchent"),
+    ("E0031.explain",
+"The addresses given to the sections make them share some bytes. Move one of
+them with --text, --data or --bss, or let the linker place .data and .bss
+after .text.",
+"Les adresses données aux sections leur font partager des octets. Déplacer
+l'une d'elles avec --text, --data ou --bss, ou laisser l'éditeur de liens
+placer .data et .bss après .text."),
+
+    ("E0032", "Unknown opcode {opcode} in word {word}", "opcode inconnu {opcode} dans le mot {word}"),
+    ("E0032.explain",
+"The 5 bits opcode of the word isn't part of the instruction set: the word is
+data, or the program jumped into the middle of an instruction. The CPU raises
+the illegal instruction interrupt, the disassembler shows the word as .WORD.",
+"L'opcode de 5 bits du mot ne fait pas partie du jeu d'instructions : le mot
+est une donnée, ou le programme a sauté au milieu d'une instruction. Le CPU
+déclenche l'interruption d'instruction illégale, le désassembleur affiche le
+mot comme .WORD."),
+
+    ("E0033", "Truncated instruction (opcode {opcode}), expected {expected} extension word(s) but found {found}", "instruction tronquée (opcode {opcode}), {expected} mot(s) d'extension attendu(s), {found} trouvé(s)"),
+    ("E0033.explain",
+"The image ends in the middle of an instruction: its extension word (the
+value of MOVE, an extended immediate or address) is missing. The image is
+probably cut, or ends with data that looks like an instruction.",
+"L'image s'arrête au milieu d'une instruction : son mot d'extension (la valeur
+de MOVE, une valeur immédiate ou une adresse étendue) manque. L'image est
+probablement coupée, ou se termine par une donnée qui ressemble à une
+instruction."),
+
+    ("E0034", "Illegal instruction {word} at {address}", "instruction illégale {word} à l'adresse {address}"),
+    ("E0034.explain",
+"The CPU can't execute the word at PC: its opcode is unknown, its extension
+word is missing, or it writes to an immediate value (MOVE R0, #1 encoded by
+hand for instance). The CPU pushes PC and the state register, then jumps to
+the handler whose address is stored at the illegal instruction vector (0x0E).",
+"Le CPU ne peut pas exécuter le mot à PC : son opcode est inconnu, son mot
+d'extension manque, ou il écrit dans une valeur immédiate (MOVE R0, #1 codé à
+la main par exemple). Le CPU empile PC et le registre d'état, puis saute au
+gestionnaire dont l'adresse est rangée dans le vecteur d'instruction illégale
+(0x0E)."),
+
+    ("E0035", "line {line}: unexpected `{text}`", "ligne {line} : `{text}` inattendu"),
+    ("E0035.explain",
+"The line isn't one of the entries of an object (section, export, import or
+relocation), or one of its fields is wrong. The objects are written by
+proco asm -c, assemble the sources again rather than editing them.",
+"La ligne n'est pas une des entrées d'un objet (section, export, import ou
+relocation), ou l'un de ses champs est faux. Les objets sont écrits par
+proco asm -c, assembler de nouveau les sources plutôt que de les modifier."),
+
+    ("E0036", "expected the sections text, data and bss", "sections text, data et bss attendues"),
+    ("E0036.explain",
+"An object holds every section, once and in this order: text, data then bss.
+The file is probably cut, assemble its sources again with proco asm -c.",
+"Un objet contient chaque section, une seule fois et dans cet ordre : text,
+data puis bss. Le fichier est probablement coupé, assembler de nouveau ses
+sources avec proco asm -c."),
+
+    ("E0037", "relocation {section} {offset} is outside of the bytes of the section", "la relocation {section} {offset} est en dehors des octets de la section"),
+    ("E0037.explain",
+"A relocation changes a word of its section, the word has to be within the
+bytes of the section. .bss has no bytes, so no relocation either.",
+"Une relocation modifie un mot de sa section, le mot doit être dans les
+octets de la section. .bss n'a pas d'octets, donc pas de relocation non plus."),
+
+    ("E0038", "line {line}: expected `<address> <name>`, found `{text}`", "ligne {line} : `<adresse> <nom>` attendu, `{text}` trouvé"),
+    ("E0038.explain",
+"Each line of a symbol file gives the hexadecimal address of a name, then
+possibly its kind (label, constant or import).
+
+    0010 START label",
+"Chaque ligne d'un fichier de symboles donne l'adresse hexadécimale d'un nom,
+puis éventuellement sa nature (label, constant ou import).
+
+    0010 START label"),
+
+    ("E0039", "binary image has an odd number of bytes", "l'image binaire a un nombre impair d'octets"),
+    ("E0039.explain",
+"A binary image holds 16 bits words, two bytes each. The file is probably
+cut, or isn't an image written with --format bin.",
+"Une image binaire contient des mots de 16 bits, de deux octets chacun. Le
+fichier est probablement coupé, ou n'est pas une image écrite avec
+--format bin."),
+
+    ("E0040", "unknown command {command}", "commande inconnue {command}"),
+    ("E0040.explain",
+"The first argument is the command: asm, link or explain.
+
+    proco asm prog.asm -o prog.bin",
+"Le premier argument est la commande : asm, link ou explain.
+
+    proco asm prog.asm -o prog.bin"),
+
+    ("E0041", "missing command", "commande manquante"),
+    ("E0041.explain",
+"The command line is empty, it starts with the command: asm, link or explain.
+
+    proco asm prog.asm -o prog.bin",
+"La ligne de commande est vide, elle commence par la commande : asm, link ou
+explain.
+
+    proco asm prog.asm -o prog.bin"),
+
+    ("E0042", "unknown option {option}", "option inconnue {option}"),
+    ("E0042.explain",
+"The argument starts with a `-` but isn't one of the options of the command,
+the usage lists them.",
+"L'argument commence par un `-` mais n'est pas une des options de la commande,
+l'usage les liste."),
+
+    ("E0043", "{option} needs a value", "{option} a besoin d'une valeur"),
+    ("E0043.explain",
+"The option is followed by its value, the command line ends before it.
+
+    proco asm prog.asm -o       ; -o prog.bin",
+"L'option est suivie de sa valeur, la ligne de commande s'arrête avant.
+
+    proco asm prog.asm -o       ; -o prog.bin"),
+
+    ("E0044", "bad address {value}", "adresse invalide {value}"),
+    ("E0044.explain",
+"An address is a number from 0 to 65535, in decimal or in hexadecimal after
+0x.
+
+    --origin 0x10000    ; past the end of the memory",
+"Une adresse est un nombre de 0 à 65535, en décimal ou en hexadécimal après
+0x.
+
+    --origin 0x10000    ; au-delà de la fin de la mémoire"),
+
+    ("E0045", "bad constant name {name}", "nom de constante invalide {name}"),
+    ("E0045.explain",
+"The name given to -D is the name of a constant of the sources, a letter or
+a `_` followed by letters, digits and `_`.
+
+    -D 1DEBUG       ; -D DEBUG",
+"Le nom donné à -D est celui d'une constante des sources, une lettre ou un
+`_` suivi de lettres, de chiffres et de `_`.
+
+    -D 1DEBUG       ; -D DEBUG"),
+
+    ("E0046", "bad value for {name}: {error}", "valeur invalide pour {name} : {error}"),
+    ("E0046.explain",
+"The value given to -D is an expression of numbers, it can't use the labels
+or the constants of the sources.
+
+    -D SIZE=4*8",
+"La valeur donnée à -D est une expression de nombres, elle ne peut pas
+utiliser les étiquettes ou les constantes des sources.
+
+    -D SIZE=4*8"),
+
+    ("E0047", "{option} takes {expected}, not {value}", "{option} prend {expected}, pas {value}"),
+    ("E0047.explain",
+"The option only takes some values: bin or hex for --format, auto, always or
+never for --color, en or fr for --lang.",
+"L'option ne prend que certaines valeurs : bin ou hex pour --format, auto,
+always ou never pour --color, en ou fr pour --lang."),
+
+    ("E0048", "{option} only goes with {command}", "{option} ne va qu'avec {command}"),
+    ("E0048.explain",
+"The option belongs to another command: -c to asm, --data and --bss to link.",
+"L'option appartient à une autre commande : -c à asm, --data et --bss à link."),
+
+    ("E0049", "no input file", "aucun fichier en entrée"),
+    ("E0049.explain",
+"asm takes the sources to assemble, link the objects to link.
+
+    proco link main.o lib.o",
+"asm prend les sources à assembler, link les objets à lier.
+
+    proco link main.o lib.o"),
+
+    ("E0050", "explain takes a single error code", "explain prend un seul code d'erreur"),
+    ("E0050.explain",
+"explain describes one error at a time, given by its code.
+
+    proco explain E0007",
+"explain décrit une erreur à la fois, donnée par son code.
+
+    proco explain E0007"),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_code_is_translated() {
+        let codes: Vec<&str> = codes().collect();
+        assert_eq!(codes.len(), 50);
+
+        for (i, code) in codes.iter().enumerate() {
+            assert_eq!(*code, format!("E{:04}", i + 1));
+            for language in [Language::En, Language::Fr] {
+                assert!(lookup(code, language).is_some_and(|text| !text.is_empty()), "{} has no message", code);
+                assert!(explain(code, language).is_some(), "{} has no explanation", code);
+            }
+        }
+    }
+
+    #[test]
+    fn arguments() {
+        let name: &dyn Display = &"LOOP";
+        assert_eq!(fill("{name} is {name}, {other} {", &[("name", name)]), "LOOP is LOOP, {other} {");
+        assert_eq!(explain("e0007", Language::En), lookup("E0007.explain", Language::En));
+        assert_eq!(Language::parse("fr"), Ok(Language::Fr));
+        assert!(Language::parse("de").is_err());
+    }
+}
@@ -0,0 +1,425 @@
+//! The CPU : fetch an instruction at PC, decode it and execute it.
+//!
+//! R0 - R5 are general purpose registers, R6 is the program counter and R7
+//! the stack pointer. The stack grows downward and SP points to the last
+//! pushed word.
+//!
+//! The state register holds the flags :
+//! ```text
+//! bit 0 | C | carry (or borrow for SUB/CMP, or the last bit shifted out)
+//! bit 1 | Z | the result is 0
+//! bit 2 | N | the most significant bit of the result (bit 7 for MOVE.L/MOVE.H)
+//! ```
+
+use std::fmt;
+
+use crate::catalog;
+use crate::decoder::decode;
+use crate::game::ILLEGAL_INSTRUCTION_VECTOR;
+use crate::isa::{self, Condition, Kind};
+use crate::memory::Memory;
+use crate::parser::{
+    InstructionFormat,
+    _Format1opLayout,
+    _Format2opLayout,
+    _FormatMoveLayout,
+};
+
+pub const PC: usize = 6;
+pub const SP: usize = 7;
+
+const FLAG_C: u16 = 0b001;
+const FLAG_Z: u16 = 0b010;
+const FLAG_N: u16 = 0b100;
+
+// Addressing modes, as encoded in the op_type fields
+const MODE_REGISTER: u16 = 0b000;
+const MODE_PRE_DECREMENT: u16 = 0b001;
+const MODE_INDIRECT: u16 = 0b010;
+const MODE_POST_INCREMENT: u16 = 0b011;
+const MODE_IMMEDIATE: u16 = 0b100;
+const MODE_ADDRESS: u16 = 0b101;
+const MODE_EXTENDED_IMMEDIATE: u16 = 0b110;
+const MODE_EXTENDED_ADDRESS: u16 = 0b111;
+
+/// Where an operand lives once its addressing mode has been applied
+#[derive(Clone, Copy)]
+enum Location {
+    Register(usize),
+    Memory(u16),
+    Immediate(u16),
+}
+
+/// Raised when an instruction can't be decoded or uses an operand it can't use
+struct Illegal;
+
+/// An instruction the CPU couldn't execute, it raised the illegal instruction
+/// interrupt instead
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IllegalInstruction {
+    /// Address of the instruction
+    pub address: u16,
+    /// Its first word
+    pub word: u16,
+}
+
+impl IllegalInstruction {
+    /// Stable code of the error, see `catalog`
+    pub fn code(&self) -> &'static str {
+        "E0034"
+    }
+}
+
+impl fmt::Display for IllegalInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let arguments: [(&str, &dyn fmt::Display); 2] = [("word", &format!("{:#06x}", self.word)), ("address", &format!("{:#06x}", self.address))];
+        f.write_str(&catalog::text(self.code(), &arguments))
+    }
+}
+
+impl std::error::Error for IllegalInstruction { }
+
+pub struct Cpu {
+    pub registers: [u16; 8],
+    pub state_register: u16,
+}
+
+impl Cpu {
+    pub fn new() -> Self {
+        Cpu {
+            registers: [0; 8],
+            state_register: 0,
+        }
+    }
+
+    pub fn get_c(&self) -> bool {
+        self.state_register & FLAG_C != 0
+    }
+
+    pub fn get_z(&self) -> bool {
+        self.state_register & FLAG_Z != 0
+    }
+
+    pub fn get_n(&self) -> bool {
+        self.state_register & FLAG_N != 0
+    }
+
+    pub fn set_c(&mut self) {
+        self.set_flag(FLAG_C, true);
+    }
+
+    fn set_flag(&mut self, flag: u16, value: bool) {
+        if value {
+            self.state_register |= flag;
+        } else {
+            self.state_register &= !flag;
+        }
+    }
+
+    /// Update the flags from a 16 bits result
+    fn set_flags(&mut self, result: u16, carry: bool) {
+        self.set_flag(FLAG_C, carry);
+        self.set_flag(FLAG_Z, result == 0);
+        self.set_flag(FLAG_N, result & 0x8000 != 0);
+    }
+
+    /// Update the flags from a byte moved by MOVE.L/MOVE.H
+    fn set_flags_u8(&mut self, result: u8) {
+        self.set_flag(FLAG_C, false);
+        self.set_flag(FLAG_Z, result == 0);
+        self.set_flag(FLAG_N, result & 0x80 != 0);
+    }
+
+    fn push(&mut self, mem: &mut Memory, value: u16) {
+        self.registers[SP] = self.registers[SP].wrapping_sub(2);
+        mem.write_u16(self.registers[SP], value);
+    }
+
+    fn pop(&mut self, mem: &mut Memory) -> u16 {
+        let value = mem.read_u16(self.registers[SP]);
+        self.registers[SP] = self.registers[SP].wrapping_add(2);
+        value
+    }
+
+    /// Save PC and the state register on the stack, then jump to the handler
+    /// whose address is stored at `vector`
+    pub fn trigger_interrupt(&mut self, mem: &mut Memory, vector: u16) {
+        self.push(mem, self.registers[PC]);
+        self.push(mem, self.state_register);
+        self.registers[PC] = mem.read_u16(vector);
+    }
+
+    /// Execute the instruction at PC, returns the illegal instruction met
+    /// instead, once its interrupt is raised
+    pub fn step(&mut self, mem: &mut Memory) -> Option<IllegalInstruction> {
+        let pc = self.registers[PC];
+        let mut consumed: u16 = 0;
+
+        let fetched = {
+            let mut words = std::iter::from_fn(|| {
+                let word = mem.read_u16(pc.wrapping_add(consumed * 2));
+                consumed += 1;
+                Some(word)
+            });
+            decode(&mut words)
+        };
+
+        // PC always points to the next instruction while executing
+        self.registers[PC] = pc.wrapping_add(consumed * 2);
+
+        let result = match fetched {
+            Ok(Some(instruction)) => self.execute(mem, &instruction),
+            _ => Err(Illegal),
+        };
+
+        if result.is_ok() {
+            return None;
+        }
+
+        // Read before the interrupt pushes over it
+        let illegal = IllegalInstruction { address: pc, word: mem.read_u16(pc) };
+        self.trigger_interrupt(mem, ILLEGAL_INSTRUCTION_VECTOR);
+        Some(illegal)
+    }
+
+    /// Apply an addressing mode, `size` is the amount (in bytes) used by the
+    /// auto increment/decrement modes. For the extended modes `value` is the
+    /// extension word
+    fn locate(&mut self, mode: u16, value: u16, size: u16) -> Result<Location, Illegal> {
+        let register = (value & 0b111) as usize;
+
+        Ok(match mode {
+            MODE_REGISTER => Location::Register(register),
+            MODE_PRE_DECREMENT => {
+                self.registers[register] = self.registers[register].wrapping_sub(size);
+                Location::Memory(self.registers[register])
+            },
+            MODE_INDIRECT => Location::Memory(self.registers[register]),
+            MODE_POST_INCREMENT => {
+                let addr = self.registers[register];
+                self.registers[register] = addr.wrapping_add(size);
+                Location::Memory(addr)
+            },
+            MODE_IMMEDIATE | MODE_EXTENDED_IMMEDIATE => Location::Immediate(value),
+            MODE_ADDRESS | MODE_EXTENDED_ADDRESS => Location::Memory(value),
+            _ => return Err(Illegal),
+        })
+    }
+
+    fn read(&self, mem: &Memory, location: Location) -> u16 {
+        match location {
+            Location::Register(register) => self.registers[register],
+            Location::Memory(addr) => mem.read_u16(addr),
+            Location::Immediate(value) => value,
+        }
+    }
+
+    fn write(&mut self, mem: &mut Memory, location: Location, value: u16) -> Result<(), Illegal> {
+        match location {
+            Location::Register(register) => self.registers[register] = value,
+            Location::Memory(addr) => mem.write_u16(addr, value),
+            Location::Immediate(_) => return Err(Illegal),
+        }
+
+        Ok(())
+    }
+
+    /// Evaluate the branch conditions, shared by Bxx and Jxx
+    fn condition(&self, condition: Condition) -> bool {
+        match condition {
+            Condition::CarryClear => !self.get_c(),                     // CC / GT
+            Condition::CarrySet => self.get_c(),                        // CS / LT
+            Condition::Equal => self.get_z(),                           // EQ
+            Condition::NotEqual => !self.get_z(),                       // NE
+            Condition::LessOrEqual => self.get_c() || self.get_z(),     // LE
+            Condition::GreaterOrEqual => !self.get_c() || self.get_z(), // GE
+            Condition::Always | Condition::Subroutine => true,          // RA / MP, SR
+        }
+    }
+
+    fn execute(&mut self, mem: &mut Memory, instruction: &InstructionFormat) -> Result<(), Illegal> {
+        match instruction {
+            InstructionFormat::Format0op(layout) => match *layout.opcode {
+                // RTS
+                0x1C => {
+                    self.registers[PC] = self.pop(mem);
+                    Ok(())
+                },
+                // RTE
+                0x1E => {
+                    self.state_register = self.pop(mem);
+                    self.registers[PC] = self.pop(mem);
+                    Ok(())
+                },
+                _ => Err(Illegal),
+            },
+            InstructionFormat::Format1op(layout) => self.execute_1op(mem, layout),
+            InstructionFormat::Format2op(layout) => self.execute_2op(mem, layout),
+            InstructionFormat::FormatMoveOp(layout) => self.execute_move(mem, layout),
+        }
+    }
+
+    fn execute_move(&mut self, mem: &mut Memory, layout: &_FormatMoveLayout) -> Result<(), Illegal> {
+        let source_type = *layout.source_type;
+        let destination_type = *layout.destination_type;
+
+        // The register number goes with the operand that isn't an immediate/address
+        let (source_value, destination_value) = match source_type {
+            MODE_IMMEDIATE | MODE_ADDRESS => (*layout.value, *layout.registry_no),
+            _ => (*layout.registry_no, *layout.value),
+        };
+
+        match (*layout.h, *layout.l) {
+            // MOVE
+            (1, 1) => {
+                let source = self.locate(source_type, source_value, 2)?;
+                let value = self.read(mem, source);
+                let destination = self.locate(destination_type, destination_value, 2)?;
+                self.write(mem, destination, value)?;
+                self.set_flags(value, false);
+            },
+            // MOVE.L / MOVE.H
+            (h, _) => {
+                let source = self.locate(source_type, source_value, 1)?;
+                let value = self.read(mem, source);
+                let byte = if h == 1 { (value >> 8) as u8 } else { value as u8 };
+
+                match self.locate(destination_type, destination_value, 1)? {
+                    Location::Register(register) => {
+                        self.registers[register] = (self.registers[register] & 0xFF00) | byte as u16;
+                    },
+                    Location::Memory(addr) => mem.write_u8(addr, byte),
+                    Location::Immediate(_) => return Err(Illegal),
+                }
+                self.set_flags_u8(byte);
+            },
+        }
+
+        Ok(())
+    }
+
+    fn execute_1op(&mut self, mem: &mut Memory, layout: &_Format1opLayout) -> Result<(), Illegal> {
+        let opcode = *layout.opcode;
+        let op_type = *layout.op_type;
+        let op_value = layout.extension.map_or(*layout.op_value, |extension| *extension);
+
+        match opcode {
+            // PUSH
+            0x01 => {
+                let source = self.locate(op_type, op_value, 2)?;
+                let value = self.read(mem, source);
+                self.push(mem, value);
+                self.set_flags(value, false);
+            },
+            // POP
+            0x02 => {
+                let value = self.pop(mem);
+                let destination = self.locate(op_type, op_value, 2)?;
+                self.write(mem, destination, value)?;
+                self.set_flags(value, false);
+            },
+            // NOT
+            0x0B => {
+                let destination = self.locate(op_type, op_value, 2)?;
+                let value = !self.read(mem, destination);
+                self.write(mem, destination, value)?;
+                self.set_flags(value, false);
+            },
+            // TRAP
+            0x1D => {
+                let source = self.locate(op_type, op_value, 2)?;
+                let vector = self.read(mem, source);
+                self.trigger_interrupt(mem, vector);
+            },
+            _ => match isa::by_opcode(opcode).map(|instruction| instruction.kind) {
+                // Bxx / BSR, the displacement is relative to the next instruction.
+                // Only the 8 bits one has to be sign extended
+                Some(Kind::Branch(condition)) => {
+                    let displacement = if op_type == MODE_IMMEDIATE {
+                        op_value as u8 as i8 as u16
+                    } else {
+                        let source = self.locate(op_type, op_value, 2)?;
+                        self.read(mem, source)
+                    };
+
+                    if condition == Condition::Subroutine {
+                        self.push(mem, self.registers[PC]);
+                    }
+                    if self.condition(condition) {
+                        self.registers[PC] = self.registers[PC].wrapping_add(displacement);
+                    }
+                },
+                // Jxx / JSR
+                Some(Kind::Jump(condition)) => {
+                    let source = self.locate(op_type, op_value, 2)?;
+                    let target = self.read(mem, source);
+
+                    if condition == Condition::Subroutine {
+                        self.push(mem, self.registers[PC]);
+                    }
+                    if self.condition(condition) {
+                        self.registers[PC] = target;
+                    }
+                },
+                _ => return Err(Illegal),
+            },
+        }
+
+        Ok(())
+    }
+
+    fn execute_2op(&mut self, mem: &mut Memory, layout: &_Format2opLayout) -> Result<(), Illegal> {
+        let op_value = layout.extension.map_or(*layout.op_value, |extension| *extension);
+        let source = self.locate(*layout.op_type_source, op_value, 2)?;
+        let source = self.read(mem, source);
+
+        let register = *layout.registry_dest as usize;
+        let destination = self.registers[register];
+
+        let (result, carry) = match *layout.opcode {
+            // ADD
+            0x03 => destination.overflowing_add(source),
+            // CMP / SUB
+            0x04 | 0x05 => destination.overflowing_sub(source),
+            // LSL, the carry holds the last bit shifted out
+            0x06 => match source {
+                0 => (destination, false),
+                1..=16 => (
+                    ((destination as u32) << source) as u16,
+                    (destination as u32 >> (16 - source)) & 1 == 1,
+                ),
+                _ => (0, false),
+            },
+            // LSR
+            0x07 => match source {
+                0 => (destination, false),
+                1..=16 => (
+                    (destination as u32 >> source) as u16,
+                    (destination as u32 >> (source - 1)) & 1 == 1,
+                ),
+                _ => (0, false),
+            },
+            // AND
+            0x08 => (destination & source, false),
+            // OR
+            0x09 => (destination | source, false),
+            // XOR
+            0x0A => (destination ^ source, false),
+            _ => return Err(Illegal),
+        };
+
+        // CMP only updates the flags
+        if *layout.opcode != 0x04 {
+            self.registers[register] = result;
+        }
+        self.set_flags(result, carry);
+
+        Ok(())
+    }
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,268 @@
+//! Turns machine words back into `InstructionFormat`, the first stage of the CPU.
+//!
+//! The format of an instruction is never stored in the binary, it is deduced
+//! from the 5 bits opcode : the opcode is looked up in the ISA table which
+//! tells which layout has been used to encode it.
+
+use core::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::parser::{
+    has_extension,
+    InstructionFormat,
+    _Format0opLayout,
+    _Format1opLayout,
+    _Format2opLayout,
+    _FormatMoveLayout,
+};
+use crate::catalog;
+use crate::error::FileError;
+use crate::isa::{self, Format};
+use crate::utils::BitInt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    /// The opcode of the word isn't part of the instruction set
+    UnknownOpcode { opcode: u16, word: u16 },
+    /// The stream stopped before all the extension words of an instruction were read
+    TruncatedInstruction { opcode: u16, expected: usize, found: usize },
+}
+
+impl DecodeError {
+    /// Stable code of the error, see `catalog`
+    pub fn code(&self) -> &'static str {
+        match self {
+            DecodeError::UnknownOpcode { .. } => "E0032",
+            DecodeError::TruncatedInstruction { .. } => "E0033",
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let code = self.code();
+        let message = match self {
+            DecodeError::UnknownOpcode { opcode, word } => {
+                catalog::text(code, &[("opcode", &format!("{:05b}", opcode)), ("word", &format!("{:#06x}", word))])
+            },
+            DecodeError::TruncatedInstruction { opcode, expected, found } => {
+                catalog::text(code, &[("opcode", &format!("{:05b}", opcode)), ("expected", expected), ("found", found)])
+            },
+        };
+
+        f.write_str(&message)
+    }
+}
+
+impl std::error::Error for DecodeError { }
+
+fn field<const N: usize>(word: u16, shift: u16) -> BitInt<N> {
+    let mask = if N == 16 { 0xFFFF } else { (1 << N) - 1 };
+
+    BitInt::<N>::new((word >> shift) & mask).unwrap()
+}
+
+/// Read the extension word of the 1op/2op formats, when `mode` needs one
+fn extension<I: Iterator<Item = u16>>(words: &mut I, opcode: u16, mode: u16) -> Result<Option<BitInt<16>>, DecodeError> {
+    if !has_extension(mode) {
+        return Ok(None);
+    }
+
+    match words.next() {
+        Some(word) => Ok(Some(field(word, 0))),
+        None => Err(DecodeError::TruncatedInstruction { opcode, expected: 1, found: 0 }),
+    }
+}
+
+/// Decode the next instruction of the stream.
+///
+/// Returns `Ok(None)` once the stream is exhausted.
+pub fn decode<I: Iterator<Item = u16>>(words: &mut I) -> Result<Option<InstructionFormat>, DecodeError> {
+    let word = match words.next() {
+        Some(word) => word,
+        None => return Ok(None),
+    };
+
+    let opcode = word >> 11;
+    let format = isa::by_opcode(opcode)
+        .ok_or(DecodeError::UnknownOpcode { opcode, word })?
+        .format;
+
+    let instruction = match format {
+        Format::Op0 => InstructionFormat::Format0op(_Format0opLayout {
+            opcode: field(word, 11),
+            op_reserved: field(word, 0),
+        }),
+        Format::Op1 => InstructionFormat::Format1op(_Format1opLayout {
+            opcode: field(word, 11),
+            op_type: field(word, 8),
+            op_value: field(word, 0),
+            extension: extension(words, opcode, *field::<3>(word, 8))?,
+        }),
+        Format::Op2 => InstructionFormat::Format2op(_Format2opLayout {
+            opcode: field(word, 11),
+            registry_dest: field(word, 8),
+            op_type_source: field(word, 5),
+            op_value: field(word, 0),
+            extension: extension(words, opcode, *field::<3>(word, 5))?,
+        }),
+        Format::Move => {
+            let value = words.next()
+                .ok_or(DecodeError::TruncatedInstruction { opcode, expected: 1, found: 0 })?;
+
+            InstructionFormat::FormatMoveOp(_FormatMoveLayout {
+                opcode: field(word, 11),
+                h: field(word, 10),
+                l: field(word, 9),
+                source_type: field(word, 6),
+                destination_type: field(word, 3),
+                registry_no: field(word, 0),
+                value: field(value, 0),
+            })
+        },
+    };
+
+    Ok(Some(instruction))
+}
+
+/// Decode a whole program
+pub fn decode_all(words: &[u16]) -> Result<Vec<InstructionFormat>, DecodeError> {
+    let mut stream = words.iter().copied();
+    let mut instructions = Vec::new();
+
+    while let Some(instruction) = decode(&mut stream)? {
+        instructions.push(instruction);
+    }
+
+    Ok(instructions)
+}
+
+/// Read a raw big-endian image, as written by `encoder::write_words`
+pub fn read_words<R: Read>(reader: &mut R) -> io::Result<Vec<u16>> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+
+    if bytes.len() % 2 != 0 {
+        return Err(FileError::OddImage.into());
+    }
+
+    Ok(bytes.chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .collect())
+}
+
+/// Read the image stored at `path`
+pub fn read_binary<P: AsRef<Path>>(path: P) -> io::Result<Vec<u16>> {
+    read_words(&mut File::open(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::{encode, encode_all};
+
+    fn roundtrip(instruction: InstructionFormat) {
+        let words = encode(&instruction);
+        let decoded = decode(&mut words.into_iter()).unwrap().unwrap();
+        assert_eq!(decoded, instruction);
+    }
+
+    #[test]
+    fn roundtrip_every_format() {
+        roundtrip(InstructionFormat::Format0op(_Format0opLayout {
+            opcode: BitInt::<5>::new(0x1C).unwrap(),
+            op_reserved: BitInt::<11>::new(0).unwrap(),
+        }));
+        roundtrip(InstructionFormat::Format1op(_Format1opLayout {
+            opcode: BitInt::<5>::new(0x01).unwrap(),
+            op_type: BitInt::<3>::new(0b101).unwrap(),
+            op_value: BitInt::<8>::new(0xFA).unwrap(),
+            extension: None,
+        }));
+        roundtrip(InstructionFormat::Format1op(_Format1opLayout {
+            opcode: BitInt::<5>::new(0x1A).unwrap(),
+            op_type: BitInt::<3>::new(0b110).unwrap(),
+            op_value: BitInt::<8>::new(0).unwrap(),
+            extension: Some(BitInt::<16>::new(0x1234).unwrap()),
+        }));
+        roundtrip(InstructionFormat::Format2op(_Format2opLayout {
+            opcode: BitInt::<5>::new(0x03).unwrap(),
+            registry_dest: BitInt::<3>::new(7).unwrap(),
+            op_type_source: BitInt::<3>::new(0b011).unwrap(),
+            op_value: BitInt::<5>::new(0x1F).unwrap(),
+            extension: None,
+        }));
+        roundtrip(InstructionFormat::Format2op(_Format2opLayout {
+            opcode: BitInt::<5>::new(0x04).unwrap(),
+            registry_dest: BitInt::<3>::new(0).unwrap(),
+            op_type_source: BitInt::<3>::new(0b111).unwrap(),
+            op_value: BitInt::<5>::new(0).unwrap(),
+            extension: Some(BitInt::<16>::new(0xFFFE).unwrap()),
+        }));
+        roundtrip(InstructionFormat::FormatMoveOp(_FormatMoveLayout {
+            opcode: BitInt::<5>::new(0x00).unwrap(),
+            h: BitInt::<1>::new(0).unwrap(),
+            l: BitInt::<1>::new(1).unwrap(),
+            source_type: BitInt::<3>::new(0b100).unwrap(),
+            destination_type: BitInt::<3>::new(0b010).unwrap(),
+            registry_no: BitInt::<3>::new(5).unwrap(),
+            value: BitInt::<16>::new(0xCAFE).unwrap(),
+        }));
+    }
+
+    #[test]
+    fn roundtrip_parsed_program() {
+        let program: Vec<InstructionFormat> = [
+            "ADD R0, R1",
+            "ADD (R1), R3",
+            "ADD -(R3), R4",
+            "PUSH @0xFA",
+            "PUSH @0x1FA",
+            "CMP #0xFFFE, R0",
+            "JMP #0x1234",
+            "MOVE.L R3, @0x22",
+            "MOVE.H R1, (R2)",
+            "RTS",
+        ].iter()
+            .map(|line| {
+                let symbols = crate::parser::Symbols::default();
+                let constants = crate::parser::Constants::new();
+                let context = crate::parser::Context::new(&symbols, &constants);
+                match crate::ast::parse_line(line, context.dialect).pop() {
+                    Some(crate::ast::Statement::Instruction(instruction)) => crate::parser::parse(&instruction, &context).unwrap(),
+                    statement => panic!("{:?} isn't an instruction", statement),
+                }
+            })
+            .collect();
+
+        assert_eq!(decode_all(&encode_all(&program)).unwrap(), program);
+    }
+
+    #[test]
+    fn unknown_opcode() {
+        assert_eq!(decode_all(&[0xF800]), Err(DecodeError::UnknownOpcode { opcode: 0x1F, word: 0xF800 }));
+    }
+
+    #[test]
+    fn truncated_move() {
+        assert_eq!(decode_all(&[0x022B]), Err(DecodeError::TruncatedInstruction { opcode: 0, expected: 1, found: 0 }));
+    }
+
+    #[test]
+    fn truncated_extension() {
+        // CMP #x, R0 with an extended immediate but no extension word
+        assert_eq!(decode_all(&[0x20C0]), Err(DecodeError::TruncatedInstruction { opcode: 0x04, expected: 1, found: 0 }));
+    }
+
+    #[test]
+    fn image_is_big_endian() {
+        let mut bytes: &[u8] = &[0x19, 0x00, 0x02, 0x2B];
+        assert_eq!(read_words(&mut bytes).unwrap(), vec![0x1900, 0x022B]);
+
+        let mut bytes: &[u8] = &[0x19, 0x00, 0x02];
+        let error = read_words(&mut bytes).err().unwrap();
+        assert_eq!(FileError::of(&error), Some(&FileError::OddImage));
+    }
+}
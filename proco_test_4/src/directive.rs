@@ -0,0 +1,268 @@
+//! Assembler directives, the lines starting with a `.` :
+//!
+//! ```text
+//! .org 0x100          | continue at 0x100 (it can't move backwards)
+//! .word 1, -2, LABEL  | 16 bits values
+//! .byte 1, 0xFF       | 8 bits values
+//! .string "hi\n"      | the bytes of the string followed by a NUL
+//! .space 16[, 0xFF]   | 16 bytes (filled with 0 by default)
+//! .align 2            | continue at the next multiple of 2
+//! .equ SIZE, 4 * 2    | the constant SIZE, also written SIZE = 4 * 2
+//! .text               | continue in the code section (the default one)
+//! .data               | continue in the data section
+//! .bss                | continue in the section reserved at run time
+//! .global START, LOOP | export labels (or constants) to the other objects
+//! .extern PRINT       | import labels from the other objects
+//! ```
+//!
+//! Values are expressions (see `expr`) written without the `#` of the
+//! immediates. Words and bytes are stored the way the memory holds them, the
+//! low byte of a word first.
+//!
+//! Each section has its own address, the following lines continue where the
+//! section has been left. `.org` only moves within `.text`, `.bss` only
+//! reserves space (`.space` with no fill, `.align`).
+
+use crate::error::{AsmErrorKind, ParseError};
+use crate::expr::{self, Base};
+use crate::ast::{Directive, Node};
+use crate::parser::{self, Context};
+
+/// The sections of a program, laid out one after the other
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Section {
+    Text,
+    Data,
+    Bss,
+}
+
+impl Section {
+    pub const ALL: [Section; 3] = [Section::Text, Section::Data, Section::Bss];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Section::Text => "text",
+            Section::Data => "data",
+            Section::Bss => "bss",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Section> {
+        Section::ALL.into_iter().find(|section| section.name() == name)
+    }
+}
+
+/// A directive that doesn't emit anything, handled by the assembler itself
+#[derive(Debug, Clone, PartialEq)]
+pub enum Declaration<'a> {
+    Section(Section),
+    Global(Vec<Node<'a>>),
+    Extern(Vec<Node<'a>>),
+}
+
+/// What a directive puts at the current address
+#[derive(Debug, Clone, PartialEq)]
+pub struct Placed {
+    /// Address where the directive starts (`.org` and `.align` move it)
+    pub address: u16,
+    pub bytes: Vec<u8>,
+}
+
+/// Value of an argument that has to fit in `bits` bits, signed or not. The
+/// linker only fixes words, it has to be a number in a relocatable object
+fn sized_value(argument: Node, bits: usize, context: &Context) -> Result<u16, ParseError> {
+    let value = expr::evaluate(argument, context)?;
+    if context.fixups.is_some() && value.base != Base::Absolute {
+        return Err(ParseError::new(argument.span, AsmErrorKind::NotRelocatable(argument.text.to_string())));
+    }
+
+    value.bits(argument, bits)
+}
+
+/// Same as `sized_value` for the arguments that move the next addresses : the
+/// names have to be known by the first pass, the labels would move otherwise.
+/// An address or a size isn't negative
+fn layout_value(argument: Node, bits: usize, context: &Context) -> Result<u16, ParseError> {
+    let value = expr::evaluate(argument, context)?;
+    if value.forward {
+        return Err(ParseError::new(argument.span, AsmErrorKind::ForwardReference(argument.text.to_string())));
+    }
+    if value.value < 0 {
+        return Err(ParseError::new(argument.span, AsmErrorKind::ValueOutOfRange { value: value.value, bits }));
+    }
+
+    value.bits(argument, bits)
+}
+
+/// Bytes of a `"..."` literal, without its quotes
+fn string_bytes(argument: Node) -> Result<Vec<u8>, ParseError> {
+    let content = argument.text.strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+        .filter(|_| argument.text.len() >= 2)
+        .ok_or(ParseError::new(argument.span, AsmErrorKind::UnterminatedString))?;
+
+    let mut bytes = Vec::new();
+    let mut chars = content.chars();
+
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('0') => '\0',
+                Some('\\') => '\\',
+                Some('"') => '"',
+                _ => return Err(ParseError::new(argument.span, AsmErrorKind::BadOperand(argument.text.to_string()))),
+            },
+            c => c,
+        };
+
+        let mut buffer = [0; 4];
+        bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+    }
+
+    Ok(bytes)
+}
+
+/// Report a wrong number of arguments of `directive`, it takes `min` to `max`
+fn count(directive: &Directive, min: usize, max: usize) -> Result<(), ParseError> {
+    let arguments = &directive.arguments;
+    if (min..=max).contains(&arguments.len()) {
+        return Ok(());
+    }
+
+    let token = arguments.get(max).copied().unwrap_or(directive.name);
+    Err(ParseError::new(token.span, AsmErrorKind::OperandCount {
+        mnemonic: directive.name.text.to_string(),
+        expected: if arguments.len() < min { min } else { max },
+        found: arguments.len(),
+    }))
+}
+
+/// The name and the expression of the constant defined by `directive`, `None`
+/// when it doesn't define one
+pub fn constant<'a>(directive: &Directive<'a>) -> Option<Result<(Node<'a>, Node<'a>), ParseError>> {
+    if !directive.name.text.eq_ignore_ascii_case(".equ") && directive.name.text != "=" {
+        return None;
+    }
+
+    Some(count(directive, 2, 2).and_then(|_| {
+        let (name, expression) = (directive.arguments[0], directive.arguments[1]);
+        if !parser::is_identifier(name.text) {
+            return Err(ParseError::new(name.span, AsmErrorKind::BadOperand(name.text.to_string())));
+        }
+        Ok((name, expression))
+    }))
+}
+
+/// The declaration of `directive`, `None` when it isn't one
+pub fn declaration<'a>(directive: &Directive<'a>) -> Option<Result<Declaration<'a>, ParseError>> {
+    let name = directive.name.text.to_lowercase();
+
+    if let Some(section) = name.strip_prefix('.').and_then(Section::from_name) {
+        return Some(count(directive, 0, 0).map(|_| Declaration::Section(section)));
+    }
+
+    let global = match name.as_str() {
+        ".global" => true,
+        ".extern" => false,
+        _ => return None,
+    };
+
+    let names = directive.arguments.clone();
+    Some(count(directive, 1, usize::MAX).and_then(|_| {
+        if let Some(bad) = names.iter().find(|name| !parser::is_identifier(name.text)) {
+            return Err(ParseError::new(bad.span, AsmErrorKind::BadOperand(bad.text.to_string())));
+        }
+        Ok(if global { Declaration::Global(names) } else { Declaration::Extern(names) })
+    }))
+}
+
+/// The smallest alignment satisfying both `a` and `b` (their least common
+/// multiple), up to 0x10000 : nothing fits past a larger one
+pub fn common_alignment(a: u32, b: u32) -> u32 {
+    let (mut x, mut y) = (a, b);
+    while y != 0 {
+        (x, y) = (y, x % y);
+    }
+
+    ((a / x) as u64 * b as u64).min(0x10000) as u32
+}
+
+/// The alignment asked by `directive`, `None` when it isn't an `.align`
+pub fn alignment(directive: &Directive, context: &Context) -> Option<Result<u16, ParseError>> {
+    if !directive.name.text.eq_ignore_ascii_case(".align") {
+        return None;
+    }
+
+    Some(count(directive, 1, 1).and_then(|_| {
+        let argument = directive.arguments[0];
+        match layout_value(argument, 16, context)? {
+            0 => Err(ParseError::new(argument.span, AsmErrorKind::ValueOutOfRange { value: 0, bits: 16 })),
+            alignment => Ok(alignment),
+        }
+    }))
+}
+
+/// Parse `directive` placed at `context.address`
+pub fn parse(directive: &Directive, context: &Context) -> Result<Placed, ParseError> {
+    let name = directive.name;
+    let arguments = &directive.arguments;
+    let count = |min: usize, max: usize| count(directive, min, max);
+    let address = context.address;
+
+    match name.text.to_lowercase().as_str() {
+        ".org" => {
+            count(1, 1)?;
+            if context.section != Section::Text {
+                return Err(ParseError::new(name.span, AsmErrorKind::OrgOutsideText));
+            }
+            let target = layout_value(arguments[0], 16, context)?;
+
+            if target < address {
+                return Err(ParseError::new(arguments[0].span, AsmErrorKind::OrgBackwards { from: address, to: target }));
+            }
+            Ok(Placed { address: target, bytes: Vec::new() })
+        },
+        ".word" => {
+            count(1, usize::MAX)?;
+            let mut bytes = Vec::new();
+            for &argument in arguments {
+                let value = expr::evaluate(argument, context)?;
+                context.fixup(argument, bytes.len() as u16, value, false)?;
+                bytes.extend_from_slice(&value.bits(argument, 16)?.to_le_bytes());
+            }
+            Ok(Placed { address, bytes })
+        },
+        ".byte" => {
+            count(1, usize::MAX)?;
+            let bytes = arguments.iter()
+                .map(|argument| sized_value(*argument, 8, context).map(|value| value as u8))
+                .collect::<Result<Vec<u8>, _>>()?;
+            Ok(Placed { address, bytes })
+        },
+        ".string" => {
+            count(1, 1)?;
+            let mut bytes = string_bytes(arguments[0])?;
+            bytes.push(0);
+            Ok(Placed { address, bytes })
+        },
+        ".space" => {
+            count(1, 2)?;
+            let size = layout_value(arguments[0], 16, context)?;
+            let fill = match arguments.get(1) {
+                Some(fill) => sized_value(*fill, 8, context)? as u8,
+                None => 0,
+            };
+            Ok(Placed { address, bytes: vec![fill; size as usize] })
+        },
+        ".align" => {
+            let alignment = alignment(directive, context).expect("an .align has an alignment")?;
+            // The next multiple may be the end of the memory, where nothing fits
+            let aligned = (address as u32).next_multiple_of(alignment as u32);
+            let address = u16::try_from(aligned).map_err(|_| ParseError::new(directive.span, AsmErrorKind::Overflow))?;
+            Ok(Placed { address, bytes: Vec::new() })
+        },
+        _ => Err(ParseError::new(name.span, AsmErrorKind::UnknownDirective(name.text.to_string()))),
+    }
+}
@@ -0,0 +1,244 @@
+//! Turns machine words back into assembly source.
+//!
+//! The output can be assembled again : operands are written the way the parser
+//! reads them, a branch is written as the offset from the branch itself (or as
+//! the label of its target when the symbols of the image are known) and a word
+//! that isn't an instruction becomes a `.WORD`. The instructions a
+//! pseudo-instruction expands to can be folded back into it (see `fold`).
+
+use core::fmt;
+use std::collections::HashMap;
+
+use crate::decoder::decode;
+use crate::isa::{self, Kind};
+use crate::parser::{
+    InstructionFormat,
+    SymbolTable,
+    _Format1opLayout,
+    _FormatMoveLayout,
+};
+use crate::symbols;
+
+/// An instruction (or a word that isn't one) found in an image
+pub struct Line {
+    pub address: u16,
+    /// The instruction word followed by its extension words
+    pub words: Vec<u16>,
+    /// `None` when the words can't be decoded
+    pub instruction: Option<InstructionFormat>,
+}
+
+/// Split the image loaded at `origin` into instructions
+pub fn disassemble(words: &[u16], origin: u16) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut i = 0;
+
+    while i < words.len() {
+        let address = origin.wrapping_add((i * 2) as u16);
+        let mut stream = words[i..].iter().copied();
+
+        let (instruction, size) = match decode(&mut stream) {
+            Ok(Some(instruction)) => (Some(instruction), words.len() - i - stream.len()),
+            Ok(None) => break,
+            Err(_) => (None, 1),
+        };
+
+        lines.push(Line { address, words: words[i..i + size].to_vec(), instruction });
+        i += size;
+    }
+
+    lines
+}
+
+/// Name of the labels by address, the first one in alphabetical order wins
+pub fn labels(symbols: &SymbolTable) -> HashMap<u16, &str> {
+    let mut labels = HashMap::new();
+    for (name, address) in symbols::sorted(symbols) {
+        labels.entry(address).or_insert(name);
+    }
+
+    labels
+}
+
+/// Assembly syntax of a line, `labels` are used to name the branch targets
+pub struct Syntax<'a> {
+    pub line: &'a Line,
+    pub labels: Option<&'a HashMap<u16, &'a str>>,
+}
+
+fn operand(mode: u16, value: u16) -> String {
+    match mode {
+        0b000 => format!("R{}", value),
+        0b001 => format!("-(R{})", value),
+        0b010 => format!("(R{})", value),
+        0b011 => format!("(R{})+", value),
+        0b100 | 0b110 => format!("#0x{:X}", value),
+        _ => format!("@0x{:X}", value),
+    }
+}
+
+impl Syntax<'_> {
+    fn label(&self, address: u16) -> Option<&str> {
+        self.labels.and_then(|labels| labels.get(&address).copied())
+    }
+
+    fn format_1op(&self, layout: &_Format1opLayout) -> String {
+        let opcode = *layout.opcode;
+        let mode = *layout.op_type;
+        let value = layout.extension.map_or(*layout.op_value, |extension| *extension);
+
+        let next = self.line.address.wrapping_add((self.line.words.len() * 2) as u16);
+        let kind = isa::by_opcode(opcode).map_or(Kind::Plain, |instruction| instruction.kind);
+
+        // Bxx and BSR take a displacement instead of an address
+        match mode {
+            0b100 | 0b110 if matches!(kind, Kind::Branch(_)) => {
+                let displacement = if mode == 0b100 { value as u8 as i8 as u16 } else { value };
+                let target = next.wrapping_add(displacement);
+
+                match self.label(target) {
+                    Some(label) => format!("#{}", label),
+                    None => format!("#{}", target.wrapping_sub(self.line.address) as i16),
+                }
+            },
+            0b100 | 0b110 if matches!(kind, Kind::Jump(_)) => match self.label(value) {
+                Some(label) => format!("#{}", label),
+                None => operand(mode, value),
+            },
+            _ => operand(mode, value),
+        }
+    }
+
+    fn format_move(layout: &_FormatMoveLayout) -> String {
+        let suffix = match (*layout.h, *layout.l) {
+            (0, _) => ".L",
+            (_, 0) => ".H",
+            _ => "",
+        };
+
+        // The register number goes with the operand that isn't an immediate/address
+        let (source, destination) = match *layout.source_type {
+            0b100 | 0b101 => (*layout.value, *layout.registry_no),
+            _ => (*layout.registry_no, *layout.value),
+        };
+
+        format!("MOVE{} {}, {}", suffix, operand(*layout.source_type, source), operand(*layout.destination_type, destination))
+    }
+}
+
+impl fmt::Display for Syntax<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Some(instruction) = &self.line.instruction else {
+            return write!(f, ".WORD 0x{:04X}", self.line.words[0]);
+        };
+
+        let mnemonic = |opcode: u16| isa::by_opcode(opcode).map_or("???", |instruction| instruction.mnemonic);
+
+        match instruction {
+            InstructionFormat::Format0op(layout) => write!(f, "{}", mnemonic(*layout.opcode)),
+            InstructionFormat::Format1op(layout) => write!(f, "{} {}", mnemonic(*layout.opcode), self.format_1op(layout)),
+            InstructionFormat::Format2op(layout) => {
+                let value = layout.extension.map_or(*layout.op_value, |extension| *extension);
+                write!(f, "{} {}, R{}", mnemonic(*layout.opcode), operand(*layout.op_type_source, value), *layout.registry_dest)
+            },
+            InstructionFormat::FormatMoveOp(layout) => write!(f, "{}", Syntax::format_move(layout)),
+        }
+    }
+}
+
+/// The pseudo-instruction `lines` start with (see `isa::PSEUDO`), along with
+/// the number of lines it stands for. A line with a label can only be the first one
+pub fn fold(lines: &[Line], labels: Option<&HashMap<u16, &str>>) -> Option<(String, usize)> {
+    let longest = isa::PSEUDO.iter().map(|pseudo| pseudo.body.len()).max().unwrap_or(0);
+    let labeled = |line: &Line| labels.is_some_and(|labels| labels.contains_key(&line.address));
+    let length = lines.iter()
+        .take(longest)
+        .enumerate()
+        .take_while(|(i, line)| line.instruction.is_some() && (*i == 0 || !labeled(line)))
+        .count();
+
+    let texts: Vec<String> = lines[..length].iter()
+        .map(|line| Syntax { line, labels }.to_string())
+        .collect();
+
+    // The longest body wins, it stands for more instructions
+    (1..=length).rev()
+        .find_map(|n| isa::PSEUDO.iter().find_map(|pseudo| pseudo.fold(&texts[..n])).map(|folded| (folded, n)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler;
+
+    fn assemble(source: &str) -> assembler::Program {
+        assembler::assemble("test.asm", source, 0x10).unwrap()
+    }
+
+    fn text(words: &[u16], labels: Option<&HashMap<u16, &str>>) -> Vec<String> {
+        disassemble(words, 0x10).iter()
+            .map(|line| Syntax { line, labels }.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn canonical_syntax_reassembles() {
+        let source = [
+            "MOVE.L (R0), R1",
+            "MOVE.H #0xCD, R0",
+            "MOVE R3, @0x22",
+            "ADD #0xFFFE, R0",
+            "SUB (R1)+, R2",
+            "CMP -(R3), R4",
+            "PUSH @0x1FA",
+            "BCC #-4",
+            "BSR #256",
+            "JMP (R0)",
+            "RTS",
+        ];
+        let words = assemble(&source.join("\n")).words();
+
+        assert_eq!(text(&words, None), source);
+    }
+
+    #[test]
+    fn branch_targets_use_labels() {
+        let program = assemble("START: ADD #1, R0\nBNE #START\nBEQ #-4\nJMP #START\nJMP #0x40");
+        let labels = labels(&program.symbols);
+
+        assert_eq!(text(&program.words(), Some(&labels)), [
+            "ADD #0x1, R0",
+            "BNE #START",
+            "BEQ #-4",
+            "JMP #START",
+            "JMP #0x40",
+        ]);
+    }
+
+    #[test]
+    fn pseudo_instructions_fold_back() {
+        let program = assemble("START: NEG R1\nSWAP R1, (R2)\nLI R0, #START\nINC R0\nL: ADD #0x1, R0\nCALL START\nRTS");
+        let labels = labels(&program.symbols);
+        let lines = disassemble(&program.words(), 0x10);
+
+        let mut folded = Vec::new();
+        let mut i = 0;
+        while i < lines.len() {
+            let (text, n) = fold(&lines[i..], Some(&labels)).unwrap_or_else(|| (Syntax { line: &lines[i], labels: Some(&labels) }.to_string(), 1));
+            folded.push(text);
+            i += n;
+        }
+
+        assert_eq!(folded, ["NEG R1", "SWAP R1, (R2)", "LI R0, #0x10", "INC R0", "INC R0", "CALL START", "RET"]);
+    }
+
+    #[test]
+    fn unknown_words() {
+        let lines = disassemble(&[0xF800, 0xF000], 0x10);
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(Syntax { line: &lines[0], labels: None }.to_string(), ".WORD 0xF800");
+        assert_eq!(lines[1].address, 0x12);
+        assert_eq!(Syntax { line: &lines[1], labels: None }.to_string(), "RTE");
+    }
+}
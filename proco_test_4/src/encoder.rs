@@ -0,0 +1,130 @@
+//! Turns parsed instructions into machine words.
+//!
+//! Every instruction is packed into a 16 bits word, most significant bits
+//! first, exactly in the order the fields are declared in the layouts :
+//!
+//! ```text
+//! Format0op  | opcode(5) | reserved(11)                                          |
+//! Format1op  | opcode(5) | op_type(3) | op_value(8)                              | (+ extension(16))
+//! Format2op  | opcode(5) | registry_dest(3) | op_type_source(3) | op_value(5)    | (+ extension(16))
+//! MoveFormat | opcode(5) | h(1) | l(1) | source_type(3) | dest_type(3) | reg(3) | + value(16)
+//! ```
+//!
+//! The move format is always followed by an extension word holding its 16 bits
+//! value. The 1op/2op formats only get one when their operand uses an extended
+//! mode (`#x` or `@x` that doesn't fit in op_value, or that uses a label) : the
+//! op_value field is then left to 0 and the whole value is in the extension word.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::parser::{
+    InstructionFormat,
+    _Format0opLayout,
+    _Format1opLayout,
+    _Format2opLayout,
+    _FormatMoveLayout,
+};
+
+impl _Format0opLayout {
+    pub fn encode(&self) -> u16 {
+        (*self.opcode << 11) | *self.op_reserved
+    }
+}
+
+impl _Format1opLayout {
+    pub fn encode(&self) -> Vec<u16> {
+        let word = (*self.opcode << 11) | (*self.op_type << 8) | *self.op_value;
+
+        std::iter::once(word)
+            .chain(self.extension.map(|extension| *extension))
+            .collect()
+    }
+}
+
+impl _Format2opLayout {
+    pub fn encode(&self) -> Vec<u16> {
+        let word = (*self.opcode << 11)
+            | (*self.registry_dest << 8)
+            | (*self.op_type_source << 5)
+            | *self.op_value;
+
+        std::iter::once(word)
+            .chain(self.extension.map(|extension| *extension))
+            .collect()
+    }
+}
+
+impl _FormatMoveLayout {
+    pub fn encode(&self) -> [u16; 2] {
+        let word = (*self.opcode << 11)
+            | (*self.h << 10)
+            | (*self.l << 9)
+            | (*self.source_type << 6)
+            | (*self.destination_type << 3)
+            | *self.registry_no;
+
+        [word, *self.value]
+    }
+}
+
+/// Encode a single instruction, the returned vector holds the instruction word
+/// followed by its extension words (if any)
+pub fn encode(instruction: &InstructionFormat) -> Vec<u16> {
+    match instruction {
+        InstructionFormat::Format0op(layout) => vec![layout.encode()],
+        InstructionFormat::Format1op(layout) => layout.encode(),
+        InstructionFormat::Format2op(layout) => layout.encode(),
+        InstructionFormat::FormatMoveOp(layout) => layout.encode().to_vec(),
+    }
+}
+
+/// Number of words of `instruction`, without encoding it
+pub fn length(instruction: &InstructionFormat) -> usize {
+    match instruction {
+        InstructionFormat::Format0op(_) => 1,
+        InstructionFormat::Format1op(layout) => 1 + layout.extension.is_some() as usize,
+        InstructionFormat::Format2op(layout) => 1 + layout.extension.is_some() as usize,
+        InstructionFormat::FormatMoveOp(_) => 2,
+    }
+}
+
+/// Encode a whole program, one instruction after the other
+pub fn encode_all(instructions: &[InstructionFormat]) -> Vec<u16> {
+    instructions.iter()
+        .flat_map(encode)
+        .collect()
+}
+
+/// The words of a memory image, the low byte of a word first
+pub fn words(bytes: &[u8]) -> Vec<u16> {
+    bytes.chunks(2)
+        .map(|pair| u16::from_le_bytes([pair[0], *pair.get(1).unwrap_or(&0)]))
+        .collect()
+}
+
+/// Write the words as a raw big-endian image
+pub fn write_words<W: Write>(writer: &mut W, words: &[u16]) -> io::Result<()> {
+    for word in words {
+        writer.write_all(&word.to_be_bytes())?;
+    }
+
+    writer.flush()
+}
+
+/// Write the words as text, one word per line in hexadecimal
+pub fn write_hex<W: Write>(writer: &mut W, words: &[u16]) -> io::Result<()> {
+    for word in words {
+        writeln!(writer, "{:04x}", word)?;
+    }
+
+    writer.flush()
+}
+
+/// Write the words into the file at `path`
+pub fn write_image<P: AsRef<Path>>(path: P, words: &[u16]) -> io::Result<()> {
+    let mut file = File::create(path)?;
+
+    write_words(&mut file, words)
+}
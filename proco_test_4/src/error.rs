@@ -0,0 +1,307 @@
+//! Errors reported by the assembler.
+//!
+//! The parser only knows the token an error comes from, the assembler then
+//! locates it in the file (line and column span) to build an `AsmError`. The
+//! messages come from the `catalog`, by the code of the error. The files the
+//! tools write (objects, symbols, images) are read back with `FileError`.
+
+use core::fmt;
+use std::io;
+
+use crate::catalog;
+use crate::directive::Section;
+use crate::isa::{self, Modes};
+use crate::utils::{Diagnostic, Snippet};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AsmErrorKind {
+    UnknownMnemonic(String),
+    BadOperand(String),
+    ValueOutOfRange { value: i64, bits: usize },
+    IllegalAddressingMode { mnemonic: String, operand: String, role: String, allowed: Modes },
+    OperandCount { mnemonic: String, expected: usize, found: usize },
+    UndefinedLabel(String),
+    /// `previous` is where the name is defined first, `None` for a mnemonic
+    DuplicateLabel { name: String, previous: Option<Location> },
+    UnknownDirective(String),
+    UnterminatedString,
+    OrgBackwards { from: u16, to: u16 },
+    MisalignedInstruction(u16),
+    ForwardReference(String),
+    DivisionByZero,
+    Overflow,
+    UnterminatedMacro(String),
+    UnexpectedEndm,
+    MacroRecursion(String),
+    IncludeFailed { path: String, reason: String },
+    IncludeCycle(String),
+    UnterminatedConditional(String),
+    UnmatchedConditional(String),
+    ConditionalAfterElse(String),
+    NotRelocatable(String),
+    OrgOutsideText,
+    DataInBss,
+    UndefinedExport(String),
+}
+
+impl AsmErrorKind {
+    /// Stable code of the error, the key of its texts in the catalog
+    pub fn code(&self) -> &'static str {
+        match self {
+            AsmErrorKind::UnknownMnemonic(_) => "E0001",
+            AsmErrorKind::BadOperand(_) => "E0002",
+            AsmErrorKind::ValueOutOfRange { .. } => "E0003",
+            AsmErrorKind::IllegalAddressingMode { .. } => "E0004",
+            AsmErrorKind::OperandCount { .. } => "E0005",
+            AsmErrorKind::UndefinedLabel(_) => "E0006",
+            AsmErrorKind::DuplicateLabel { previous: Some(_), .. } => "E0007",
+            AsmErrorKind::DuplicateLabel { previous: None, .. } => "E0008",
+            AsmErrorKind::UnknownDirective(_) => "E0009",
+            AsmErrorKind::UnterminatedString => "E0010",
+            AsmErrorKind::OrgBackwards { .. } => "E0011",
+            AsmErrorKind::MisalignedInstruction(_) => "E0012",
+            AsmErrorKind::ForwardReference(_) => "E0013",
+            AsmErrorKind::DivisionByZero => "E0014",
+            AsmErrorKind::Overflow => "E0015",
+            AsmErrorKind::UnterminatedMacro(_) => "E0016",
+            AsmErrorKind::UnexpectedEndm => "E0017",
+            AsmErrorKind::MacroRecursion(_) => "E0018",
+            AsmErrorKind::IncludeFailed { .. } => "E0019",
+            AsmErrorKind::IncludeCycle(_) => "E0020",
+            AsmErrorKind::UnterminatedConditional(_) => "E0021",
+            AsmErrorKind::UnmatchedConditional(_) => "E0022",
+            AsmErrorKind::ConditionalAfterElse(_) => "E0023",
+            AsmErrorKind::NotRelocatable(_) => "E0024",
+            AsmErrorKind::OrgOutsideText => "E0025",
+            AsmErrorKind::DataInBss => "E0026",
+            AsmErrorKind::UndefinedExport(_) => "E0027",
+        }
+    }
+
+    /// What to do about the error, when there is more to say than its message
+    pub fn help(&self) -> Option<String> {
+        match self {
+            AsmErrorKind::UnknownMnemonic(mnemonic) => {
+                isa::suggest(mnemonic).map(|suggestion| catalog::text("E0001.help", &[("suggestion", &suggestion)]))
+            },
+            AsmErrorKind::IllegalAddressingMode { mnemonic, role, allowed, .. } if *allowed == isa::REGISTER => {
+                // MOVE is the one writing anywhere
+                let key = if role == "destination" && mnemonic != "MOVE" { "E0004.help.move" } else { "E0004.help" };
+                Some(catalog::text(key, &[("mnemonic", mnemonic), ("role", &role_name(role))]))
+            },
+            AsmErrorKind::ValueOutOfRange { bits, .. } => {
+                let (min, max) = (-(1i64 << (bits - 1)), (1i64 << bits) - 1);
+                Some(catalog::text("E0003.help", &[("bits", bits), ("min", &min), ("max", &max)]))
+            },
+            _ => catalog::try_text(&format!("{}.help", self.code()), &[]),
+        }
+    }
+}
+
+/// The role of an operand in the current language
+fn role_name(role: &str) -> String {
+    catalog::try_text(&format!("role.{}", role), &[]).unwrap_or_else(|| role.to_string())
+}
+
+impl fmt::Display for AsmErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let code = self.code();
+        let message = match self {
+            AsmErrorKind::UnknownMnemonic(mnemonic) => catalog::text(code, &[("mnemonic", mnemonic)]),
+            AsmErrorKind::BadOperand(operand) => catalog::text(code, &[("operand", operand)]),
+            AsmErrorKind::ValueOutOfRange { value, bits } => catalog::text(code, &[("value", value), ("bits", bits)]),
+            AsmErrorKind::IllegalAddressingMode { mnemonic, operand, role, allowed } => {
+                catalog::text(code, &[("mnemonic", mnemonic), ("operand", operand), ("role", &role_name(role)), ("allowed", allowed)])
+            },
+            AsmErrorKind::OperandCount { mnemonic, expected, found } => {
+                catalog::text(code, &[("mnemonic", mnemonic), ("expected", expected), ("found", found)])
+            },
+            AsmErrorKind::UndefinedLabel(label) | AsmErrorKind::ForwardReference(label) => catalog::text(code, &[("label", label)]),
+            AsmErrorKind::DuplicateLabel { name, previous: Some(previous) } => catalog::text(code, &[("name", name), ("previous", previous)]),
+            AsmErrorKind::DuplicateLabel { name, previous: None }
+            | AsmErrorKind::UnterminatedMacro(name)
+            | AsmErrorKind::MacroRecursion(name)
+            | AsmErrorKind::UndefinedExport(name) => catalog::text(code, &[("name", name)]),
+            AsmErrorKind::UnknownDirective(directive)
+            | AsmErrorKind::UnterminatedConditional(directive)
+            | AsmErrorKind::UnmatchedConditional(directive)
+            | AsmErrorKind::ConditionalAfterElse(directive) => catalog::text(code, &[("directive", directive)]),
+            AsmErrorKind::OrgBackwards { from, to } => {
+                catalog::text(code, &[("from", &format!("{:#06x}", from)), ("to", &format!("{:#06x}", to))])
+            },
+            AsmErrorKind::MisalignedInstruction(address) => catalog::text(code, &[("address", &format!("{:#06x}", address))]),
+            AsmErrorKind::IncludeFailed { path, reason } => catalog::text(code, &[("path", path), ("reason", reason)]),
+            AsmErrorKind::IncludeCycle(file) => catalog::text(code, &[("file", file)]),
+            AsmErrorKind::NotRelocatable(expression) => catalog::text(code, &[("expression", expression)]),
+            AsmErrorKind::UnterminatedString
+            | AsmErrorKind::DivisionByZero
+            | AsmErrorKind::Overflow
+            | AsmErrorKind::UnexpectedEndm
+            | AsmErrorKind::OrgOutsideText
+            | AsmErrorKind::DataInBss => catalog::text(code, &[]),
+        };
+
+        f.write_str(&message)
+    }
+}
+
+/// Error found by the parser, `span` is the part of the line it comes from
+#[derive(Debug)]
+pub struct ParseError {
+    pub span: Span,
+    pub kind: AsmErrorKind,
+}
+
+impl ParseError {
+    pub fn new(span: Span, kind: AsmErrorKind) -> Self {
+        ParseError { span, kind }
+    }
+}
+
+/// Columns (0 based, end excluded) of the faulty part of a line
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// The empty span at `at`
+    pub fn at(at: usize) -> Span {
+        Span { start: at, end: at }
+    }
+}
+
+/// A line of a source file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub file: String,
+    /// Line number, 1 based
+    pub line: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+/// Call of the macro an erroneous line has been expanded from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expansion {
+    pub name: String,
+    pub file: String,
+    /// Line number of the call, 1 based
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmError {
+    pub file: String,
+    /// Line number, 1 based
+    pub line: usize,
+    /// The text of the line, once the parameters of its macro are substituted
+    pub text: String,
+    /// For a line expanded from a macro, the columns are the ones of the line
+    /// once its parameters are substituted
+    pub span: Span,
+    pub kind: AsmErrorKind,
+    /// Macro calls the line comes from, the innermost first
+    pub expansions: Vec<Expansion>,
+}
+
+impl AsmError {
+    pub fn new(file: &str, line: usize, text: &str, span: Span, kind: AsmErrorKind) -> Self {
+        AsmError { file: file.to_string(), line, text: text.to_string(), span, kind, expansions: Vec::new() }
+    }
+
+    /// The error as the command line reports it, its line underlined
+    pub fn diagnostic(&self) -> Diagnostic<'_> {
+        Diagnostic {
+            code: Some(self.kind.code()),
+            message: self.kind.to_string(),
+            snippet: Some(Snippet { file: &self.file, line: self.line, text: &self.text, start: self.span.start, end: self.span.end }),
+            help: self.kind.help(),
+            notes: self.expansions.iter()
+                .map(|call| {
+                    let location = Location { file: call.file.clone(), line: call.line };
+                    catalog::text("expansion.at", &[("name", &call.name), ("location", &location)])
+                })
+                .collect(),
+        }
+    }
+
+    /// The file and the line the error comes from, in the sources given to the
+    /// assembler : the outermost macro call for a line expanded from a macro
+    pub fn origin(&self) -> (&str, usize) {
+        match self.expansions.last() {
+            Some(call) => (&call.file, call.line),
+            None => (&self.file, self.line),
+        }
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let column = self.text[..self.span.start].chars().count() + 1;
+        write!(f, "{}:{}:{}: {}", self.file, self.line, column, self.kind)?;
+        for call in &self.expansions {
+            write!(f, "\n{}:{}: {}", call.file, call.line, catalog::text("expansion", &[("name", &call.name)]))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for AsmError { }
+
+/// A file written by the tools that can't be read back, given as the
+/// `io::Error` of kind `InvalidData` it converts to
+#[derive(Debug, Clone, PartialEq)]
+pub enum FileError {
+    /// A line of an object, 1 based
+    ObjectLine { line: usize, text: String },
+    MissingSections,
+    RelocationOutside { section: Section, offset: u16 },
+    /// A line of a symbol file, 1 based
+    SymbolLine { line: usize, text: String },
+    OddImage,
+}
+
+impl FileError {
+    /// Stable code of the error, see `catalog`
+    pub fn code(&self) -> &'static str {
+        match self {
+            FileError::ObjectLine { .. } => "E0035",
+            FileError::MissingSections => "E0036",
+            FileError::RelocationOutside { .. } => "E0037",
+            FileError::SymbolLine { .. } => "E0038",
+            FileError::OddImage => "E0039",
+        }
+    }
+
+    /// The `FileError` an `io::Error` has been converted from
+    pub fn of(error: &io::Error) -> Option<&FileError> {
+        error.get_ref().and_then(|inner| inner.downcast_ref())
+    }
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let code = self.code();
+        let message = match self {
+            FileError::ObjectLine { line, text } | FileError::SymbolLine { line, text } => catalog::text(code, &[("line", line), ("text", text)]),
+            FileError::RelocationOutside { section, offset } => catalog::text(code, &[("section", &section.name()), ("offset", &format!("{:04x}", offset))]),
+            FileError::MissingSections | FileError::OddImage => catalog::text(code, &[]),
+        };
+
+        f.write_str(&message)
+    }
+}
+
+impl std::error::Error for FileError { }
+
+impl From<FileError> for io::Error {
+    fn from(error: FileError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, error)
+    }
+}
@@ -0,0 +1,380 @@
+//! Integer expressions, used by the operands and the directives :
+//!
+//! ```text
+//! ADD #STACK_TOP-2, R0
+//! MOVE #(WIDTH*HEIGHT), R1
+//! MOVE @BUFFER+4, R2
+//! .byte 'A' | 0x80
+//! ```
+//!
+//! From the lowest to the highest precedence : `|`, `^`, `&`, `<<` `>>`,
+//! `+` `-`, `*` `/` `%`, then the unary `-` `+` `~`. The values are decimal,
+//! `0x` hex, `0b` binary, character literals (`'A'`, `'\n'`) or the names of the
+//! labels and constants. The computation is done on 64 bits, the result is
+//! then checked against the field it goes to.
+
+use crate::ast::Node;
+use crate::directive::Section;
+use crate::error::{AsmErrorKind, ParseError, Span};
+use crate::lexer::{Lexer, TokenKind};
+use crate::parser::Context;
+
+/// What a value is relative to, the linker adds its address to the value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Base {
+    /// A plain number
+    Absolute,
+    /// An address in a section of the program
+    Section(Section),
+    /// An address relative to a symbol of another object, by its index among
+    /// the imports
+    Import(usize),
+    /// A combination of addresses that isn't an address (the sum of two
+    /// labels...), only known once the program is laid out
+    Mixed,
+}
+
+/// Result of an expression, along with what it depends on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Value {
+    pub value: i64,
+    /// Depends on the address of a label
+    pub labels: bool,
+    /// Depends on a name that isn't known yet : a label of the next lines
+    /// during the first pass, or a constant defined later
+    pub forward: bool,
+    /// Relative to the address of a section or of a symbol for an address (a
+    /// label plus or minus a number), as opposed to a number
+    pub base: Base,
+}
+
+impl Value {
+    pub fn number(value: i64) -> Value {
+        Value { value, labels: false, forward: false, base: Base::Absolute }
+    }
+
+    /// Whether the value is an address
+    pub fn relocatable(&self) -> bool {
+        matches!(self.base, Base::Section(_) | Base::Import(_))
+    }
+
+    /// Whether the value may change between the two passes of the assembler
+    pub fn deferred(&self) -> bool {
+        self.labels || self.forward
+    }
+
+    /// The value of `node` as a field of `bits` bits, see `in_range`
+    pub fn bits(&self, node: Node, bits: usize) -> Result<u16, ParseError> {
+        in_range(node.span, self.value, bits)
+    }
+}
+
+/// `value` has to fit in `bits` bits, signed or not. Negative values are
+/// returned in two's complement
+pub fn in_range(span: Span, value: i64, bits: usize) -> Result<u16, ParseError> {
+    if (-(1 << (bits - 1))..(1 << bits)).contains(&value) {
+        Ok((value & ((1 << bits) - 1)) as u16)
+    } else {
+        Err(ParseError::new(span, AsmErrorKind::ValueOutOfRange { value, bits }))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token<'a> {
+    Number(i64),
+    Name(&'a str),
+    Operator(&'a str),
+    Open,
+    Close,
+}
+
+const OPERATORS: [&str; 11] = ["<<", ">>", "+", "-", "*", "/", "%", "&", "|", "^", "~"];
+
+/// Binary operators, from the lowest precedence to the highest
+const PRECEDENCE: [&[&str]; 6] = [&["|"], &["^"], &["&"], &["<<", ">>"], &["+", "-"], &["*", "/", "%"]];
+
+fn bad(node: Node) -> ParseError {
+    ParseError::new(node.span, AsmErrorKind::BadOperand(node.text.to_string()))
+}
+
+/// Value of the number `token`, with an optional `0x`/`0b` prefix
+fn number(node: Node) -> Result<i64, ParseError> {
+    let token = node.text;
+    let (digits, radix) = if let Some(hex) = token.strip_prefix("0x").or(token.strip_prefix("0X")) {
+        (hex, 16)
+    } else if let Some(binary) = token.strip_prefix("0b").or(token.strip_prefix("0B")) {
+        (binary, 2)
+    } else {
+        (token, 10)
+    };
+
+    match i64::from_str_radix(digits, radix) {
+        Ok(value) => Ok(value),
+        // The digits are right, the number is too big
+        Err(_) if !digits.is_empty() && digits.chars().all(|c| c.is_digit(radix)) => {
+            Err(ParseError::new(node.span, AsmErrorKind::Overflow))
+        },
+        Err(_) => Err(bad(node)),
+    }
+}
+
+/// Value of the character literal `node`, quotes included
+fn character(node: Node) -> Result<i64, ParseError> {
+    let content = &node.text[1..node.text.len() - 1];
+    let mut chars = content.chars();
+
+    let c = match (chars.next(), chars.next(), chars.next()) {
+        (Some('\\'), Some(escaped), None) => match escaped {
+            'n' => '\n',
+            't' => '\t',
+            '0' => '\0',
+            '\\' | '\'' | '"' => escaped,
+            _ => return Err(bad(node)),
+        },
+        (Some(c), None, None) if c != '\\' => c,
+        _ => return Err(bad(node)),
+    };
+
+    Ok(c as i64)
+}
+
+/// Whether `token` is a `1b`/`1f` reference to a numeric label, `0b1f` is a number
+fn is_numeric_reference(token: &str) -> bool {
+    token.len() > 1
+        && token.ends_with(['b', 'f', 'B', 'F'])
+        && token[..token.len() - 1].bytes().all(|c| c.is_ascii_digit())
+}
+
+/// Split `expression` into its tokens, each one along with where it is in the line
+fn tokenize(expression: Node<'_>) -> Result<Vec<(Token<'_>, Node<'_>)>, ParseError> {
+    let offset = expression.span.start;
+
+    Lexer::new(expression.text)
+        .map(|token| {
+            let node = Node { text: token.text, span: Span { start: offset + token.span.start, end: offset + token.span.end } };
+            let kind = match token.kind {
+                TokenKind::Open => Token::Open,
+                TokenKind::Close => Token::Close,
+                // `1b` and `1f` refer to the numeric labels
+                TokenKind::Number if is_numeric_reference(token.text) => Token::Name(token.text),
+                TokenKind::Number => Token::Number(number(node)?),
+                TokenKind::Char => Token::Number(character(node)?),
+                // `.` joins the local labels to their scope (`ROUTINE.loop`)
+                TokenKind::Identifier => Token::Name(token.text),
+                TokenKind::Operator if OPERATORS.contains(&token.text) => Token::Operator(token.text),
+                _ => return Err(bad(node)),
+            };
+            Ok((kind, node))
+        })
+        .collect()
+}
+
+/// Recursive descent over the tokens of an expression
+struct Parser<'a, 'c> {
+    expression: Node<'a>,
+    tokens: Vec<(Token<'a>, Node<'a>)>,
+    position: usize,
+    context: &'c Context<'c>,
+}
+
+impl<'a> Parser<'a, '_> {
+    /// The next token, if it is one of `operators`
+    fn operator(&self, operators: &[&str]) -> Option<Node<'a>> {
+        match self.tokens.get(self.position) {
+            Some((Token::Operator(operator), node)) if operators.contains(operator) => Some(*node),
+            _ => None,
+        }
+    }
+
+    /// The token the parser stopped on, the end of the expression when there is none
+    fn here(&self) -> Node<'a> {
+        match self.tokens.get(self.position) {
+            Some((_, node)) => *node,
+            None => Node { text: "", span: Span::at(self.expression.span.end) },
+        }
+    }
+
+    /// The next token if it is a binary operator, along with its precedence
+    fn binary_operator(&self) -> Option<(Node<'a>, usize)> {
+        match self.tokens.get(self.position) {
+            Some((Token::Operator(operator), node)) => PRECEDENCE.iter()
+                .position(|operators| operators.contains(operator))
+                .map(|level| (*node, level)),
+            _ => None,
+        }
+    }
+
+    /// The operations whose operators have at least the precedence `level`,
+    /// from left to right
+    fn binary(&mut self, level: usize) -> Result<Value, ParseError> {
+        let mut left = self.unary()?;
+        while let Some((operator, precedence)) = self.binary_operator().filter(|(_, precedence)| *precedence >= level) {
+            self.position += 1;
+            let right = self.binary(precedence + 1)?;
+            left = apply(operator, left, right)?;
+        }
+
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Value, ParseError> {
+        match self.operator(&["-", "+", "~"]) {
+            Some(operator) => {
+                self.position += 1;
+                let operand = self.unary()?;
+                let value = match operator.text {
+                    "-" => operand.value.checked_neg().ok_or(ParseError::new(operator.span, AsmErrorKind::Overflow))?,
+                    "~" => !operand.value,
+                    _ => operand.value,
+                };
+
+                let base = match operand.base {
+                    _ if operator.text == "+" => operand.base,
+                    Base::Absolute => Base::Absolute,
+                    _ => Base::Mixed,
+                };
+                Ok(Value { value, base, ..operand })
+            },
+            None => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Value, ParseError> {
+        let Some(&(kind, node)) = self.tokens.get(self.position) else {
+            // Nothing left where an operand is expected
+            return Err(bad(self.expression));
+        };
+        self.position += 1;
+
+        match kind {
+            Token::Number(value) => Ok(Value::number(value)),
+            Token::Name(_) => self.context.lookup(node),
+            Token::Open => {
+                let value = self.binary(0)?;
+                match self.tokens.get(self.position) {
+                    Some((Token::Close, _)) => {
+                        self.position += 1;
+                        Ok(value)
+                    },
+                    _ => Err(bad(self.here())),
+                }
+            },
+            Token::Close | Token::Operator(_) => Err(bad(node)),
+        }
+    }
+}
+
+/// `left operator right`, an address stays an address when a number is added to it
+fn apply(operator: Node, left: Value, right: Value) -> Result<Value, ParseError> {
+    let (a, b) = (left.value, right.value);
+
+    if matches!(operator.text, "/" | "%") && b == 0 {
+        return Err(ParseError::new(operator.span, AsmErrorKind::DivisionByZero));
+    }
+
+    let value = match operator.text {
+        "+" => a.checked_add(b),
+        "-" => a.checked_sub(b),
+        "*" => a.checked_mul(b),
+        "/" => a.checked_div(b),
+        "%" => a.checked_rem(b),
+        "<<" => u32::try_from(b).ok().and_then(|shift| a.checked_shl(shift)),
+        ">>" => u32::try_from(b).ok().and_then(|shift| a.checked_shr(shift)),
+        "&" => Some(a & b),
+        "|" => Some(a | b),
+        "^" => Some(a ^ b),
+        _ => unreachable!("{} isn't a binary operator", operator.text),
+    };
+
+    let base = match (operator.text, left.base, right.base) {
+        (_, Base::Absolute, Base::Absolute) => Base::Absolute,
+        ("+" | "-", base, Base::Absolute) => base,
+        ("+", Base::Absolute, base) => base,
+        // The distance between two labels of a section is a number
+        ("-", Base::Section(left), Base::Section(right)) if left == right => Base::Absolute,
+        _ => Base::Mixed,
+    };
+
+    Ok(Value {
+        value: value.ok_or(ParseError::new(operator.span, AsmErrorKind::Overflow))?,
+        labels: left.labels || right.labels,
+        forward: left.forward || right.forward,
+        base,
+    })
+}
+
+/// Evaluate `expression`, the names are resolved through the context
+pub fn evaluate(expression: Node, context: &Context) -> Result<Value, ParseError> {
+    let mut parser = Parser { expression, tokens: tokenize(expression)?, position: 0, context };
+
+    let value = parser.binary(0)?;
+    match parser.tokens.get(parser.position) {
+        Some((_, node)) => Err(bad(*node)),
+        None => Ok(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{Constants, Symbols};
+
+    fn eval(text: &str) -> Result<i64, AsmErrorKind> {
+        let mut symbols = Symbols::default();
+        symbols.define("BUFFER", None, 0, Section::Data, 0x100).unwrap();
+        let constants = Constants::new();
+        let context = Context::new(&symbols, &constants);
+
+        evaluate(Node::whole(text), &context)
+            .map(|value| value.value)
+            .map_err(|error| error.kind)
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("1 + 2 * 3"), Ok(7));
+        assert_eq!(eval("(1 + 2) * 3"), Ok(9));
+        assert_eq!(eval("1 << 2 + 1"), Ok(8));
+        assert_eq!(eval("0xF0 | 0x0F & 0x3C ^ 1"), Ok(0xFD));
+        assert_eq!(eval("-2 - -3"), Ok(1));
+        assert_eq!(eval("~0 & 0xFF"), Ok(0xFF));
+        assert_eq!(eval("17 % 5 / 2"), Ok(1));
+        assert_eq!(eval("0b101 >> 1"), Ok(2));
+    }
+
+    #[test]
+    fn operands() {
+        assert_eq!(eval("'A' + 1"), Ok(66));
+        assert_eq!(eval("'\\n'"), Ok(10));
+        assert_eq!(eval("'\\''"), Ok(39));
+        assert_eq!(eval("BUFFER+4"), Ok(0x104));
+        assert_eq!(eval("NOWHERE"), Err(AsmErrorKind::UndefinedLabel("NOWHERE".to_string())));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(eval("1 / (2 - 2)"), Err(AsmErrorKind::DivisionByZero));
+        assert_eq!(eval("1 << 64"), Err(AsmErrorKind::Overflow));
+        assert_eq!(eval("99999999999999999999"), Err(AsmErrorKind::Overflow));
+        assert_eq!(eval("(1 + 2"), Err(AsmErrorKind::BadOperand("".to_string())));
+        assert_eq!(eval("1 +"), Err(AsmErrorKind::BadOperand("1 +".to_string())));
+        assert_eq!(eval("1 2"), Err(AsmErrorKind::BadOperand("2".to_string())));
+        assert_eq!(eval("1 ? 2"), Err(AsmErrorKind::BadOperand("?".to_string())));
+        assert_eq!(eval("'AB'"), Err(AsmErrorKind::BadOperand("'AB'".to_string())));
+    }
+
+    #[test]
+    fn addresses() {
+        let mut symbols = Symbols::default();
+        symbols.define("START", None, 0, Section::Text, 0x10).unwrap();
+        symbols.define("END", None, 1, Section::Text, 0x20).unwrap();
+        let constants = Constants::new();
+        let context = Context::new(&symbols, &constants);
+
+        assert!(evaluate(Node::whole("START+2"), &context).unwrap().relocatable());
+        assert_eq!(evaluate(Node::whole("END-START"), &context).unwrap().base, Base::Absolute);
+        assert_eq!(evaluate(Node::whole("END+START"), &context).unwrap().base, Base::Mixed);
+        assert!(evaluate(Node::whole("END-START"), &context).unwrap().deferred());
+        assert!(!evaluate(Node::whole("4*2"), &context).unwrap().deferred());
+    }
+}
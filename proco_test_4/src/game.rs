@@ -0,0 +1,26 @@
+//! Memory map of the machine and how a program gets booted.
+//!
+//! ```text
+//! 0x0000 - 0x000F | interrupt vectors, each one holds the address of its handler
+//! 0x0010 - ...    | program, the CPU starts executing at RESET_ADDR
+//! ```
+
+use crate::cpu::{Cpu, PC};
+use crate::memory::Memory;
+
+/// Address of the first instruction executed after a reset
+pub const RESET_ADDR: u16 = 0x10;
+
+/// Vector used when the CPU fetches something it can't decode or execute
+pub const ILLEGAL_INSTRUCTION_VECTOR: u16 = 0x0E;
+
+/// Load a program at `RESET_ADDR` and return a CPU ready to execute it
+pub fn boot(program: &[u16]) -> (Cpu, Memory) {
+    let mut memory = Memory::new();
+    memory.load(RESET_ADDR, program);
+
+    let mut cpu = Cpu::new();
+    cpu.registers[PC] = RESET_ADDR;
+
+    (cpu, memory)
+}
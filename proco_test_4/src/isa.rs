@@ -0,0 +1,408 @@
+//! Description of the instruction set, the single source of truth for the
+//! parser (mnemonics, operand counts and legal addressing modes), the decoder
+//! (format of an opcode) and the generated reference (`cargo run --bin isadoc`).
+
+use core::fmt;
+
+/// How the instruction is packed, see `encoder`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Op0,
+    Op1,
+    Op2,
+    Move,
+}
+
+/// Set of addressing modes, one bit per mode as encoded in the op_type fields
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Modes(u8);
+
+pub const REGISTER: Modes = Modes(1 << 0b000);
+pub const PRE_DECREMENT: Modes = Modes(1 << 0b001);
+pub const INDIRECT: Modes = Modes(1 << 0b010);
+pub const POST_INCREMENT: Modes = Modes(1 << 0b011);
+pub const IMMEDIATE: Modes = Modes(1 << 0b100);
+pub const ADDRESS: Modes = Modes(1 << 0b101);
+
+/// Every mode but the immediate one
+pub const WRITABLE: Modes = REGISTER.with(PRE_DECREMENT).with(INDIRECT).with(POST_INCREMENT).with(ADDRESS);
+pub const ANY: Modes = WRITABLE.with(IMMEDIATE);
+
+const MODE_NAMES: [&str; 6] = ["Rn", "-(Rn)", "(Rn)", "(Rn)+", "#x", "@x"];
+
+impl Modes {
+    pub const fn with(self, other: Modes) -> Modes {
+        Modes(self.0 | other.0)
+    }
+
+    pub const fn without(self, other: Modes) -> Modes {
+        Modes(self.0 & !other.0)
+    }
+
+    /// Whether the mode `mode` (0b000 to 0b101) is part of the set
+    pub fn contains(self, mode: u16) -> bool {
+        mode < 8 && self.0 & (1 << mode) != 0
+    }
+}
+
+impl fmt::Display for Modes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<&str> = MODE_NAMES.iter()
+            .enumerate()
+            .filter(|(mode, _)| self.contains(*mode as u16))
+            .map(|(_, name)| *name)
+            .collect();
+
+        write!(f, "{}", names.join(", "))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OperandSpec {
+    /// What the operand is used for ("source", "destination"...), used by the errors
+    pub role: &'static str,
+    pub modes: Modes,
+}
+
+/// What an instruction does to a flag
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Effect {
+    Unchanged,
+    Cleared,
+    /// Set from the result (or restored, for RTE)
+    Updated,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Flags {
+    pub c: Effect,
+    pub z: Effect,
+    pub n: Effect,
+}
+
+/// When a branch or a jump is taken
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Condition {
+    CarryClear,
+    CarrySet,
+    Equal,
+    NotEqual,
+    LessOrEqual,
+    GreaterOrEqual,
+    Always,
+    /// Always, the return address being pushed first
+    Subroutine,
+}
+
+/// What an instruction does to the flow of the program
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Plain,
+    /// Its operand is a displacement from the next instruction
+    Branch(Condition),
+    /// Its operand is an address
+    Jump(Condition),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Instruction {
+    pub mnemonic: &'static str,
+    pub opcode: u16,
+    pub format: Format,
+    pub kind: Kind,
+    pub operands: &'static [OperandSpec],
+    pub flags: Flags,
+    pub summary: &'static str,
+}
+
+const NONE: &[OperandSpec] = &[];
+const SOURCE: &[OperandSpec] = &[OperandSpec { role: "source", modes: ANY }];
+const DESTINATION: &[OperandSpec] = &[OperandSpec { role: "destination", modes: WRITABLE }];
+const TARGET: &[OperandSpec] = &[OperandSpec { role: "target", modes: ANY }];
+const VECTOR: &[OperandSpec] = &[OperandSpec { role: "vector", modes: ANY }];
+const TWO_OPERANDS: &[OperandSpec] = &[
+    OperandSpec { role: "source", modes: ANY },
+    OperandSpec { role: "destination", modes: REGISTER },
+];
+const MOVE_OPERANDS: &[OperandSpec] = &[
+    OperandSpec { role: "source", modes: ANY },
+    OperandSpec { role: "destination", modes: WRITABLE },
+];
+
+const UNCHANGED: Flags = Flags { c: Effect::Unchanged, z: Effect::Unchanged, n: Effect::Unchanged };
+const LOGIC: Flags = Flags { c: Effect::Cleared, z: Effect::Updated, n: Effect::Updated };
+const ARITHMETIC: Flags = Flags { c: Effect::Updated, z: Effect::Updated, n: Effect::Updated };
+
+/// The whole instruction set. When several mnemonics share an opcode (BCC and
+/// BGT...) the first one is the canonical one, used by the disassembler
+pub static ISA: &[Instruction] = &[
+    Instruction { mnemonic: "MOVE", opcode: 0x00, format: Format::Move, kind: Kind::Plain, operands: MOVE_OPERANDS, flags: LOGIC, summary: "D = S (MOVE.L/MOVE.H: low/high byte of S)" },
+    Instruction { mnemonic: "PUSH", opcode: 0x01, format: Format::Op1, kind: Kind::Plain, operands: SOURCE, flags: LOGIC, summary: "push S on the stack" },
+    Instruction { mnemonic: "POP", opcode: 0x02, format: Format::Op1, kind: Kind::Plain, operands: DESTINATION, flags: LOGIC, summary: "pop D from the stack" },
+    Instruction { mnemonic: "ADD", opcode: 0x03, format: Format::Op2, kind: Kind::Plain, operands: TWO_OPERANDS, flags: ARITHMETIC, summary: "D = D + S" },
+    Instruction { mnemonic: "CMP", opcode: 0x04, format: Format::Op2, kind: Kind::Plain, operands: TWO_OPERANDS, flags: ARITHMETIC, summary: "D - S, only the flags are kept" },
+    Instruction { mnemonic: "SUB", opcode: 0x05, format: Format::Op2, kind: Kind::Plain, operands: TWO_OPERANDS, flags: ARITHMETIC, summary: "D = D - S" },
+    Instruction { mnemonic: "LSL", opcode: 0x06, format: Format::Op2, kind: Kind::Plain, operands: TWO_OPERANDS, flags: ARITHMETIC, summary: "D = D << S, C is the last bit shifted out" },
+    Instruction { mnemonic: "LSR", opcode: 0x07, format: Format::Op2, kind: Kind::Plain, operands: TWO_OPERANDS, flags: ARITHMETIC, summary: "D = D >> S, C is the last bit shifted out" },
+    Instruction { mnemonic: "AND", opcode: 0x08, format: Format::Op2, kind: Kind::Plain, operands: TWO_OPERANDS, flags: LOGIC, summary: "D = D & S" },
+    Instruction { mnemonic: "OR", opcode: 0x09, format: Format::Op2, kind: Kind::Plain, operands: TWO_OPERANDS, flags: LOGIC, summary: "D = D | S" },
+    Instruction { mnemonic: "XOR", opcode: 0x0A, format: Format::Op2, kind: Kind::Plain, operands: TWO_OPERANDS, flags: LOGIC, summary: "D = D ^ S" },
+    Instruction { mnemonic: "NOT", opcode: 0x0B, format: Format::Op1, kind: Kind::Plain, operands: DESTINATION, flags: LOGIC, summary: "D = !D" },
+    Instruction { mnemonic: "BCC", opcode: 0x0C, format: Format::Op1, kind: Kind::Branch(Condition::CarryClear), operands: TARGET, flags: UNCHANGED, summary: "branch if C is clear" },
+    Instruction { mnemonic: "BGT", opcode: 0x0C, format: Format::Op1, kind: Kind::Branch(Condition::CarryClear), operands: TARGET, flags: UNCHANGED, summary: "branch if greater than (alias of BCC)" },
+    Instruction { mnemonic: "BCS", opcode: 0x0D, format: Format::Op1, kind: Kind::Branch(Condition::CarrySet), operands: TARGET, flags: UNCHANGED, summary: "branch if C is set" },
+    Instruction { mnemonic: "BLT", opcode: 0x0D, format: Format::Op1, kind: Kind::Branch(Condition::CarrySet), operands: TARGET, flags: UNCHANGED, summary: "branch if less than (alias of BCS)" },
+    Instruction { mnemonic: "BEQ", opcode: 0x0E, format: Format::Op1, kind: Kind::Branch(Condition::Equal), operands: TARGET, flags: UNCHANGED, summary: "branch if equal" },
+    Instruction { mnemonic: "BNE", opcode: 0x0F, format: Format::Op1, kind: Kind::Branch(Condition::NotEqual), operands: TARGET, flags: UNCHANGED, summary: "branch if not equal" },
+    Instruction { mnemonic: "BLE", opcode: 0x10, format: Format::Op1, kind: Kind::Branch(Condition::LessOrEqual), operands: TARGET, flags: UNCHANGED, summary: "branch if less or equal" },
+    Instruction { mnemonic: "BGE", opcode: 0x11, format: Format::Op1, kind: Kind::Branch(Condition::GreaterOrEqual), operands: TARGET, flags: UNCHANGED, summary: "branch if greater or equal" },
+    Instruction { mnemonic: "BRA", opcode: 0x12, format: Format::Op1, kind: Kind::Branch(Condition::Always), operands: TARGET, flags: UNCHANGED, summary: "branch always" },
+    Instruction { mnemonic: "BSR", opcode: 0x13, format: Format::Op1, kind: Kind::Branch(Condition::Subroutine), operands: TARGET, flags: UNCHANGED, summary: "branch to a subroutine" },
+    Instruction { mnemonic: "JCC", opcode: 0x14, format: Format::Op1, kind: Kind::Jump(Condition::CarryClear), operands: TARGET, flags: UNCHANGED, summary: "jump if C is clear" },
+    Instruction { mnemonic: "JGT", opcode: 0x14, format: Format::Op1, kind: Kind::Jump(Condition::CarryClear), operands: TARGET, flags: UNCHANGED, summary: "jump if greater than (alias of JCC)" },
+    Instruction { mnemonic: "JCS", opcode: 0x15, format: Format::Op1, kind: Kind::Jump(Condition::CarrySet), operands: TARGET, flags: UNCHANGED, summary: "jump if C is set" },
+    Instruction { mnemonic: "JLT", opcode: 0x15, format: Format::Op1, kind: Kind::Jump(Condition::CarrySet), operands: TARGET, flags: UNCHANGED, summary: "jump if less than (alias of JCS)" },
+    Instruction { mnemonic: "JEQ", opcode: 0x16, format: Format::Op1, kind: Kind::Jump(Condition::Equal), operands: TARGET, flags: UNCHANGED, summary: "jump if equal" },
+    Instruction { mnemonic: "JNE", opcode: 0x17, format: Format::Op1, kind: Kind::Jump(Condition::NotEqual), operands: TARGET, flags: UNCHANGED, summary: "jump if not equal" },
+    Instruction { mnemonic: "JLE", opcode: 0x18, format: Format::Op1, kind: Kind::Jump(Condition::LessOrEqual), operands: TARGET, flags: UNCHANGED, summary: "jump if less or equal" },
+    Instruction { mnemonic: "JGE", opcode: 0x19, format: Format::Op1, kind: Kind::Jump(Condition::GreaterOrEqual), operands: TARGET, flags: UNCHANGED, summary: "jump if greater or equal" },
+    Instruction { mnemonic: "JMP", opcode: 0x1A, format: Format::Op1, kind: Kind::Jump(Condition::Always), operands: TARGET, flags: UNCHANGED, summary: "jump always" },
+    Instruction { mnemonic: "JSR", opcode: 0x1B, format: Format::Op1, kind: Kind::Jump(Condition::Subroutine), operands: TARGET, flags: UNCHANGED, summary: "jump to a subroutine" },
+    Instruction { mnemonic: "RTS", opcode: 0x1C, format: Format::Op0, kind: Kind::Plain, operands: NONE, flags: UNCHANGED, summary: "return from a subroutine" },
+    Instruction { mnemonic: "TRAP", opcode: 0x1D, format: Format::Op1, kind: Kind::Plain, operands: VECTOR, flags: UNCHANGED, summary: "software interrupt through the vector" },
+    Instruction { mnemonic: "RTE", opcode: 0x1E, format: Format::Op0, kind: Kind::Plain, operands: NONE, flags: ARITHMETIC, summary: "return from an interrupt, the flags are restored" },
+];
+
+pub fn lookup(mnemonic: &str) -> Option<&'static Instruction> {
+    ISA.iter().find(|instruction| instruction.mnemonic == mnemonic)
+}
+
+/// An instruction of the assembler only, replaced by the instructions of its
+/// body. `\1` and `\2` stand for its operands, the body is written the way
+/// the disassembler writes its instructions so that it can be folded back
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pseudo {
+    pub mnemonic: &'static str,
+    /// The shape of each operand
+    pub operands: &'static [&'static str],
+    pub body: &'static [&'static str],
+    pub summary: &'static str,
+}
+
+pub static PSEUDO: &[Pseudo] = &[
+    Pseudo { mnemonic: "CLR", operands: &["\\1"], body: &["XOR \\1, \\1"], summary: "A = 0" },
+    Pseudo { mnemonic: "INC", operands: &["\\1"], body: &["ADD #0x1, \\1"], summary: "A = A + 1" },
+    Pseudo { mnemonic: "DEC", operands: &["\\1"], body: &["SUB #0x1, \\1"], summary: "A = A - 1" },
+    Pseudo { mnemonic: "NEG", operands: &["\\1"], body: &["NOT \\1", "ADD #0x1, \\1"], summary: "A = -A" },
+    Pseudo { mnemonic: "TST", operands: &["\\1"], body: &["CMP #0x0, \\1"], summary: "A - 0, only the flags are kept" },
+    Pseudo { mnemonic: "CALL", operands: &["\\1"], body: &["JSR #\\1"], summary: "jump to the subroutine A" },
+    Pseudo { mnemonic: "RET", operands: &[], body: &["RTS"], summary: "return from a subroutine" },
+    Pseudo { mnemonic: "SWAP", operands: &["\\1", "\\2"], body: &["PUSH \\1", "MOVE \\2, \\1", "POP \\2"], summary: "exchange A and B through the stack" },
+    Pseudo { mnemonic: "LI", operands: &["\\1", "#\\2"], body: &["MOVE #\\2, \\1"], summary: "A = B, any 16 bits value" },
+];
+
+pub fn pseudo(mnemonic: &str) -> Option<&'static Pseudo> {
+    PSEUDO.iter().find(|pseudo| pseudo.mnemonic == mnemonic)
+}
+
+/// Match `text` against `pattern`, each `\n` of the pattern taking the text up
+/// to the next literal part. A `\n` already captured has to match the same text
+fn capture<'t>(pattern: &str, text: &'t str, captures: &mut [Option<&'t str>; 2]) -> bool {
+    let mut pattern = pattern;
+    let mut text = text;
+
+    while !pattern.is_empty() {
+        let Some(literal) = pattern.strip_prefix('\\') else {
+            let end = pattern.find('\\').unwrap_or(pattern.len());
+            match text.strip_prefix(&pattern[..end]) {
+                Some(rest) => (pattern, text) = (&pattern[end..], rest),
+                None => return false,
+            }
+            continue;
+        };
+
+        let index = usize::from(literal.as_bytes()[0] - b'1');
+        pattern = &literal[1..];
+        let end = match pattern.find('\\').map_or(pattern, |end| &pattern[..end]) {
+            "" => text.len(),
+            next => match text.find(next) {
+                Some(end) => end,
+                None => return false,
+            },
+        };
+
+        let (captured, rest) = text.split_at(end);
+        if captured.is_empty() || captures[index].is_some_and(|previous| previous != captured) {
+            return false;
+        }
+        captures[index] = Some(captured);
+        text = rest;
+    }
+
+    text.is_empty()
+}
+
+/// `template` with its `\n` replaced by the captures
+fn fill(template: &str, captures: &[Option<&str>; 2]) -> String {
+    let mut result = template.to_string();
+    for (i, captured) in captures.iter().enumerate() {
+        if let Some(captured) = captured {
+            result = result.replace(&format!("\\{}", i + 1), captured);
+        }
+    }
+
+    result
+}
+
+impl Pseudo {
+    /// The instructions replacing the pseudo-instruction, or the index of the
+    /// first operand without the expected shape
+    pub fn expand(&self, operands: &[&str]) -> Result<Vec<String>, usize> {
+        let mut captures = [None; 2];
+        for (i, (shape, operand)) in self.operands.iter().zip(operands).enumerate() {
+            if !capture(shape, operand.trim(), &mut captures) {
+                return Err(i);
+            }
+        }
+
+        Ok(self.body.iter().map(|line| fill(line, &captures)).collect())
+    }
+
+    /// The pseudo-instruction whose body is `lines`, written as the disassembler does
+    pub fn fold(&self, lines: &[String]) -> Option<String> {
+        if lines.len() != self.body.len() {
+            return None;
+        }
+
+        let mut captures = [None; 2];
+        if !self.body.iter().zip(lines).all(|(line, text)| capture(line, text, &mut captures)) {
+            return None;
+        }
+
+        let operands: Vec<String> = self.operands.iter().map(|shape| fill(shape, &captures)).collect();
+        Some(format!("{} {}", self.mnemonic, operands.join(", ")).trim_end().to_string())
+    }
+}
+
+/// Number of characters to insert, remove or replace to turn `a` into `b`
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, c) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, d) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(c != *d);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+/// The mnemonic closest to the unknown `mnemonic`, when one is close enough
+pub fn suggest(mnemonic: &str) -> Option<&'static str> {
+    let mnemonic = mnemonic.to_ascii_uppercase();
+
+    ISA.iter()
+        .map(|instruction| (distance(&mnemonic, instruction.mnemonic), instruction.mnemonic))
+        .filter(|(distance, _)| *distance <= 1 || (*distance == 2 && mnemonic.len() > 3))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, mnemonic)| mnemonic)
+}
+
+/// Canonical instruction of an opcode
+pub fn by_opcode(opcode: u16) -> Option<&'static Instruction> {
+    ISA.iter().find(|instruction| instruction.opcode == opcode)
+}
+
+fn effect(flag: &str, effect: Effect) -> &str {
+    match effect {
+        Effect::Unchanged => "-",
+        Effect::Cleared => "0",
+        Effect::Updated => flag,
+    }
+}
+
+/// The instruction set reference, as markdown
+pub fn reference() -> String {
+    let mut doc = String::from("# Instruction set\n\n");
+    doc.push_str("Generated from `src/isa.rs` by `cargo run --bin isadoc`.\n\n");
+    doc.push_str("| Mnemonic | Opcode | Format | Operands | C | Z | N | Operation |\n");
+    doc.push_str("|----------|--------|--------|----------|---|---|---|-----------|\n");
+
+    for instruction in ISA {
+        let operands: Vec<String> = instruction.operands.iter()
+            .map(|operand| format!("{}: {}", operand.role, operand.modes))
+            .collect();
+
+        doc.push_str(&format!(
+            "| {} | 0x{:02X} | {:?} | {} | {} | {} | {} | {} |\n",
+            instruction.mnemonic,
+            instruction.opcode,
+            instruction.format,
+            operands.join("; "),
+            effect("C", instruction.flags.c),
+            effect("Z", instruction.flags.z),
+            effect("N", instruction.flags.n),
+            instruction.summary.replace('|', "\\|"),
+        ));
+    }
+
+    doc.push_str("\nMOVE can't have both an immediate/address source and an address destination.\n");
+
+    doc.push_str("\n## Pseudo-instructions\n\n");
+    doc.push_str("Replaced by the assembler with the instructions of their expansion, whose modes their operands follow. `disasm --fold` folds them back.\n\n");
+    doc.push_str("| Mnemonic | Operands | Expansion | Operation |\n");
+    doc.push_str("|----------|----------|-----------|-----------|\n");
+
+    for pseudo in PSEUDO {
+        let names = [Some("A"), Some("B")];
+        let operands: Vec<String> = pseudo.operands.iter().map(|shape| fill(shape, &names)).collect();
+        let body: Vec<String> = pseudo.body.iter().map(|line| format!("`{}`", fill(line, &names))).collect();
+
+        doc.push_str(&format!("| {} | {} | {} | {} |\n", pseudo.mnemonic, operands.join(", "), body.join("; "), pseudo.summary));
+    }
+
+    doc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reference_is_up_to_date() {
+        let written = include_str!("../ISA.md");
+        assert_eq!(written, reference(), "ISA.md is outdated, run `cargo run --bin isadoc > ISA.md`");
+    }
+
+    #[test]
+    fn pseudo_instructions() {
+        let li = pseudo("LI").unwrap();
+        assert_eq!(li.expand(&["R0", " #0x1234"]), Ok(vec!["MOVE #0x1234, R0".to_string()]));
+        assert_eq!(li.expand(&["R0", "0x1234"]), Err(1));
+        assert_eq!(li.fold(&["MOVE #0x1234, R0".to_string()]), Some("LI R0, #0x1234".to_string()));
+        assert_eq!(li.fold(&["MOVE R1, R0".to_string()]), None);
+
+        let clr = pseudo("CLR").unwrap();
+        assert_eq!(clr.fold(&["XOR R1, R1".to_string()]), Some("CLR R1".to_string()));
+        assert_eq!(clr.fold(&["XOR R1, R2".to_string()]), None);
+
+        let swap = pseudo("SWAP").unwrap();
+        let body = swap.expand(&["R1", "@0x10"]).unwrap();
+        assert_eq!(body, ["PUSH R1", "MOVE @0x10, R1", "POP @0x10"]);
+        assert_eq!(swap.fold(&body), Some("SWAP R1, @0x10".to_string()));
+        assert_eq!(pseudo("RET").unwrap().fold(&["RTS".to_string()]), Some("RET".to_string()));
+    }
+
+    #[test]
+    fn aliases_come_after_their_canonical_mnemonic() {
+        assert_eq!(by_opcode(0x0C).unwrap().mnemonic, "BCC");
+        assert_eq!(by_opcode(0x15).unwrap().mnemonic, "JCS");
+        assert_eq!(lookup("BGT").unwrap().opcode, 0x0C);
+        assert_eq!(lookup("BGT").unwrap().kind, Kind::Branch(Condition::CarryClear));
+        assert_eq!(lookup("JLT").unwrap().kind, Kind::Jump(Condition::CarrySet));
+    }
+}
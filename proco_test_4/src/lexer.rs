@@ -0,0 +1,158 @@
+//! Tokens of a line of assembly, the `ast` and the expressions are built from
+//! them.
+//!
+//! ```text
+//! LOOP:  MOVE.L #'A' + 1, (R0)+ ; comment
+//! ^^^^^  ^^^^^^ ^^^ ^ ^ ^ ^^ ^^ ^^^^^^^^^
+//! |   Colon     | Char  | | |Close       Comment
+//! Identifier    Hash    | | Identifier
+//!        Identifier     | Comma
+//!                       Operator, Number, Open...
+//! ```
+//!
+//! The lexer never fails : a character it doesn't know is an `Unknown` token,
+//! the parser reports it along with what it belongs to. A string or a
+//! character literal without its closing quote goes up to the end of the line.
+
+use crate::error::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    /// A name, a mnemonic, a register or a directive : letters, digits, `_`
+    /// and `.` (`ROUTINE.loop`, `MOVE.L`, `.word`)
+    Identifier,
+    /// Starts with a digit (`12`, `0x1F`, `0b101`, `1b`)
+    Number,
+    /// `"..."`, quotes included
+    String,
+    /// `'A'`, quotes included
+    Char,
+    Comma,
+    Colon,
+    Hash,
+    At,
+    Open,
+    Close,
+    /// `+ - * / % & | ^ ~ << >> =`
+    Operator,
+    /// From a `;` to the end of the line
+    Comment,
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Token<'a> {
+    pub kind: TokenKind,
+    pub text: &'a str,
+    /// Where the token is in the line
+    pub span: Span,
+}
+
+/// The tokens of a line, the spaces between them are skipped
+pub struct Lexer<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(text: &'a str) -> Self {
+        Lexer { text, position: 0 }
+    }
+}
+
+fn is_word(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_' || c == b'.'
+}
+
+/// Length of the literal at the start of `rest` up to its closing `quote`,
+/// an escaped one doesn't count. `None` when it isn't closed
+fn quoted(rest: &[u8], quote: u8) -> Option<usize> {
+    let mut escaped = false;
+
+    for (i, &c) in rest.iter().enumerate().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            b'\\' => escaped = true,
+            _ if c == quote => return Some(i + 1),
+            _ => { },
+        }
+    }
+
+    None
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        let bytes = self.text.as_bytes();
+        while self.position < bytes.len() && bytes[self.position].is_ascii_whitespace() {
+            self.position += 1;
+        }
+
+        let start = self.position;
+        let rest = &bytes[start..];
+        let &c = rest.first()?;
+        let word = || rest.iter().position(|&c| !is_word(c)).unwrap_or(rest.len());
+
+        let (kind, length) = match c {
+            b'0'..=b'9' => (TokenKind::Number, word()),
+            _ if c.is_ascii_alphabetic() || c == b'_' || c == b'.' => (TokenKind::Identifier, word()),
+            b'"' => (TokenKind::String, quoted(rest, c).unwrap_or(rest.len())),
+            b'\'' => match quoted(rest, c) {
+                Some(length) => (TokenKind::Char, length),
+                None => (TokenKind::Unknown, rest.len()),
+            },
+            b';' => (TokenKind::Comment, rest.len()),
+            b',' => (TokenKind::Comma, 1),
+            b':' => (TokenKind::Colon, 1),
+            b'#' => (TokenKind::Hash, 1),
+            b'@' => (TokenKind::At, 1),
+            b'(' => (TokenKind::Open, 1),
+            b')' => (TokenKind::Close, 1),
+            b'<' | b'>' if rest.get(1) == Some(&c) => (TokenKind::Operator, 2),
+            b'+' | b'-' | b'*' | b'/' | b'%' | b'&' | b'|' | b'^' | b'~' | b'=' => (TokenKind::Operator, 1),
+            // A whole character, not a byte of it
+            _ => (TokenKind::Unknown, self.text[start..].chars().next().unwrap().len_utf8()),
+        };
+
+        self.position += length;
+        Some(Token { kind, text: &self.text[start..self.position], span: Span { start, end: self.position } })
+    }
+}
+
+/// Every token of `text`, its comment included
+pub fn lex(text: &str) -> Vec<Token<'_>> {
+    Lexer::new(text).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens() {
+        let tokens: Vec<(TokenKind, &str)> = lex("LOOP:\tmove.l #'\\'' << 2, (r0)+ ; it's done")
+            .into_iter()
+            .map(|token| (token.kind, token.text))
+            .collect();
+        assert_eq!(tokens, vec![
+            (TokenKind::Identifier, "LOOP"),
+            (TokenKind::Colon, ":"),
+            (TokenKind::Identifier, "move.l"),
+            (TokenKind::Hash, "#"),
+            (TokenKind::Char, "'\\''"),
+            (TokenKind::Operator, "<<"),
+            (TokenKind::Number, "2"),
+            (TokenKind::Comma, ","),
+            (TokenKind::Open, "("),
+            (TokenKind::Identifier, "r0"),
+            (TokenKind::Close, ")"),
+            (TokenKind::Operator, "+"),
+            (TokenKind::Comment, "; it's done"),
+        ]);
+
+        assert_eq!(lex(" .string \"a;b")[1].kind, TokenKind::String);
+        assert_eq!(lex("?é")[1], Token { kind: TokenKind::Unknown, text: "é", span: Span { start: 1, end: 3 } });
+    }
+}
@@ -0,0 +1,29 @@
+//! Assembler, encoder/decoder and emulator of the proco CPU.
+//!
+//! The binaries in `src/bin` are the command line tools built on these modules :
+//! `proco` (assembler driver), `disasm`, `isadoc` and `bench`.
+
+pub mod assembler;
+pub mod ast;
+pub mod catalog;
+pub mod cpu;
+pub mod decoder;
+pub mod directive;
+pub mod disasm;
+pub mod encoder;
+pub mod error;
+pub mod expr;
+pub mod game;
+pub mod isa;
+pub mod lexer;
+pub mod linker;
+pub mod listing;
+pub mod memory;
+pub mod object;
+pub mod parser;
+pub mod preprocessor;
+pub mod symbols;
+pub mod utils;
+
+#[cfg(test)]
+mod test;
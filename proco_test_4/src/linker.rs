@@ -0,0 +1,324 @@
+//! Linker, combines relocatable objects (see `object`) into a program image.
+//!
+//! The sections of the same name are put one after the other, in the order
+//! of the objects and each one on a multiple of its alignment. `.text` starts at the
+//! address given by the layout, `.data` and `.bss` too when it gives one, or
+//! right after the previous section otherwise. The image goes from the first
+//! byte of `.text` or `.data` to the last one, `.bss` is left out.
+
+use core::fmt;
+use std::collections::HashMap;
+
+use crate::assembler::Placement;
+use crate::catalog;
+use crate::directive::{self, Section};
+use crate::encoder;
+use crate::object::{Object, Target};
+use crate::parser::SymbolTable;
+
+/// Where the sections go
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Layout {
+    pub text: u16,
+    pub data: Option<u16>,
+    pub bss: Option<u16>,
+}
+
+impl Layout {
+    fn start(&self, section: Section) -> Option<u16> {
+        match section {
+            Section::Text => Some(self.text),
+            Section::Data => self.data,
+            Section::Bss => self.bss,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LinkError {
+    UndefinedSymbol { name: String, object: String },
+    DuplicateSymbol { name: String, first: String, second: String },
+    Overflow(Section),
+    Overlap(Section, Section),
+}
+
+impl LinkError {
+    /// Stable code of the error, see `catalog`
+    pub fn code(&self) -> &'static str {
+        match self {
+            LinkError::UndefinedSymbol { .. } => "E0028",
+            LinkError::DuplicateSymbol { .. } => "E0029",
+            LinkError::Overflow(_) => "E0030",
+            LinkError::Overlap(_, _) => "E0031",
+        }
+    }
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let code = self.code();
+        let message = match self {
+            LinkError::UndefinedSymbol { name, object } => catalog::text(code, &[("name", name), ("object", object)]),
+            LinkError::DuplicateSymbol { name, first, second } => catalog::text(code, &[("name", name), ("first", first), ("second", second)]),
+            LinkError::Overflow(section) => catalog::text(code, &[("section", &section.name())]),
+            LinkError::Overlap(first, second) => catalog::text(code, &[("first", &first.name()), ("second", &second.name())]),
+        };
+
+        f.write_str(&message)
+    }
+}
+
+impl std::error::Error for LinkError { }
+
+/// A linked program
+pub struct Linked {
+    /// Address of the first byte of the image
+    pub origin: u16,
+    pub bytes: Vec<u8>,
+    /// Every section, in the order of `Section::ALL`
+    pub sections: Vec<Placement>,
+    /// Address of every exported label
+    pub symbols: SymbolTable,
+}
+
+impl Linked {
+    /// The machine words of the whole image
+    pub fn words(&self) -> Vec<u16> {
+        encoder::words(&self.bytes)
+    }
+}
+
+/// Link the `objects`, each one along with its name, every error is reported
+pub fn link(objects: &[(String, Object)], layout: &Layout) -> Result<Linked, Vec<LinkError>> {
+    let mut errors = Vec::new();
+
+    // Address of each section of each object
+    let mut bases: Vec<HashMap<Section, u16>> = vec![HashMap::new(); objects.len()];
+    let mut placements = Vec::new();
+    let mut end = layout.text as u32;
+
+    for (index, section) in Section::ALL.into_iter().enumerate() {
+        // Every object of the section is aligned when the section is
+        let alignment = objects.iter().fold(2, |alignment, (_, object)| directive::common_alignment(alignment, object.sections[index].alignment));
+        let start = layout.start(section).map_or(end.next_multiple_of(alignment), u32::from);
+        let mut address = start;
+
+        for (k, (_, object)) in objects.iter().enumerate() {
+            address = address.next_multiple_of(object.sections[index].alignment);
+            bases[k].insert(section, address as u16);
+            address += object.sections[index].size as u32;
+        }
+
+        // An empty section takes no memory, wherever it starts
+        if address > 0x10000 && address > start {
+            errors.push(LinkError::Overflow(section));
+        }
+        placements.push(Placement { section, start: start as u16, size: (address - start) as u16, alignment });
+        end = address;
+    }
+
+    for (k, first) in placements.iter().enumerate() {
+        for second in &placements[k + 1..] {
+            let (a, b) = (first.start as u32, second.start as u32);
+            if first.size > 0 && second.size > 0 && a < b + second.size as u32 && b < a + first.size as u32 {
+                errors.push(LinkError::Overlap(first.section, second.section));
+            }
+        }
+    }
+
+    // The bases mean nothing once a section is truncated or overlaps another one
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    // Address of every exported name, along with the object exporting it
+    let mut globals: HashMap<&str, (u16, usize)> = HashMap::new();
+    for (k, (name, object)) in objects.iter().enumerate() {
+        for export in &object.exports {
+            let address = export.section.map_or(export.value, |section| bases[k][&section].wrapping_add(export.value));
+
+            match globals.get(export.name.as_str()) {
+                Some((_, first)) => errors.push(LinkError::DuplicateSymbol {
+                    name: export.name.clone(),
+                    first: objects[*first].0.clone(),
+                    second: name.clone(),
+                }),
+                None => { globals.insert(&export.name, (address, k)); },
+            }
+        }
+    }
+
+    // The image holds .text and .data
+    let filled: Vec<&Placement> = placements.iter()
+        .filter(|placement| placement.section != Section::Bss && placement.size > 0)
+        .collect();
+    let origin = filled.iter().map(|placement| placement.start).min().unwrap_or(layout.text);
+    let image_end = filled.iter().map(|placement| placement.start as usize + placement.size as usize).max().unwrap_or(origin as usize);
+    let mut bytes = vec![0; image_end - origin as usize];
+    // Where `address` is in the image, `.text` and `.data` being in it
+    let index = |address: usize| address - origin as usize;
+
+    for (k, (name, object)) in objects.iter().enumerate() {
+        for content in object.sections.iter().filter(|content| !content.bytes.is_empty()) {
+            let start = index(bases[k][&content.section] as usize);
+            bytes[start..start + content.bytes.len()].copy_from_slice(&content.bytes);
+        }
+
+        for relocation in &object.relocations {
+            let target = match &relocation.target {
+                Target::Section(section) => bases[k][section],
+                Target::Symbol(symbol) => match globals.get(symbol.as_str()) {
+                    Some((address, _)) => *address,
+                    None => {
+                        let error = LinkError::UndefinedSymbol { name: symbol.clone(), object: name.clone() };
+                        if !errors.contains(&error) {
+                            errors.push(error);
+                        }
+                        continue;
+                    },
+                },
+            };
+
+            let section = bases[k][&relocation.section];
+            let at = index(section as usize + relocation.offset as usize);
+            let word = u16::from_le_bytes([bytes[at], bytes[at + 1]]);
+            let added = if relocation.pc_relative { target.wrapping_sub(section) } else { target };
+            bytes[at..at + 2].copy_from_slice(&word.wrapping_add(added).to_le_bytes());
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let symbols = objects.iter()
+        .flat_map(|(_, object)| &object.exports)
+        .filter(|export| export.section.is_some())
+        .map(|export| (export.name.clone(), globals[export.name.as_str()].0))
+        .collect();
+
+    Ok(Linked { origin, bytes, sections: placements, symbols })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::{assemble_with, Settings, Source};
+    use crate::cpu::PC;
+    use crate::error::FileError;
+    use crate::game;
+    use crate::object::{self, Object};
+    use crate::parser::Dialect;
+    use crate::preprocessor::FileLoader;
+
+    /// Assemble `text` as an object, written then read back
+    fn object(file: &str, text: &str) -> (String, Object) {
+        let settings = Settings { origin: 0, loader: &FileLoader::default(), defines: &[], relocatable: true, dialect: Dialect::default() };
+        let program = assemble_with(&[Source { file, text }], &settings).unwrap();
+
+        let mut written = Vec::new();
+        object::write_object(&mut written, &Object::new(&program)).unwrap();
+        (file.to_string(), object::read_object(written.as_slice()).unwrap())
+    }
+
+    #[test]
+    fn objects_are_linked() {
+        let main = object("main.asm", "
+            .extern DOUBLE, RESULT
+            .global START
+        START:
+            MOVE @VALUE, R0
+            BSR #DOUBLE
+            MOVE R0, @RESULT
+        END: BRA #END
+            .data
+        VALUE: .word 21");
+        let double = object("double.asm", "
+            .global DOUBLE, RESULT
+        DOUBLE:
+            ADD R0, R0
+            RTS
+            .bss
+        RESULT: .space 2");
+
+        let linked = link(&[main, double], &Layout { text: game::RESET_ADDR, data: None, bss: Some(0x200) }).unwrap();
+        assert_eq!(linked.origin, game::RESET_ADDR);
+        assert_eq!(linked.symbols["RESULT"], 0x200);
+
+        let (mut cpu, mut memory) = game::boot(&linked.words());
+        cpu.registers[crate::cpu::SP] = 0xf0;
+        for _ in 0..20 {
+            cpu.step(&mut memory);
+        }
+        assert_eq!(cpu.registers[0], 42);
+        assert_eq!(memory.read_u16(0x200), 42);
+        // Stuck on END
+        let end = cpu.registers[PC];
+        cpu.step(&mut memory);
+        assert_eq!(cpu.registers[PC], end);
+    }
+
+    #[test]
+    fn link_errors() {
+        let first = object("first.asm", ".extern MISSING\n.global SHARED\nSHARED: MOVE #MISSING, R0\nMOVE #MISSING, R1");
+        let second = object("second.asm", ".global SHARED\nSHARED: RTS");
+
+        let errors = link(&[first, second], &Layout { text: 0x10, data: None, bss: None }).err().unwrap();
+        assert_eq!(errors, vec![
+            LinkError::DuplicateSymbol { name: "SHARED".to_string(), first: "first.asm".to_string(), second: "second.asm".to_string() },
+            LinkError::UndefinedSymbol { name: "MISSING".to_string(), object: "first.asm".to_string() },
+        ]);
+
+        let code = object("code.asm", "RTS\nRTS\n.data\n.word 1");
+        let errors = link(&[code], &Layout { text: 0x10, data: Some(0x12), bss: None }).err().unwrap();
+        assert_eq!(errors, vec![LinkError::Overlap(Section::Text, Section::Data)]);
+
+        let code = object("code.asm", "MOVE @VALUE, R0\nRTS\n.data\nVALUE: .word 1");
+        let errors = link(&[code], &Layout { text: 0xfffc, data: None, bss: None }).err().unwrap();
+        assert_eq!(errors, vec![LinkError::Overflow(Section::Text), LinkError::Overflow(Section::Data)]);
+    }
+
+    #[test]
+    fn sections_are_aligned() {
+        let first = object("first.asm", "RTS\n.data\n.byte 1");
+        let second = object("second.asm", "
+            .global TABLE
+            MOVE @TABLE, R0
+            .data
+            .byte 2
+            .align 8
+        TABLE: .word 0x1234");
+        assert_eq!(second.1.sections[1].alignment, 8);
+
+        // Each object on its alignment, the section on the alignment of all of them
+        let linked = link(&[first, second], &Layout { text: 0x10, data: None, bss: None }).unwrap();
+        assert_eq!(linked.sections[1].start % 8, 0);
+        assert_eq!(linked.sections[1].alignment, 8);
+        let table = linked.symbols["TABLE"];
+        assert_eq!(table % 8, 0);
+        let offset = (table - linked.origin) as usize;
+        assert_eq!(linked.bytes[offset..offset + 2], [0x34, 0x12]);
+        assert_eq!(linked.words()[2], table);
+
+        for alignment in ["0", "65537", "x"] {
+            let text = format!("section text 0 {}\nsection data 0 2\nsection bss 0 2\n", alignment);
+            let error = object::read_object(text.as_bytes()).err().unwrap();
+            assert_eq!(FileError::of(&error), Some(&FileError::ObjectLine { line: 1, text: format!("section text 0 {}", alignment) }));
+        }
+    }
+
+    #[test]
+    fn relocations_stay_in_their_section() {
+        let sections = "section text 4 2 0000fc00\nsection data 0 2\nsection bss 2 2\n";
+        assert!(object::read_object(format!("{}relocation text 0002 absolute .text", sections).as_bytes()).is_ok());
+        for relocation in ["relocation text 0003 absolute .text", "relocation data 0000 absolute .text", "relocation bss 0000 absolute .text"] {
+            let error = object::read_object(format!("{}{}", sections, relocation).as_bytes()).err().unwrap();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidData, "{}", relocation);
+            assert_eq!(FileError::of(&error).map(FileError::code), Some("E0037"), "{}", relocation);
+        }
+
+        // Every section, in order
+        let error = object::read_object("section data 0 2\nsection text 0 2\nsection bss 0 2\n".as_bytes()).err().unwrap();
+        assert_eq!(FileError::of(&error), Some(&FileError::MissingSections));
+    }
+}
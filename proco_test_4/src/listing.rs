@@ -0,0 +1,239 @@
+//! Listing of an assembled program : every source line along with the address
+//! and the words it has been assembled into (the bytes, for the data). The
+//! lines of an included file follow its `.include`, the lines expanded from a
+//! macro or a pseudo-instruction follow the call and are marked by a `+`. The labels can be listed at
+//! the end.
+//!
+//! ```text
+//! ; prog.asm
+//! 0010  18c0 fffe         1  START: ADD #0xFFFE, R0
+//!                         2  ; a comment
+//!                         3  SAVE R1
+//! 0014  0801              3+     PUSH R1
+//!
+//! ; symbols
+//! 0010  START
+//! ```
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::assembler::{Assembled, Content, Program};
+use crate::encoder::encode;
+use crate::symbols;
+
+/// At most this many data bytes are listed on a line
+const LISTED_BYTES: usize = 5;
+
+fn hex(content: &Content) -> String {
+    match content {
+        Content::Instruction(instruction) => {
+            let words: Vec<String> = encode(instruction).iter()
+                .map(|word| format!("{:04x}", word))
+                .collect();
+            words.join(" ")
+        },
+        Content::Data(bytes) => {
+            let listed: Vec<String> = bytes.iter()
+                .take(LISTED_BYTES)
+                .map(|byte| format!("{:02x}", byte))
+                .collect();
+            let more = if bytes.len() > LISTED_BYTES { ".." } else { "" };
+            format!("{}{}", listed.join(" "), more)
+        },
+    }
+}
+
+/// Where the items of the program come from
+struct Origins<'p> {
+    program: &'p Program,
+    /// Items of each (file, line), its expansions apart
+    items: HashMap<(usize, usize), Vec<&'p Assembled>>,
+    /// Items of each expanded line
+    expanded: HashMap<usize, Vec<&'p Assembled>>,
+    /// Expanded lines of each macro call
+    expansions: HashMap<(usize, usize), Vec<usize>>,
+    /// Files included by each line
+    includes: HashMap<(usize, usize), Vec<usize>>,
+}
+
+impl<'p> Origins<'p> {
+    fn new(program: &'p Program) -> Self {
+        let mut origins = Origins {
+            program,
+            items: HashMap::new(),
+            expanded: HashMap::new(),
+            expansions: HashMap::new(),
+            includes: HashMap::new(),
+        };
+
+        for assembled in &program.items {
+            match assembled.expansion {
+                Some(n) => origins.expanded.entry(n).or_default().push(assembled),
+                None => origins.items.entry((assembled.file, assembled.line)).or_default().push(assembled),
+            }
+        }
+        for (n, expanded) in program.expansions.iter().enumerate() {
+            origins.expansions.entry((expanded.file, expanded.line)).or_default().push(n);
+        }
+        for (file, source) in program.files.iter().enumerate() {
+            if let Some(site) = source.included {
+                origins.includes.entry(site).or_default().push(file);
+            }
+        }
+
+        origins
+    }
+
+    /// The lines of `file`, each included file right after its `.include`
+    fn write_file<W: Write>(&self, writer: &mut W, file: usize) -> io::Result<()> {
+        let source = &self.program.files[file];
+        writeln!(writer, "; {}", source.name)?;
+
+        for (i, line) in source.text.lines().enumerate() {
+            let items = self.items.get(&(file, i)).map_or(&[][..], Vec::as_slice);
+            write_line(writer, items, i + 1, ' ', line)?;
+
+            for expanded in self.expansions.get(&(file, i)).into_iter().flatten() {
+                let items = self.expanded.get(expanded).map_or(&[][..], Vec::as_slice);
+                write_line(writer, items, i + 1, '+', &self.program.expansions[*expanded].text)?;
+            }
+
+            if let Some(included) = self.includes.get(&(file, i)) {
+                for included in included {
+                    self.write_file(writer, *included)?;
+                }
+                writeln!(writer, "; {}", source.name)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A line and what it has been assembled into, `marker` follows the line number
+fn write_line<W: Write>(writer: &mut W, items: &[&Assembled], number: usize, marker: char, text: &str) -> io::Result<()> {
+    if items.is_empty() {
+        return writeln!(writer, "{:4}  {:15} {:>5}{} {}", "", "", number, marker, text);
+    }
+
+    // The source is only written next to the first instruction of the line
+    for (k, assembled) in items.iter().enumerate() {
+        if k == 0 {
+            writeln!(writer, "{:04x}  {:15} {:>5}{} {}", assembled.address, hex(&assembled.content), number, marker, text)?;
+        } else {
+            writeln!(writer, "{:04x}  {}", assembled.address, hex(&assembled.content))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Write the listing of `program`, followed by its labels when `with_symbols` is set
+pub fn write_listing<W: Write>(writer: &mut W, program: &Program, with_symbols: bool) -> io::Result<()> {
+    let origins = Origins::new(program);
+
+    for (file, source) in program.files.iter().enumerate() {
+        if source.included.is_none() {
+            origins.write_file(writer, file)?;
+        }
+    }
+
+    if with_symbols {
+        writeln!(writer, "\n; symbols")?;
+        for (name, address) in symbols::sorted(&program.symbols) {
+            writeln!(writer, "{:04x}  {}", address, name)?;
+        }
+    }
+
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::{assemble_all, assemble_with, Settings, Source};
+    use crate::parser::Dialect;
+    use crate::preprocessor::Loader;
+
+    #[test]
+    fn every_line_is_listed() {
+        let sources = [Source { file: "prog.asm", text: "START: ADD #0xFFFE, R0\n; a comment\nRTS" }];
+        let program = assemble_all(&sources, 0x10).unwrap();
+
+        let mut listing = Vec::new();
+        write_listing(&mut listing, &program, false).unwrap();
+
+        assert_eq!(String::from_utf8(listing).unwrap(), "\
+; prog.asm
+0010  18c0 fffe           1  START: ADD #0xFFFE, R0
+                          2  ; a comment
+0014  e000                3  RTS
+");
+    }
+
+    /// Included files held in memory
+    struct Files(&'static [(&'static str, &'static str)]);
+
+    impl Loader for Files {
+        fn load(&self, path: &str, _from: &str) -> io::Result<(String, String)> {
+            self.0.iter()
+                .find(|(name, _)| *name == path)
+                .map(|(name, text)| (name.to_string(), text.to_string()))
+                .ok_or(io::Error::new(io::ErrorKind::NotFound, "no such file"))
+        }
+    }
+
+    #[test]
+    fn expansions_and_symbols() {
+        let sources = [Source { file: "prog.asm", text: "\
+.macro SAVE reg
+    PUSH \\reg ; saved
+.endm
+START: SAVE R1
+.include \"end.asm\"
+; done" }];
+        let loader = Files(&[("end.asm", "END: RTS")]);
+        let settings = Settings { origin: 0x10, loader: &loader, defines: &[], relocatable: false, dialect: Dialect::default() };
+        let program = assemble_with(&sources, &settings).unwrap();
+
+        let mut listing = Vec::new();
+        write_listing(&mut listing, &program, true).unwrap();
+
+        assert_eq!(String::from_utf8(listing).unwrap(), "\
+; prog.asm
+                          1  .macro SAVE reg
+                          2      PUSH \\reg ; saved
+                          3  .endm
+                          4  START: SAVE R1
+0010  0801                4+     PUSH R1 ; saved
+                          5  .include \"end.asm\"
+; end.asm
+0012  e000                1  END: RTS
+; prog.asm
+                          6  ; done
+
+; symbols
+0010  START
+0012  END
+");
+    }
+
+    #[test]
+    fn pseudo_instructions_are_expanded() {
+        let sources = [Source { file: "prog.asm", text: "NEG R1\nRET" }];
+        let program = assemble_all(&sources, 0x10).unwrap();
+
+        let mut listing = Vec::new();
+        write_listing(&mut listing, &program, false).unwrap();
+
+        assert_eq!(String::from_utf8(listing).unwrap(), "\
+; prog.asm
+                          1  NEG R1
+0010  5801                1+ NOT R1
+0012  1981                1+ ADD #0x1, R1
+                          2  RET
+0014  e000                2+ RTS
+");
+    }
+}
@@ -0,0 +1,49 @@
+//! The 64 KiB of memory the CPU can address.
+//!
+//! Memory is byte addressed and words are stored little-endian : the low byte
+//! at `addr`, the high byte at `addr + 1`. Addresses wrap around at 0xFFFF.
+
+pub const MEMORY_SIZE: usize = 0x10000;
+
+pub struct Memory {
+    bytes: Vec<u8>,
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Memory {
+            bytes: vec![0; MEMORY_SIZE],
+        }
+    }
+
+    pub fn read_u8(&self, addr: u16) -> u8 {
+        self.bytes[addr as usize]
+    }
+
+    pub fn write_u8(&mut self, addr: u16, value: u8) {
+        self.bytes[addr as usize] = value;
+    }
+
+    pub fn read_u16(&self, addr: u16) -> u16 {
+        u16::from_le_bytes([self.read_u8(addr), self.read_u8(addr.wrapping_add(1))])
+    }
+
+    pub fn write_u16(&mut self, addr: u16, value: u16) {
+        let [low, high] = value.to_le_bytes();
+        self.write_u8(addr, low);
+        self.write_u8(addr.wrapping_add(1), high);
+    }
+
+    /// Copy a sequence of words starting at `addr`
+    pub fn load(&mut self, addr: u16, words: &[u16]) {
+        for (i, word) in words.iter().enumerate() {
+            self.write_u16(addr.wrapping_add((i * 2) as u16), *word);
+        }
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
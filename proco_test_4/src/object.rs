@@ -0,0 +1,227 @@
+//! Relocatable object files, assembled apart then combined by the linker.
+//!
+//! Every section of an object starts at 0, the linker puts it on a multiple
+//! of its alignment (what its `.align` ask for). The words holding an address are
+//! listed by the relocations : the linker adds the address of their target (a
+//! section of the object or a symbol of another one) once the sections are
+//! placed. A text file, one entry per line :
+//!
+//! ```text
+//! ; proco object
+//! section text 8 2 0018feff00e00000
+//! section data 2 4 2a00
+//! section bss 16 8
+//! export START text 0000
+//! export SIZE abs 0010
+//! import PRINT
+//! relocation text 0006 absolute PRINT
+//! relocation text 0002 pc-relative .data
+//! ```
+//!
+//! The bytes of a section are in the order of the memory, `.bss` has none (so
+//! no relocation either). A pc-relative word holds the distance from the next
+//! instruction, the linker also subtracts the address of the section holding
+//! the word.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+use crate::assembler::Program;
+use crate::directive::Section;
+use crate::error::FileError;
+use crate::symbols::Kind;
+
+/// What the linker adds to a relocated word
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    /// The address of a section of the same object
+    Section(Section),
+    /// The address of a symbol exported by another object
+    Symbol(String),
+}
+
+/// A word holding an address
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relocation {
+    /// Section holding the word
+    pub section: Section,
+    /// Bytes from the start of the section to the word
+    pub offset: u16,
+    /// The word is relative to the address of the next instruction
+    pub pc_relative: bool,
+    pub target: Target,
+}
+
+/// The content of a section
+#[derive(Debug, Clone, PartialEq)]
+pub struct Content {
+    pub section: Section,
+    pub size: u16,
+    /// What the address of the section has to be a multiple of, up to 0x10000
+    pub alignment: u32,
+    /// Empty for `.bss`
+    pub bytes: Vec<u8>,
+}
+
+/// A label or a constant given to the other objects
+#[derive(Debug, Clone, PartialEq)]
+pub struct Export {
+    pub name: String,
+    /// The section of a label, `None` for a number
+    pub section: Option<Section>,
+    pub value: u16,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Object {
+    /// Every section, in the order of `Section::ALL`
+    pub sections: Vec<Content>,
+    pub exports: Vec<Export>,
+    pub imports: Vec<String>,
+    pub relocations: Vec<Relocation>,
+}
+
+impl Object {
+    /// The object of a program assembled as relocatable (see `assembler::Settings`)
+    pub fn new(program: &Program) -> Object {
+        let sections = program.sections.iter()
+            .map(|placement| {
+                let mut bytes = match placement.section {
+                    Section::Bss => Vec::new(),
+                    _ => vec![0; placement.size as usize],
+                };
+
+                for item in program.items.iter().filter(|item| item.section == placement.section) {
+                    let start = item.address as usize - placement.start as usize;
+                    let content = item.content.bytes();
+                    bytes[start..start + content.len()].copy_from_slice(&content);
+                }
+
+                Content { section: placement.section, size: placement.size, alignment: placement.alignment, bytes }
+            })
+            .collect();
+
+        let exports = program.exports.iter()
+            .filter_map(|name| program.map.iter().find(|symbol| symbol.name == *name && symbol.kind != Kind::Import))
+            .map(|symbol| Export { name: symbol.name.clone(), section: symbol.section, value: symbol.value })
+            .collect();
+
+        Object {
+            sections,
+            exports,
+            imports: program.imports.clone(),
+            relocations: program.relocations.clone(),
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn write_object<W: Write>(writer: &mut W, object: &Object) -> io::Result<()> {
+    writeln!(writer, "; proco object")?;
+
+    for content in &object.sections {
+        if content.bytes.is_empty() {
+            writeln!(writer, "section {} {} {}", content.section.name(), content.size, content.alignment)?;
+        } else {
+            writeln!(writer, "section {} {} {} {}", content.section.name(), content.size, content.alignment, hex(&content.bytes))?;
+        }
+    }
+    for export in &object.exports {
+        let section = export.section.map_or("abs", Section::name);
+        writeln!(writer, "export {} {} {:04x}", export.name, section, export.value)?;
+    }
+    for import in &object.imports {
+        writeln!(writer, "import {}", import)?;
+    }
+    for relocation in &object.relocations {
+        let kind = if relocation.pc_relative { "pc-relative" } else { "absolute" };
+        let target = match &relocation.target {
+            Target::Section(section) => format!(".{}", section.name()),
+            Target::Symbol(name) => name.clone(),
+        };
+        writeln!(writer, "relocation {} {:04x} {} {}", relocation.section.name(), relocation.offset, kind, target)?;
+    }
+
+    writer.flush()
+}
+
+pub fn write_object_file<P: AsRef<Path>>(path: P, object: &Object) -> io::Result<()> {
+    write_object(&mut File::create(path)?, object)
+}
+
+/// Read back what `write_object` wrote, blank lines and comments are skipped
+pub fn read_object<R: BufRead>(reader: R) -> io::Result<Object> {
+    let mut object = Object { sections: Vec::new(), exports: Vec::new(), imports: Vec::new(), relocations: Vec::new() };
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() || line.trim_start().starts_with(';') {
+            continue;
+        }
+
+        let invalid = || io::Error::from(FileError::ObjectLine { line: i + 1, text: line.clone() });
+        let section = |name: &str| Section::from_name(name).ok_or_else(invalid);
+        let number = |text: &str, radix: u32| u16::from_str_radix(text, radix).map_err(|_| invalid());
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields[..] {
+            ["section", name, size, alignment, ref bytes @ ..] if bytes.len() <= 1 => {
+                let bytes = bytes.first().map_or(Ok(Vec::new()), |bytes| {
+                    (0..bytes.len())
+                        .step_by(2)
+                        .map(|k| bytes.get(k..k + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()).ok_or_else(invalid))
+                        .collect()
+                })?;
+                let (section, size) = (section(name)?, number(size, 10)?);
+                let alignment = alignment.parse().ok().filter(|alignment| (1..=0x10000).contains(alignment)).ok_or_else(invalid)?;
+                let expected = if section == Section::Bss { 0 } else { size as usize };
+                if bytes.len() != expected {
+                    return Err(invalid());
+                }
+                object.sections.push(Content { section, size, alignment, bytes });
+            },
+            ["export", name, section_name, value] => {
+                let section = if section_name == "abs" { None } else { Some(section(section_name)?) };
+                object.exports.push(Export { name: name.to_string(), section, value: number(value, 16)? });
+            },
+            ["import", name] => object.imports.push(name.to_string()),
+            ["relocation", section_name, offset, kind, target] => {
+                let pc_relative = match kind {
+                    "absolute" => false,
+                    "pc-relative" => true,
+                    _ => return Err(invalid()),
+                };
+                let target = match target.strip_prefix('.') {
+                    Some(name) => Target::Section(section(name)?),
+                    None => Target::Symbol(target.to_string()),
+                };
+                object.relocations.push(Relocation { section: section(section_name)?, offset: number(offset, 16)?, pc_relative, target });
+            },
+            _ => return Err(invalid()),
+        }
+    }
+
+    // Every section is there, in order
+    let sections: Vec<Section> = object.sections.iter().map(|content| content.section).collect();
+    if sections != Section::ALL {
+        return Err(FileError::MissingSections.into());
+    }
+
+    // The relocated words are within the bytes of their section
+    for relocation in &object.relocations {
+        let content = &object.sections[relocation.section as usize];
+        if content.bytes.len() < relocation.offset as usize + 2 {
+            return Err(FileError::RelocationOutside { section: relocation.section, offset: relocation.offset }.into());
+        }
+    }
+
+    Ok(object)
+}
+
+pub fn read_object_file<P: AsRef<Path>>(path: P) -> io::Result<Object> {
+    read_object(BufReader::new(File::open(path)?))
+}
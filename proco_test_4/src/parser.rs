@@ -1,8 +1,13 @@
-use crate::utils::{BitInt, alert};
-
-use regex::{self, Regex};
+use crate::error::{AsmErrorKind, ParseError};
+use crate::directive::Section;
+use crate::ast::{self, Instruction, Node, OperandExpr};
+use crate::cpu::{PC, SP};
+use crate::expr::{self, Base, Value};
+use crate::isa::{self, Format, Kind, OperandSpec};
+use crate::utils::BitInt;
 
 
+#[derive(Debug, Clone, PartialEq)]
 pub enum InstructionFormat {
     Format0op(_Format0opLayout),
     Format1op(_Format1opLayout),
@@ -10,32 +15,50 @@ pub enum InstructionFormat {
     FormatMoveOp(_FormatMoveLayout)
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct _Format0opLayout {
-    opcode: BitInt::<5>,
-    op_reserved: BitInt::<11>,
+    pub opcode: BitInt::<5>,
+    pub op_reserved: BitInt::<11>,
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct _Format1opLayout {
-    opcode: BitInt::<5>,
-    op_type: BitInt::<3>,
-    op_value: BitInt::<8>,
+    pub opcode: BitInt::<5>,
+    pub op_type: BitInt::<3>,
+    pub op_value: BitInt::<8>,
+    /// Only present with the extended modes (see `MODE_EXTENDED_IMMEDIATE`)
+    pub extension: Option<BitInt::<16>>,
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct _Format2opLayout {
-    opcode: BitInt::<5>,
-    registry_dest: BitInt::<3>,
-    op_type_source: BitInt::<3>,
-    op_value: BitInt::<5>,
+    pub opcode: BitInt::<5>,
+    pub registry_dest: BitInt::<3>,
+    pub op_type_source: BitInt::<3>,
+    pub op_value: BitInt::<5>,
+    /// Only present with the extended modes (see `MODE_EXTENDED_IMMEDIATE`)
+    pub extension: Option<BitInt::<16>>,
+}
+
+/// `#x` whose value is held by an extension word following the instruction
+pub const MODE_EXTENDED_IMMEDIATE: u16 = 0b110;
+/// `@x` whose address is held by an extension word following the instruction
+pub const MODE_EXTENDED_ADDRESS: u16 = 0b111;
+
+/// Whether an addressing mode of the 1op/2op formats is followed by an extension word
+pub fn has_extension(mode: u16) -> bool {
+    mode == MODE_EXTENDED_IMMEDIATE || mode == MODE_EXTENDED_ADDRESS
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct _FormatMoveLayout {
-    opcode: BitInt::<5>,
-    h: BitInt::<1>,
-    l: BitInt::<1>,
-    source_type: BitInt::<3>,
-    destination_type: BitInt::<3>,
-    registry_no: BitInt::<3>,
-    value: BitInt::<16>,
+    pub opcode: BitInt::<5>,
+    pub h: BitInt::<1>,
+    pub l: BitInt::<1>,
+    pub source_type: BitInt::<3>,
+    pub destination_type: BitInt::<3>,
+    pub registry_no: BitInt::<3>,
+    pub value: BitInt::<16>,
 }
 
 
@@ -47,13 +70,21 @@ impl fmt::Display for _Format0opLayout {
 
 impl fmt::Display for _Format1opLayout {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Opcode: {}, Type: {}, Value: {}", self.opcode, self.op_type, self.op_value)
+        write!(f, "Opcode: {}, Type: {}, Value: {}", self.opcode, self.op_type, self.op_value)?;
+        if let Some(extension) = self.extension {
+            write!(f, ", Extension: {}", extension)?;
+        }
+        Ok(())
     }
 }
 
 impl fmt::Display for _Format2opLayout {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Opcode: {}, Dest: {}, Type Source: {}, Value: {}", self.opcode, self.registry_dest, self.op_type_source, self.op_value)
+        write!(f, "Opcode: {}, Dest: {}, Type Source: {}, Value: {}", self.opcode, self.registry_dest, self.op_type_source, self.op_value)?;
+        if let Some(extension) = self.extension {
+            write!(f, ", Extension: {}", extension)?;
+        }
+        Ok(())
     }
 }
 
@@ -75,285 +106,557 @@ impl fmt::Display for InstructionFormat {
 }
 
 use core::fmt;
-use std::{collections::HashMap, ptr::null};
-use lazy_static::lazy_static;
-lazy_static! {
-pub static ref RE_MAP: HashMap<&'static str, Regex> = [
-    ("REGISTER", Regex::new(r"^R([0-7])$").unwrap()),
-    ("VALEUR_#V", Regex::new(r"^#((6553[0-5])|(655[0-2][0-9])|(65[0-4][0-9]{2})|(6[0-4][0-9]{3})|([1-5][0-9]{4})|([0-5]{0,5})|([0-9]{1,4}))$").unwrap()),
-    ("VALEUR_OxV", Regex::new(r"^#0x([0-9A-F]{1,4})$").unwrap()),
-    ("VALEUR_0bV", Regex::new(r"^#b([0|1]{1,16})$").unwrap()),
-    ("VALEUR_TEXTE", Regex::new(r"^#([a-zA-Z]+)$").unwrap()),
-    ("ADRESS_D", Regex::new(r"^@0x([0-9A-F]{1,4})$").unwrap()),
-    ("REGISTER_I", Regex::new(r"^\(R([0-7])\)$").unwrap()),
-    ("REGISTER_I_POST", Regex::new(r"^\(R([0-7])\)\+$").unwrap()),
-    ("REGISTER_I_PRE", Regex::new(r"^\-\(R([0-7])\)$").unwrap()),
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+
+
+
+/// Address of every named label
+pub type SymbolTable = HashMap<String, u16>;
+
+/// The labels of a program, filled by the first pass of the assembler.
+///
+/// A label starting with a `.` is local to the last global label before it :
+/// `.loop` defined after `ROUTINE` is `ROUTINE.loop`, it can be used as `.loop`
+/// up to the next global label, and as `ROUTINE.loop` anywhere. A numeric label
+/// (`1:`) can be defined many times, `1b` is the last definition up to the
+/// current line and `1f` the next one.
+///
+/// The names declared by `.extern` are imported, their address is left to the
+/// linker.
+#[derive(Debug, Default)]
+pub struct Symbols {
+    /// Address of every named label, the local ones under their full name
+    pub addresses: SymbolTable,
+    /// Section of every named label
+    pub sections: HashMap<String, Section>,
+    /// Line defining every named label, its index among the lines of every file
+    pub lines: HashMap<String, usize>,
+    /// Definitions of every numeric label as (line, section, address), in the order of the lines
+    pub numeric: HashMap<u32, Vec<(usize, Section, u16)>>,
+    /// Imported names, along with the line declaring them
+    pub imports: Vec<(String, usize)>,
+}
+
+/// Whether `label` is a global label, the ones opening a scope
+pub fn is_global(label: &str) -> bool {
+    is_identifier(label)
+}
+
+/// The number of `1b`/`1f` and whether it looks forward
+fn numeric_reference(name: &str) -> Option<(u32, bool)> {
+    let (digits, direction) = name.split_at(name.len().checked_sub(1)?);
+    let forward = match direction {
+        "b" | "B" => false,
+        "f" | "F" => true,
+        _ => return None,
+    };
 
-    ("MOVE_PARSE", Regex::new(r"^MOVE.([\w])$").unwrap())
-].iter().cloned().collect();
+    Some((digits.parse().ok().filter(|_| digits.bytes().all(|c| c.is_ascii_digit()))?, forward))
 }
 
+impl Symbols {
+    /// Full name of the label `name` written in `scope`
+    pub fn full_name<'a>(name: &'a str, scope: Option<&str>) -> Cow<'a, str> {
+        match scope {
+            Some(scope) if name.starts_with('.') => Cow::Owned([scope, name].concat()),
+            _ => Cow::Borrowed(name),
+        }
+    }
 
+    /// Line defining or importing `name`
+    pub fn line(&self, name: &str) -> Option<usize> {
+        self.lines.get(name).copied()
+            .or_else(|| self.imports.iter().find(|(import, _)| import == name).map(|(_, line)| *line))
+    }
 
+    /// Define the label `name` of the `line`, returns the line already defining it
+    pub fn define(&mut self, name: &str, scope: Option<&str>, line: usize, section: Section, address: u16) -> Result<(), usize> {
+        if let Ok(number) = name.parse::<u32>() {
+            self.numeric.entry(number).or_default().push((line, section, address));
+            return Ok(());
+        }
 
-pub fn tokenize(asm_code: &str) -> Vec<&str> {
-    let re = Regex::new(r"^(.*?)(?:;|$)").unwrap();
+        let name = Symbols::full_name(name, scope).into_owned();
+        if let Some(previous) = self.line(&name) {
+            return Err(previous);
+        }
 
-    let mut line = asm_code;
-            
-    if let Some(tokens) = re.captures(&line) {
-        if let Some(without_comment) = tokens.get(1) {
-            line = without_comment.as_str();
-        }        
+        self.addresses.insert(name.clone(), address);
+        self.sections.insert(name.clone(), section);
+        self.lines.insert(name, line);
+        Ok(())
     }
 
-    line.split(|c| c == ' ' || c == ',')
-        .filter(|part| part.len() > 0)
-        .collect()
-}
+    /// Import `name` at the `line`, returns the line already defining it
+    pub fn import(&mut self, name: &str, line: usize) -> Result<(), usize> {
+        match self.line(name) {
+            Some(previous) => Err(previous),
+            None => {
+                self.imports.push((name.to_string(), line));
+                Ok(())
+            },
+        }
+    }
 
+    /// Section and address of the label `name` (`1b`/`1f` included) used by the `line` in `scope`
+    pub fn label(&self, name: &str, scope: Option<&str>, line: usize) -> Option<(Section, u16)> {
+        if let Some((number, forward)) = numeric_reference(name) {
+            let definitions = self.numeric.get(&number)?;
+            // The definitions are in the order of the lines
+            let after = definitions.partition_point(|(defined, _, _)| *defined <= line);
+            let found = if forward {
+                definitions.get(after)
+            } else {
+                after.checked_sub(1).map(|before| &definitions[before])
+            };
+            return found.map(|(_, section, address)| (*section, *address));
+        }
 
-pub fn parse(tokens: Vec<&str>, instruction_set: &HashMap<&str, BitInt<5>>) -> Result<Vec<InstructionFormat>, String> {
-    let mut instructions: Vec<InstructionFormat> = Vec::new();
+        let name = Symbols::full_name(name, scope);
+        Some((*self.sections.get(name.as_ref())?, self.addresses[name.as_ref()]))
+    }
 
-    // let mut iter = tokens.iter().peekable();
+    /// Move the labels of `section` by `base`, once the size of the sections before it is known
+    pub fn relocate(&mut self, section: Section, base: u16) {
+        for (name, address) in self.addresses.iter_mut() {
+            if self.sections[name] == section {
+                *address = address.wrapping_add(base);
+            }
+        }
+        for (_, defined, address) in self.numeric.values_mut().flatten() {
+            if *defined == section {
+                *address = address.wrapping_add(base);
+            }
+        }
+    }
+}
 
-    match tokens.len() {
-        1 => {
-            // instruction_set.get(k)
-            let instruction = tokens.get(0).unwrap();
-            let opcode: BitInt<5> = *instruction_set.get(instruction).unwrap();
+/// A constant (`.equ NAME, expr` or `NAME = expr`) and where it is defined
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Constant {
+    pub value: Value,
+    /// Index of the defining line, among the lines of every file, `None` for
+    /// the constants defined outside of the sources
+    pub line: Option<usize>,
+}
 
-            instructions.push(InstructionFormat::Format0op(_Format0opLayout {
-                opcode: BitInt::<5>::new(*opcode).unwrap(),
-                op_reserved: BitInt::<11>::new(0).unwrap()
-            }))
-        },
-        2 => {
-            let instruction = tokens.get(0).unwrap();
-            let operand_1 = tokens.get(1).unwrap();
+pub type Constants = HashMap<String, Constant>;
+
+/// How the sources are written, lenient by default
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Dialect {
+    /// Mnemonics and registers in any case (`move.l r0, (sp)+`), upper case only otherwise
+    pub any_case: bool,
+    /// `PC` and `SP` for R6 and R7
+    pub register_aliases: bool,
+    /// A line starting with `*` is a comment, along with anything after a `;`
+    pub star_comments: bool,
+}
 
-            let opcode: BitInt<5> = *instruction_set.get(instruction).unwrap();
+impl Dialect {
+    /// Upper case mnemonics and registers, no aliases
+    pub const STRICT: Dialect = Dialect { any_case: false, register_aliases: false, star_comments: false };
 
-            let op_type: Operand = parse_operand_type(operand_1);
-            let op_value  = get_op_value(operand_1, op_type);
-            
-            instructions.push(InstructionFormat::Format1op(_Format1opLayout {
-                opcode: BitInt::<5>::new(*opcode).unwrap(),
-                op_type: BitInt::<3>::new(op_type.get().into()).unwrap(),
-                op_value: BitInt::<8>::new(op_value.into()).unwrap()
-            }))
+    fn matches(&self, text: &str, name: &str) -> bool {
+        text == name || (self.any_case && text.eq_ignore_ascii_case(name))
+    }
 
-        },
-        3 => {
-            let instruction = tokens.get(0).unwrap();
-            if instruction.to_uppercase().starts_with("MOVE") {
-                // todo: parse move instruction
-
-                let re: &Regex = RE_MAP.get("MOVE_PARSE").unwrap();
-                
-                let mut h_value: BitInt<1> = BitInt::<1>::new(1).unwrap();
-                let mut l_value: BitInt<1> = BitInt::<1>::new(1).unwrap();
-
-                if let Some(captures) = re.captures(instruction) {
-                    if let Some(token_value) = captures.get(1) {
-                        match token_value.as_str() {
-                            "L" => {
-                                h_value = BitInt::<1>::new(0).unwrap()
-                            },
-                            "H" => {
-                                l_value = BitInt::<1>::new(0).unwrap()
-                            },
-                            _ => {
-                                println!("tu as trouvé un truc chelou là");
-                                panic!("tu as trouvé un truc chelou là")
-                            }
-                        };
-                    }
-                }
-
-                let source = tokens.get(1).unwrap();
-                let destination = tokens.get(2).unwrap();
-
-                let source_type: Operand = parse_operand_type(source);
-                let destination_type: Operand = parse_operand_type(destination);
-
-                let registry_no;
-                let value;
-                match source_type {
-                    Operand::ImmediateValueBIN(_) |
-                    Operand::ImmediateValueDEC(_) |
-                    Operand::ImmediateValueHEX(_) |
-                    Operand::MemoryAddress(_) => {
-                        let source_value  = get_op_value(source, source_type);
-                        let destination_value  = get_op_value(destination, destination_type);
-                        registry_no = destination_value;
-                        value = source_value;
-                    },
-                    _ => {
-                        let source_value  = get_op_value(source, source_type);
-                        let destination_value  = get_op_value(destination, destination_type);
-
-                        registry_no = source_value;
-                        value = destination_value;
-                    }
-                };
+    /// The mnemonic `text` as the ISA names it
+    pub fn mnemonic<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        if self.any_case && text.bytes().any(|c| c.is_ascii_lowercase()) {
+            Cow::Owned(text.to_ascii_uppercase())
+        } else {
+            Cow::Borrowed(text)
+        }
+    }
 
-                instructions.push(InstructionFormat::FormatMoveOp(_FormatMoveLayout {
-                    opcode: BitInt::<5>::new(0).unwrap(),
-                    h: h_value,
-                    l: l_value,
-                    source_type: BitInt::<3>::new(source_type.get().into()).unwrap(),
-                    destination_type: BitInt::<3>::new(destination_type.get().into()).unwrap(),
-                    registry_no: BitInt::<3>::new(registry_no.into()).unwrap(),
-                    value: BitInt::<16>::new(value.into()).unwrap()
-                }))
-            } else {
-                let source = tokens.get(1).unwrap();
-                let destination = tokens.get(2).unwrap();
-                
-                let opcode: BitInt<5> = *instruction_set.get(instruction).unwrap();
-                
-
-                let source_type: Operand = parse_operand_type(source);
-                let source_value  = get_op_value(source, source_type);
-
-                let destination_type: Operand = parse_operand_type(destination);
-
-                match destination_type {
-                    Operand::Register(_) => {
-                        let destination_value  = get_op_value(destination, destination_type);
-
-                        instructions.push(InstructionFormat::Format2op(_Format2opLayout {
-                            opcode: opcode,
-                            registry_dest: BitInt::<3>::new(destination_value.into()).unwrap(),
-                            op_type_source: BitInt::<3>::new(source_type.get().into()).unwrap(),
-                            op_value: BitInt::<5>::new(source_value.into()).unwrap()
-                        }))
-                    },
-                    _ => {
-                        alert(format!("Destination operand isnt a register for\n{} {}, {}", instruction, source, destination).as_str());
-                        panic!()
-                    }
-                }
+    /// Number of the register `text`
+    pub fn register(&self, text: &str) -> Option<u8> {
+        if self.register_aliases {
+            if self.matches(text, "PC") {
+                return Some(PC as u8);
+            } else if self.matches(text, "SP") {
+                return Some(SP as u8);
             }
-        },
-        _ => { },
-    };
+        }
 
-    Ok(instructions)    
+        let digit = text.strip_prefix('R').or_else(|| text.strip_prefix('r').filter(|_| self.any_case))?;
+        match digit.as_bytes() {
+            [c @ b'0'..=b'7'] => Some(c - b'0'),
+            _ => None,
+        }
+    }
+
+    /// Whether the whole `line` is a comment, besides the blank ones and the `;` ones
+    pub fn is_comment(&self, line: &str) -> bool {
+        self.star_comments && line.trim_start().starts_with('*')
+    }
 }
 
+impl Default for Dialect {
+    fn default() -> Self {
+        Dialect { any_case: true, register_aliases: true, star_comments: false }
+    }
+}
 
-fn get_op_value(source: &str, operand: Operand) -> u8 {
-    match operand {
-        Operand::Register(_) => {
-            let re: &Regex = RE_MAP.get("REGISTER").unwrap();
-            
-            if let Some(captures) = re.captures(source) {
-                if let Some(token_value) = captures.get(1) {
-                    return token_value.as_str().parse::<u8>().unwrap();
-                }        
-            }
+/// Everything `parse` needs to know about where the instruction lands
+pub struct Context<'a> {
+    pub symbols: &'a Symbols,
+    pub constants: &'a Constants,
+    /// The last global label, the local labels belong to it
+    pub scope: Option<&'a str>,
+    /// Section of the line
+    pub section: Section,
+    pub dialect: Dialect,
+    /// Address of the instruction being parsed
+    pub address: u16,
+    /// Index of the line being parsed, among the lines of every file
+    pub line: usize,
+    /// During the first pass, labels defined later in the file aren't known yet
+    pub first_pass: bool,
+    /// Collects the full names of the labels and constants used, when given
+    pub references: Option<&'a RefCell<Vec<String>>>,
+    /// Collects the addresses left to the linker, only given when assembling
+    /// a relocatable object
+    pub fixups: Option<&'a RefCell<Vec<Fixup>>>,
+}
 
-            0
-        },
-        Operand::IndirectAddress(_) => {
-            let re: &Regex = RE_MAP.get("REGISTER_I").unwrap();
-            
-            if let Some(captures) = re.captures(source) {
-                if let Some(token_value) = captures.get(1) {
-                    return token_value.as_str().parse::<u8>().unwrap();
-                }        
-            }
+/// An address written in a word of a line, the linker adds the address of its
+/// base to the word
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fixup {
+    /// Bytes from the start of what the line emits
+    pub offset: u16,
+    pub base: Base,
+    /// The word holds the distance from the next instruction to the address
+    pub pc_relative: bool,
+}
 
-            0
-        },
-        Operand::PreDecrementedRegister(_) => {
-            let re = RE_MAP.get("REGISTER_I_PRE").unwrap();
-            
-            if let Some(captures) = re.captures(source) {
-                if let Some(token_value) = captures.get(1) {
-                    return token_value.as_str().parse::<u8>().unwrap();
-                }        
-            }
+impl<'a> Context<'a> {
+    /// A context outside of any line : at address 0 of `.text`, in the default
+    /// dialect, past the first pass and collecting nothing
+    pub fn new(symbols: &'a Symbols, constants: &'a Constants) -> Context<'a> {
+        Context {
+            symbols,
+            constants,
+            scope: None,
+            section: Section::Text,
+            dialect: Dialect::default(),
+            address: 0,
+            line: 0,
+            first_pass: false,
+            references: None,
+            fixups: None,
+        }
+    }
 
-            0
-        },
-        Operand::PostIncrementedRegister(_) => {
-            let re = RE_MAP.get("REGISTER_I_POST").unwrap();
-            
-            if let Some(captures) = re.captures(source) {
-                if let Some(token_value) = captures.get(1) {
-                    return token_value.as_str().parse::<u8>().unwrap();
-                }        
-            }
+    /// Value of a constant or of the address of a label
+    pub fn lookup(&self, node: Node) -> Result<Value, ParseError> {
+        let name = node.text;
+        let reference = |full_name: &str| if let Some(references) = self.references {
+            references.borrow_mut().push(full_name.to_string());
+        };
 
-            0
+        if let Some(constant) = self.constants.get(name) {
+            // The first pass doesn't know it yet, the second one has to agree
+            let forward = constant.value.forward || constant.line.is_some_and(|line| line >= self.line);
+            reference(name);
+            return Ok(Value { forward, ..constant.value });
+        }
+
+        if let Some(index) = self.symbols.imports.iter().position(|(import, _)| import == name) {
+            reference(name);
+            return Ok(Value { value: 0, labels: true, forward: false, base: Base::Import(index) });
+        }
+
+        match self.symbols.label(name, self.scope, self.line) {
+            Some((section, address)) => {
+                reference(&Symbols::full_name(name, self.scope));
+                Ok(Value { value: address as i64, labels: true, forward: false, base: Base::Section(section) })
+            },
+            // Any address will do, the first pass only cares about sizes
+            None if self.first_pass => Ok(Value { value: self.address as i64, labels: false, forward: true, base: Base::Section(self.section) }),
+            None => Err(ParseError::new(node.span, AsmErrorKind::UndefinedLabel(name.to_string()))),
+        }
+    }
+
+    /// Record that the word at `offset` (from the start of what the line
+    /// emits) holds `value`, the one of `node`, when assembling a relocatable object
+    pub fn fixup(&self, node: Node, offset: u16, value: Value, pc_relative: bool) -> Result<(), ParseError> {
+        let Some(fixups) = self.fixups else {
+            return Ok(());
+        };
+
+        match value.base {
+            Base::Absolute => { },
+            // The distance between two addresses of the section doesn't change
+            Base::Section(section) if pc_relative && section == self.section => { },
+            Base::Mixed => return Err(ParseError::new(node.span, AsmErrorKind::NotRelocatable(node.text.to_string()))),
+            base => fixups.borrow_mut().push(Fixup { offset, base, pc_relative }),
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether `token` can be the name of a label
+pub fn is_identifier(token: &str) -> bool {
+    token.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && token.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Choose between the inline field of `N` bits and an extension word for the
+/// 16 bits `field` of an operand, returns the addressing mode, the inline field
+/// and the extension word.
+///
+/// Labels are only known by the second pass, so a value using one always gets
+/// an extension word : the instruction keeps the same size in both passes
+fn split_value<const N: usize>(operand: Operand, value: Value, field: u16) -> (BitInt<3>, BitInt<N>, Option<BitInt<16>>) {
+    let mode = operand.get() as u16;
+
+    match operand {
+        // Register numbers always fit
+        Operand::Register(_) |
+        Operand::PreDecrementedRegister(_) |
+        Operand::PostIncrementedRegister(_) |
+        Operand::IndirectAddress(_) => (BitInt::<3>::new(mode).unwrap(), BitInt::<N>::new(field).unwrap(), None),
+        _ => match BitInt::<N>::new(field) {
+            Some(inline) if !value.deferred() => (BitInt::<3>::new(mode).unwrap(), inline, None),
+            _ => {
+                let extended = match operand {
+                    Operand::MemoryAddress(_) => MODE_EXTENDED_ADDRESS,
+                    _ => MODE_EXTENDED_IMMEDIATE,
+                };
+                (BitInt::<3>::new(extended).unwrap(), BitInt::<N>::new(0).unwrap(), Some(BitInt::<16>::new(field).unwrap()))
+            },
         },
-        Operand::MemoryAddress(_) => {
-            let re = RE_MAP.get("ADRESS_D").unwrap();
+    }
+}
+
+/// Operand of a Bxx/BSR, the CPU adds it to the address of the next instruction.
+///
+/// A numeric `#n` is the offset from the branch itself, as an address (a label,
+/// maybe plus a number) is turned into the offset from the next instruction.
+/// The 8 bits field is sign extended by the CPU, a displacement that doesn't
+/// fit goes to an extension word.
+fn branch_value(operand: Operand, value: Value, field: u16, address: u16) -> (BitInt<3>, BitInt<8>, Option<BitInt<16>>) {
+    let extended = |displacement: u16| (
+        BitInt::<3>::new(MODE_EXTENDED_IMMEDIATE).unwrap(),
+        BitInt::<8>::new(0).unwrap(),
+        Some(BitInt::<16>::new(displacement).unwrap()),
+    );
+
+    match operand {
+        Operand::Immediate(_) if value.relocatable() => extended(field.wrapping_sub(address.wrapping_add(4))),
+        Operand::Immediate(_) => {
+            let displacement = (field as i16).wrapping_sub(2);
 
-            if let Some(captures) = re.captures(source) {
-                if let Some(hex_string) = captures.get(1) {
-                    return u8::from_str_radix(hex_string.as_str(), 16).unwrap()
-                }    
+            match i8::try_from(displacement) {
+                Ok(short) if !value.deferred() => (BitInt::<3>::new(operand.get().into()).unwrap(), BitInt::<8>::new(short as u8 as u16).unwrap(), None),
+                _ => extended(field.wrapping_sub(4)),
             }
+        },
+        _ => split_value::<8>(operand, value, field),
+    }
+}
+
+/// Encode `instruction`, placed at `context.address`
+pub fn parse(instruction: &Instruction, context: &Context) -> Result<InstructionFormat, ParseError> {
+    let operands = &instruction.operands;
+    let instruction = instruction.mnemonic;
+
+    let unknown_mnemonic = || ParseError::new(instruction.span, AsmErrorKind::UnknownMnemonic(instruction.text.to_string()));
+
+    // MOVE.L and MOVE.H are the only mnemonics with a suffix
+    let mut h_value: BitInt<1> = BitInt::<1>::new(1).unwrap();
+    let mut l_value: BitInt<1> = BitInt::<1>::new(1).unwrap();
+    let upper = context.dialect.mnemonic(instruction.text);
+    let mut mnemonic = upper.as_ref();
+
+    if let Some(suffix) = mnemonic.strip_prefix("MOVE.") {
+        match suffix {
+            "L" => h_value = BitInt::<1>::new(0).unwrap(),
+            "H" => l_value = BitInt::<1>::new(0).unwrap(),
+            _ => return Err(unknown_mnemonic()),
+        }
+        mnemonic = "MOVE";
+    }
+
+    let spec = isa::lookup(mnemonic).ok_or_else(unknown_mnemonic)?;
+    let opcode = BitInt::<5>::new(spec.opcode).unwrap();
+
+    if operands.len() != spec.operands.len() {
+        // Point at the first extra operand, or at the mnemonic when some are missing
+        let span = operands.get(spec.operands.len()).map_or(instruction.span, |operand| operand.span);
+
+        return Err(ParseError::new(span, AsmErrorKind::OperandCount {
+            mnemonic: mnemonic.to_string(),
+            expected: spec.operands.len(),
+            found: operands.len(),
+        }));
+    }
+
+    let illegal_mode = |token: &ast::Operand, operand: &OperandSpec| ParseError::new(token.span, AsmErrorKind::IllegalAddressingMode {
+        mnemonic: mnemonic.to_string(),
+        operand: token.text.to_string(),
+        role: operand.role.to_string(),
+        allowed: operand.modes,
+    });
+
+    // At most two operands, checked against the modes of the instruction
+    let mut types = [Operand::Register(0); 2];
+    for ((token, operand), op_type) in operands.iter().zip(spec.operands).zip(&mut types) {
+        *op_type = parse_operand_type(token)?;
+        if !operand.modes.contains(op_type.get().into()) {
+            return Err(illegal_mode(token, operand));
+        }
+    }
 
-            0
+    let instruction = match spec.format {
+        Format::Op0 => {
+            InstructionFormat::Format0op(_Format0opLayout {
+                opcode,
+                op_reserved: BitInt::<11>::new(0).unwrap()
+            })
         },
-        Operand::ImmediateValueDEC(_) => {
-            let re = RE_MAP.get("VALEUR_#V").unwrap();
-            
-            if let Some(captures) = re.captures(source) {
-                if let Some(token_value) = captures.get(1) {
-                  return u8::from_str_radix(token_value.as_str(), 16).unwrap()
-                }        
+        Format::Op1 => {
+            let operand_1 = &operands[0];
+            let op_type = types[0];
+            let op_value = get_op_value(operand_1, context)?;
+            let field = op_value.bits(operand_1.node(), 16)?;
+
+            // Bxx and BSR take a displacement instead of an address
+            let is_branch = matches!(spec.kind, Kind::Branch(_));
+            let (mode, inline, extension) = if is_branch {
+                branch_value(op_type, op_value, field, context.address)
+            } else {
+                split_value::<8>(op_type, op_value, field)
+            };
+            if extension.is_some() {
+                let pc_relative = is_branch && matches!(op_type, Operand::Immediate(_)) && op_value.relocatable();
+                context.fixup(operand_1.node(), 2, op_value, pc_relative)?;
             }
-            0
+
+            InstructionFormat::Format1op(_Format1opLayout {
+                opcode,
+                op_type: mode,
+                op_value: inline,
+                extension
+            })
         },
-        Operand::ImmediateValueHEX(_) => {
-            let re = RE_MAP.get("VALEUR_0xV").unwrap();
-            
-            if let Some(captures) = re.captures(source) {
-                if let Some(token_value) = captures.get(1) {
-                    return u8::from_str_radix(token_value.as_str(), 16).unwrap()
-                }        
+        Format::Op2 => {
+            let (source, destination) = (&operands[0], &operands[1]);
+
+            let source_value = get_op_value(source, context)?;
+            let source_field = source_value.bits(source.node(), 16)?;
+            let destination_value = get_op_value(destination, context)?.bits(destination.node(), 16)?;
+            let (mode, inline, extension) = split_value::<5>(types[0], source_value, source_field);
+            if extension.is_some() {
+                context.fixup(source.node(), 2, source_value, false)?;
             }
 
-            0
+            InstructionFormat::Format2op(_Format2opLayout {
+                opcode,
+                registry_dest: BitInt::<3>::new(destination_value).unwrap(),
+                op_type_source: mode,
+                op_value: inline,
+                extension
+            })
         },
-        Operand::ImmediateValueBIN(_) => {
-            let re = RE_MAP.get("VALEUR_0bV").unwrap();
-            
-            if let Some(captures) = re.captures(source) {
-                if let Some(token_value) = captures.get(1) {
-                    match u8::from_str_radix(token_value.as_str(), 16) {
-                        Ok(decimal_value) => return decimal_value,
-                        Err(_) => { },
+        Format::Move => {
+            let (source, destination) = (&operands[0], &operands[1]);
+
+            let source_type = types[0];
+            let destination_type = types[1];
+
+            let source_value = get_op_value(&operands[0], context)?;
+            let destination_value = get_op_value(&operands[1], context)?;
+
+            // There is a single value field : the register number goes with the
+            // operand that isn't an immediate/address
+            let (register, (operand, value)) = match source_type {
+                Operand::Immediate(_) |
+                Operand::MemoryAddress(_) => {
+                    if let Operand::MemoryAddress(_) = destination_type {
+                        let registers = isa::OperandSpec { role: "destination", modes: isa::WRITABLE.without(isa::ADDRESS) };
+                        return Err(illegal_mode(destination, &registers));
                     }
-                }        
-            }
-            0
+                    ((destination, destination_value), (source, source_value))
+                },
+                _ => ((source, source_value), (destination, destination_value)),
+            };
+            let registry_no = register.1.bits(register.0.node(), 16)?;
+            let field = value.bits(operand.node(), 16)?;
+            context.fixup(operand.node(), 2, value, false)?;
+
+            InstructionFormat::FormatMoveOp(_FormatMoveLayout {
+                opcode,
+                h: h_value,
+                l: l_value,
+                source_type: BitInt::<3>::new(source_type.get().into()).unwrap(),
+                destination_type: BitInt::<3>::new(destination_type.get().into()).unwrap(),
+                registry_no: BitInt::<3>::new(registry_no).unwrap(),
+                value: BitInt::<16>::new(field).unwrap()
+            })
         },
-        
-        
-        _ => 0,
+    };
+
+    Ok(instruction)
+}
+
+/// Sign and digits of a binary literal written as `#-?b<digits>`, 17 digits at most
+fn binary_literal(source: &str) -> Option<(&str, &str)> {
+    let literal = source.strip_prefix('#')?;
+    let digits = literal.strip_prefix('-').unwrap_or(literal).strip_prefix('b')?;
+    let is_binary = (1..=17).contains(&digits.len()) && digits.bytes().all(|c| c == b'0' || c == b'1');
+
+    is_binary.then(|| (&literal[..literal.len() - digits.len() - 1], digits))
+}
+
+/// Parse the digits of a literal (the `-` included), it has to fit in 16 bits,
+/// either signed or unsigned. Negative values are stored in two's complement
+fn parse_literal(source: Node, digits: &str, radix: u32) -> Result<u16, ParseError> {
+    let value = i64::from_str_radix(digits, radix)
+        .map_err(|_| ParseError::new(source.span, AsmErrorKind::BadOperand(source.text.to_string())))?;
+
+    expr::in_range(source.span, value, 16)
+}
+
+/// Same as `parse_literal` for a literal whose sign is captured apart from its digits
+fn parse_signed_literal(source: Node, (sign, digits): (&str, &str), radix: u32) -> Result<u16, ParseError> {
+    parse_literal(source, &format!("{}{}", sign, digits), radix)
+}
+
+fn get_op_value(operand: &ast::Operand, context: &Context) -> Result<Value, ParseError> {
+    let source = operand.node();
+
+    match operand.expr {
+        OperandExpr::Register(number) |
+        OperandExpr::Indirect(number) |
+        OperandExpr::PreDecrement(number) |
+        OperandExpr::PostIncrement(number) => Ok(Value::number(number.into())),
+        OperandExpr::Address(expression) => expr::evaluate(expression, context),
+        OperandExpr::Immediate(expression) => match binary_literal(source.text) {
+            // `#b101` is older than the `0b` prefix of the expressions
+            Some(literal) => parse_signed_literal(source, literal, 2).map(|value| Value::number(value.into())),
+            None => expr::evaluate(expression, context),
+        },
+        OperandExpr::Invalid => Err(ParseError::new(source.span, AsmErrorKind::BadOperand(source.text.to_string()))),
     }
 }
 
 #[derive(Clone, Copy)]
-#[allow(dead_code)]
 enum Operand {
     Register(u8),
     PreDecrementedRegister(u8),
     PostIncrementedRegister(u8),
     MemoryAddress(u8),
     IndirectAddress(u8),
-    ImmediateValueDEC(u16),
-    ImmediateValueHEX(u16),
-    ImmediateValueBIN(u16),
-    Label(u16)
+    /// `#` followed by a literal or an expression
+    Immediate(u8),
 }
 
 impl Operand {
@@ -364,64 +667,22 @@ impl Operand {
             Operand::PostIncrementedRegister(val) => *val,
             Operand::MemoryAddress(val) => *val,
             Operand::IndirectAddress(val) => *val,
-            Operand::ImmediateValueDEC(val) => (*val).try_into().unwrap(),
-            Operand::ImmediateValueHEX(val) => (*val).try_into().unwrap(),
-            Operand::ImmediateValueBIN(val) => (*val).try_into().unwrap(),
-            Operand::Label(val) => (*val).try_into().unwrap(),
+            Operand::Immediate(val) => *val,
         }
     }
 
 }
 
 
-// Could probably be better lol
-fn parse_operand_type(operand: &str) -> Operand {
-    if operand.starts_with("R") {
-        Operand::Register(0b000)
-        // BitInt::<3>::new(0b000).unwrap() // Register
-    } else
-    if operand.starts_with("-(") && operand.ends_with(")") {
-        Operand::PreDecrementedRegister(0b001)
-        // BitInt::<3>::new(0b001).unwrap() // Register pre decrement
-    } else
-    if operand.starts_with("+(") && operand.ends_with(")") {
-        Operand::PostIncrementedRegister(0b011)
-        // BitInt::<3>::new(0b011).unwrap() // Register post increment
-    } else
-    if operand.starts_with("@") {
-        Operand::MemoryAddress(0b101)
-        // BitInt::<3>::new(0b101).unwrap() // Adress
-    } else
-    
-    if operand.starts_with("(") && operand.ends_with(")") {
-        Operand::IndirectAddress(0b010)
-        // BitInt::<3>::new(0b010).unwrap() // Indirect register
-    } else
-        
-    if operand.starts_with("#") {
-        let patterns = [
-            &RE_MAP["VALEUR_#V"],
-            &RE_MAP["VALEUR_OxV"],
-            &RE_MAP["VALEUR_0bV"],
-            &RE_MAP["VALEUR_TEXTE"],
-        ];
-            
-        let mut i = 0;
-        let mut _result: *const u8 = null();
-        for re in patterns {
-            if let Some(captures) = re.captures(&format!("{}", operand)) {
-                if let Some(token_value) = captures.get(1) {
-                    println!("token_value {}", token_value.as_str());
-                }
-            }
-            i = i + 1;
-        };
-
-        Operand::ImmediateValueBIN(0b000)
-    } else {
-        alert("Unknown operand type");
-        panic!("Unknown operand type")
+/// The addressing mode of `operand`
+fn parse_operand_type(operand: &ast::Operand) -> Result<Operand, ParseError> {
+    match operand.expr {
+        OperandExpr::Register(_) => Ok(Operand::Register(0b000)),
+        OperandExpr::PreDecrement(_) => Ok(Operand::PreDecrementedRegister(0b001)),
+        OperandExpr::Indirect(_) => Ok(Operand::IndirectAddress(0b010)),
+        OperandExpr::PostIncrement(_) => Ok(Operand::PostIncrementedRegister(0b011)),
+        OperandExpr::Immediate(_) => Ok(Operand::Immediate(0b100)),
+        OperandExpr::Address(_) => Ok(Operand::MemoryAddress(0b101)),
+        OperandExpr::Invalid => Err(ParseError::new(operand.span, AsmErrorKind::BadOperand(operand.text.to_string()))),
     }
 }
-
-
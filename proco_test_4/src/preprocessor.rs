@@ -0,0 +1,685 @@
+//! Includes, macros and conditional blocks, expanded before the passes of
+//! the assembler.
+//!
+//! `.include "file.asm"` is replaced by the lines of the file, looked for
+//! by the `Loader` (next to the including file, then in the include
+//! directories). A file can't include itself, even through other files.
+//!
+//! Macros are defined and called this way :
+//!
+//! ```text
+//! .macro SAVE first, second
+//!     PUSH \first
+//!     PUSH \second
+//! .endm
+//!
+//!     SAVE R0, R1
+//! ```
+//!
+//! A macro is called like an instruction, once it is defined. Its parameters
+//! are written `\name` in its body, they are replaced everywhere (strings
+//! included) by the arguments of the call. The body may call other macros,
+//! and define macros itself.
+//!
+//! The labels defined in a body are local to each expansion : `LOOP` becomes
+//! `LOOP__1` in the first expansion, `LOOP__2` in the second one, ... along
+//! with the references to it within the body. The numeric labels are left as
+//! they are, and the global labels of a body don't open a scope for the local
+//! labels (see `parser::Symbols`).
+//!
+//! Conditional blocks keep the lines of the first true condition :
+//!
+//! ```text
+//! .if DEBUG & TRACE        | an expression of the constants defined before
+//! .elif ...                 | (in the sources or by a `-D` of the command line)
+//! .else
+//! .endif
+//! .ifdef DEBUG              | DEBUG is a constant or a label defined before
+//! .ifndef DEBUG             | it isn't
+//! ```
+//!
+//! The addresses of the labels aren't known yet, the conditions can't use
+//! them. Only the conditional directives of a skipped block are looked at.
+//!
+//! The pseudo-instructions (`CLR R0`, `CALL PRINT`...) are expanded here too,
+//! as built-in macros whose body is given by `isa::PSEUDO`. A macro of the
+//! same name takes their place.
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::iter;
+use std::path::{Path, PathBuf};
+
+use crate::assembler::{Settings, Source, SourceFile};
+use crate::error::{AsmError, AsmErrorKind, Expansion, Location, ParseError, Span};
+use crate::expr::{self, Value};
+use crate::ast::Node;
+use crate::lexer::Lexer;
+use crate::parser::{Constant, Constants, Context, Dialect, Symbols};
+use crate::{ast, directive, isa, parser};
+
+/// Calls (or includes) deeper than this are taken as a macro expanding itself
+/// (or a file including itself)
+const MAX_DEPTH: usize = 32;
+
+/// The directives of the conditional blocks
+const CONDITIONALS: [&str; 6] = [".if", ".ifdef", ".ifndef", ".elif", ".else", ".endif"];
+
+/// Reads the files of the `.include` directives
+pub trait Loader {
+    /// The name and the text of the file `path`, included by the file `from`
+    fn load(&self, path: &str, from: &str) -> io::Result<(String, String)>;
+
+    /// What tells two files apart, `sub/../a.asm` being `a.asm`
+    fn identity(&self, name: &str) -> PathBuf {
+        PathBuf::from(name)
+    }
+}
+
+/// Looks for the included files in the directory of the including file, then
+/// in `directories` (the `-I` of the command line)
+#[derive(Default)]
+pub struct FileLoader {
+    pub directories: Vec<PathBuf>,
+}
+
+impl Loader for FileLoader {
+    fn load(&self, path: &str, from: &str) -> io::Result<(String, String)> {
+        let here = Path::new(from).parent().map(Path::to_path_buf).unwrap_or_default();
+
+        for directory in iter::once(&here).chain(&self.directories) {
+            let candidate = directory.join(path);
+            match fs::read_to_string(&candidate) {
+                Ok(text) => return Ok((candidate.display().to_string(), text)),
+                Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+                Err(error) => return Err(error),
+            }
+        }
+
+        Err(io::Error::new(io::ErrorKind::NotFound, "not found in the include directories"))
+    }
+
+    fn identity(&self, name: &str) -> PathBuf {
+        fs::canonicalize(name).unwrap_or_else(|_| PathBuf::from(name))
+    }
+}
+
+/// Call of a macro, by a line of the sources or of another macro
+#[derive(Debug, Clone, PartialEq)]
+pub struct Call {
+    pub name: String,
+    /// Index of the file of the call
+    pub file: usize,
+    /// Index of the line of the call (0 based)
+    pub line: usize,
+}
+
+/// A line to assemble, once the macros are expanded
+#[derive(Debug, Clone)]
+pub struct Line<'a> {
+    /// Index of the file the text comes from, the one defining the macro for
+    /// an expanded line
+    pub file: usize,
+    /// Index of the line in its file (0 based)
+    pub line: usize,
+    pub text: Cow<'a, str>,
+    /// Macro calls the line has been expanded from, the innermost first
+    pub calls: Vec<Call>,
+}
+
+impl Line<'_> {
+    /// Where the line stands in the sources : the outermost call for an
+    /// expanded line
+    pub fn site(&self) -> (usize, usize) {
+        self.calls.last().map_or((self.file, self.line), |call| (call.file, call.line))
+    }
+
+    /// Where the line stands in the sources, by the name of its file
+    pub fn location(&self, files: &[SourceFile]) -> Location {
+        let (file, line) = self.site();
+        Location { file: files[file].name.clone(), line: line + 1 }
+    }
+
+    /// Error on the part `span` of the text of the line
+    pub fn error(&self, files: &[SourceFile], span: Span, kind: AsmErrorKind) -> AsmError {
+        let mut error = AsmError::new(&files[self.file].name, self.line + 1, &self.text, span, kind);
+        error.expansions = self.calls.iter()
+            .map(|call| Expansion { name: call.name.clone(), file: files[call.file].name.clone(), line: call.line + 1 })
+            .collect();
+        error
+    }
+}
+
+#[derive(Clone)]
+struct Macro<'a> {
+    /// The `.macro` line
+    location: Location,
+    parameters: Vec<String>,
+    body: Vec<Line<'a>>,
+}
+
+/// A conditional block being read
+struct Conditional<'a> {
+    /// The line opening it, along with its directive
+    line: Line<'a>,
+    directive: &'static str,
+    keyword: Span,
+    /// Whether the lines of the current branch are kept
+    active: bool,
+    /// Whether a branch has been (or can't be) kept, the next ones are skipped
+    taken: bool,
+    after_else: bool,
+}
+
+/// What a line means to the expander
+enum Statement {
+    Definition { name: String, parameters: Vec<String> },
+    End,
+    Call { name: String, arguments: Vec<String> },
+    /// A pseudo-instruction, along with its expansion
+    Pseudo { name: &'static str, body: Vec<String> },
+    Include { path: String, span: Span },
+    /// A call or an include whose error is already reported
+    Reported,
+    Other,
+}
+
+fn is_word(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// The directive or mnemonic of a whole line
+fn keyword_of(text: &str, dialect: Dialect) -> &str {
+    ast::parse_line(text, dialect).last()
+        .and_then(ast::Statement::keyword)
+        .map_or("", |(keyword, _)| keyword.text)
+}
+
+/// The name of a macro and its first parameter, both in the first argument of `.macro`
+fn macro_name(argument: Node) -> (Node, Option<Node>) {
+    let Some(name) = Lexer::new(argument.text).next() else {
+        return (argument, None);
+    };
+    let start = argument.span.start;
+    let rest = argument.text[name.span.end..].trim_start();
+    let parameter = (!rest.is_empty())
+        .then(|| Node { text: rest, span: Span { start: argument.span.end - rest.len(), end: argument.span.end } });
+
+    (Node { text: name.text, span: Span { start: start + name.span.start, end: start + name.span.end } }, parameter)
+}
+
+/// `text` with every `\parameter` replaced by its argument
+fn substitute(text: &str, parameters: &[String], arguments: &[String]) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('\\') {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let end = after.find(|c: char| !is_word(c)).unwrap_or(after.len());
+
+        match parameters.iter().position(|parameter| *parameter == after[..end]) {
+            Some(index) => result.push_str(&arguments[index]),
+            None => result.push_str(&rest[start..start + 1 + end]),
+        }
+        rest = &after[end..];
+    }
+
+    result.push_str(rest);
+    result
+}
+
+/// `text` with the words of `locals` renamed, outside of the strings, of the
+/// comment and of the `\parameters`
+fn rename(text: &str, locals: &HashMap<&str, String>) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.char_indices().peekable();
+    let mut quote = None;
+
+    while let Some((i, c)) = chars.next() {
+        if let Some(opening) = quote {
+            result.push(c);
+            if c == '\\' {
+                if let Some((_, escaped)) = chars.next() {
+                    result.push(escaped);
+                }
+            } else if c == opening {
+                quote = None;
+            }
+            continue;
+        }
+
+        match c {
+            '"' | '\'' => {
+                quote = Some(c);
+                result.push(c);
+            },
+            ';' => {
+                result.push_str(&text[i..]);
+                break;
+            },
+            // A local label keeps its `.`
+            _ if c == '\\' || is_word(c) || (c == '.' && chars.peek().is_some_and(|(_, c)| is_word(*c))) => {
+                let mut end = i + c.len_utf8();
+                while let Some(&(j, c)) = chars.peek().filter(|(_, c)| is_word(*c)) {
+                    end = j + c.len_utf8();
+                    chars.next();
+                }
+
+                let word = &text[i..end];
+                result.push_str(locals.get(word).map_or(word, String::as_str));
+            },
+            _ => result.push(c),
+        }
+    }
+
+    result
+}
+
+/// The line holding the label of `line`, the part before `end`
+fn label_line<'a>(line: &Line<'a>, end: usize) -> Line<'a> {
+    let text = match &line.text {
+        Cow::Borrowed(text) => Cow::Borrowed(text[..end].trim_end()),
+        Cow::Owned(text) => Cow::Owned(text[..end].trim_end().to_string()),
+    };
+
+    Line { text, ..line.clone() }
+}
+
+/// The lines of the sources once preprocessed
+pub struct Preprocessed<'a> {
+    pub lines: Vec<Line<'a>>,
+    /// The given sources, then the included files in the order of their inclusion
+    pub files: Vec<SourceFile>,
+    /// The errors, each one along with the number of lines before it
+    pub errors: Vec<(usize, AsmError)>,
+}
+
+struct Expander<'l, 'a> {
+    loader: &'l dyn Loader,
+    dialect: Dialect,
+    /// The constants the conditions can use
+    constants: Constants,
+    /// The names `.ifdef` knows : labels and constants
+    defined: HashSet<String>,
+    files: Vec<SourceFile>,
+    macros: HashMap<String, Macro<'a>>,
+    lines: Vec<Line<'a>>,
+    errors: Vec<(usize, AsmError)>,
+    /// Expansions so far, their number makes the local labels unique
+    expansions: usize,
+}
+
+impl<'a> Expander<'_, 'a> {
+    fn error(&mut self, line: &Line, span: Span, kind: AsmErrorKind) {
+        let error = line.error(&self.files, span, kind);
+        self.errors.push((self.lines.len(), error));
+    }
+
+    /// Report the wrong number of `arguments` given to `keyword`, which takes `expected`
+    fn count(&mut self, line: &Line, keyword: Node, mnemonic: &str, arguments: &[Node], expected: usize) {
+        let span = arguments.get(expected).map_or(keyword.span, |argument| argument.span);
+        let kind = AsmErrorKind::OperandCount { mnemonic: mnemonic.to_string(), expected, found: arguments.len() };
+        self.error(line, span, kind);
+    }
+
+    /// Value of `expression`, made of the constants defined so far
+    fn evaluate(&self, expression: Node) -> Result<Value, ParseError> {
+        let symbols = Symbols::default();
+        let context = Context { dialect: self.dialect, line: self.lines.len(), ..Context::new(&symbols, &self.constants) };
+        expr::evaluate(expression, &context)
+    }
+
+    /// Keep `line`, along with the names it defines
+    fn push(&mut self, line: Line<'a>) {
+        for statement in ast::parse_line(&line.text, self.dialect) {
+            let definition = match &statement {
+                ast::Statement::Label(label) => {
+                    self.defined.insert(label.text.to_string());
+                    continue;
+                },
+                ast::Statement::Directive(directive) => directive::constant(directive),
+                ast::Statement::Instruction(_) => None,
+            };
+            let Some(Ok((name, expression))) = definition else {
+                continue;
+            };
+            self.defined.insert(name.text.to_string());
+
+            // The constants using labels are left to the assembler
+            if let Ok(value) = self.evaluate(expression) {
+                self.constants.insert(name.text.to_string(), Constant { value, line: Some(self.lines.len()) });
+            }
+        }
+
+        self.lines.push(line);
+    }
+
+    /// Whether the condition of the `directive` of `line` is true
+    fn condition(&mut self, line: &Line, directive: &str, keyword: Node, arguments: &[Node]) -> bool {
+        let argument = match arguments {
+            [] => Node { text: "", span: Span::at(keyword.span.end) },
+            [argument] => *argument,
+            _ => {
+                self.count(line, keyword, directive, arguments, 1);
+                return false;
+            },
+        };
+
+        if directive == ".ifdef" || directive == ".ifndef" {
+            if !parser::is_identifier(argument.text) {
+                self.error(line, argument.span, AsmErrorKind::BadOperand(argument.text.to_string()));
+                return false;
+            }
+            return self.defined.contains(argument.text) == (directive == ".ifdef");
+        }
+
+        match self.evaluate(argument) {
+            Ok(value) => value.value != 0,
+            Err(error) => {
+                self.error(line, error.span, error.kind);
+                false
+            },
+        }
+    }
+
+    /// Follow the conditional directive of `line`, returns false when it isn't one
+    fn conditional(&mut self, blocks: &mut Vec<Conditional<'a>>, line: &Line<'a>, keyword: Node, arguments: &[Node]) -> bool {
+        let Some(&directive) = CONDITIONALS.iter().find(|directive| keyword.text.eq_ignore_ascii_case(directive)) else {
+            return false;
+        };
+        // Whether the block holding the directive is kept
+        let enclosing = blocks.last().is_none_or(|block| block.active);
+
+        match directive {
+            ".if" | ".ifdef" | ".ifndef" => {
+                let active = enclosing && self.condition(line, directive, keyword, arguments);
+                blocks.push(Conditional {
+                    line: line.clone(),
+                    directive,
+                    keyword: keyword.span,
+                    active,
+                    taken: active || !enclosing,
+                    after_else: false,
+                });
+            },
+            ".elif" | ".else" => {
+                let Some(block) = blocks.last_mut() else {
+                    self.error(line, keyword.span, AsmErrorKind::UnmatchedConditional(directive.to_string()));
+                    return true;
+                };
+                if block.after_else {
+                    self.error(line, keyword.span, AsmErrorKind::ConditionalAfterElse(directive.to_string()));
+                    return true;
+                }
+
+                block.active = !block.taken && (directive == ".else" || self.condition(line, ".if", keyword, arguments));
+                block.taken |= block.active;
+                block.after_else = directive == ".else";
+            },
+            ".endif" => {
+                if blocks.pop().is_none() {
+                    self.error(line, keyword.span, AsmErrorKind::UnmatchedConditional(directive.to_string()));
+                }
+            },
+            _ => unreachable!("{} isn't a conditional directive", directive),
+        }
+
+        true
+    }
+
+    /// What `line` is, by its `keyword` and its `arguments`
+    fn statement(&mut self, line: &Line, keyword: Node, arguments: &[Node]) -> Statement {
+        if keyword.text.eq_ignore_ascii_case(".macro") {
+            let (name, first) = match arguments.first() {
+                Some(&argument) => macro_name(argument),
+                None => (Node { text: "", span: Span::at(keyword.span.end) }, None),
+            };
+            let parameters: Vec<Node> = first.into_iter().chain(arguments.iter().skip(1).copied()).collect();
+
+            if let Some(bad) = parameters.iter().find(|parameter| !parser::is_identifier(parameter.text)) {
+                self.error(line, bad.span, AsmErrorKind::BadOperand(bad.text.to_string()));
+            } else if !parser::is_identifier(name.text) {
+                self.error(line, name.span, AsmErrorKind::BadOperand(name.text.to_string()));
+            } else if self.macros.contains_key(name.text) || isa::lookup(&self.dialect.mnemonic(name.text)).is_some() {
+                let previous = self.macros.get(name.text).map(|defined| defined.location.clone());
+                self.error(line, name.span, AsmErrorKind::DuplicateLabel { name: name.text.to_string(), previous });
+            } else {
+                let parameters = parameters.iter().map(|parameter| parameter.text.to_string()).collect();
+                return Statement::Definition { name: name.text.to_string(), parameters };
+            }
+
+            // The body is still skipped, up to its .endm
+            return Statement::Definition { name: String::new(), parameters: Vec::new() };
+        }
+
+        if keyword.text.eq_ignore_ascii_case(".endm") {
+            return Statement::End;
+        }
+
+        if keyword.text.eq_ignore_ascii_case(".include") {
+            let [argument] = arguments else {
+                self.count(line, keyword, ".include", arguments, 1);
+                return Statement::Reported;
+            };
+
+            return match argument.text.strip_prefix('"').and_then(|path| path.strip_suffix('"')) {
+                Some(path) if argument.text.len() >= 2 => Statement::Include { path: path.to_string(), span: argument.span },
+                _ => {
+                    self.error(line, argument.span, AsmErrorKind::UnterminatedString);
+                    Statement::Reported
+                },
+            };
+        }
+
+        match self.macros.get(keyword.text) {
+            Some(called) => {
+                let expected = called.parameters.len();
+                if arguments.len() != expected {
+                    self.count(line, keyword, keyword.text, arguments, expected);
+                    return Statement::Reported;
+                }
+
+                let arguments = arguments.iter().map(|argument| argument.text.to_string()).collect();
+                Statement::Call { name: keyword.text.to_string(), arguments }
+            },
+            None => match isa::pseudo(&self.dialect.mnemonic(keyword.text)) {
+                Some(pseudo) => self.pseudo(line, keyword, pseudo, arguments),
+                None => Statement::Other,
+            },
+        }
+    }
+
+    /// The expansion of the pseudo-instruction `pseudo`, written `keyword`
+    fn pseudo(&mut self, line: &Line, keyword: Node, pseudo: &'static isa::Pseudo, arguments: &[Node]) -> Statement {
+        if arguments.len() != pseudo.operands.len() {
+            self.count(line, keyword, pseudo.mnemonic, arguments, pseudo.operands.len());
+            return Statement::Reported;
+        }
+
+        let texts: Vec<&str> = arguments.iter().map(|argument| argument.text).collect();
+        match pseudo.expand(&texts) {
+            Ok(body) => Statement::Pseudo { name: pseudo.mnemonic, body },
+            Err(i) => {
+                self.error(line, arguments[i].span, AsmErrorKind::BadOperand(texts[i].to_string()));
+                Statement::Reported
+            },
+        }
+    }
+
+    /// Expand the call of `name` made by `line`, written `keyword`
+    fn expand(&mut self, line: &Line<'a>, keyword: Span, name: &str, arguments: &[String], depth: usize) {
+        if depth >= MAX_DEPTH {
+            self.error(line, keyword, AsmErrorKind::MacroRecursion(name.to_string()));
+            return;
+        }
+
+        let called = self.macros[name].clone();
+        self.expansions += 1;
+
+        let dialect = self.dialect;
+        let locals: HashMap<&str, String> = called.body.iter()
+            .filter_map(|body_line| ast::parse_line(&body_line.text, dialect).first().and_then(ast::Statement::label))
+            .map(|label| label.text)
+            // The numeric labels can already be defined many times
+            .filter(|label| !label.starts_with(|c: char| c.is_ascii_digit()))
+            .map(|label| (label, format!("{}__{}", label, self.expansions)))
+            .collect();
+
+        let mut calls = vec![Call { name: name.to_string(), file: line.file, line: line.line }];
+        calls.extend(line.calls.iter().cloned());
+
+        let expanded = called.body.iter()
+            .map(|body_line| Line {
+                file: body_line.file,
+                line: body_line.line,
+                text: Cow::Owned(substitute(&rename(&body_line.text, &locals), &called.parameters, arguments)),
+                calls: calls.clone(),
+            })
+            .collect();
+
+        self.process(expanded, depth + 1);
+    }
+
+    /// Replace the `.include` of `line` by the lines of the file, `span` being its argument
+    fn include(&mut self, line: &Line<'a>, span: Span, path: &str, depth: usize) {
+        if depth >= MAX_DEPTH {
+            self.error(line, span, AsmErrorKind::IncludeCycle(path.to_string()));
+            return;
+        }
+
+        let (name, text) = match self.loader.load(path, &self.files[line.file].name) {
+            Ok(loaded) => loaded,
+            Err(error) => {
+                self.error(line, span, AsmErrorKind::IncludeFailed { path: path.to_string(), reason: error.to_string() });
+                return;
+            },
+        };
+
+        // The file can't be one of the files including this line
+        let identity = self.loader.identity(&name);
+        let mut includer = Some(line.file);
+        while let Some(file) = includer {
+            if self.loader.identity(&self.files[file].name) == identity {
+                self.error(line, span, AsmErrorKind::IncludeCycle(name));
+                return;
+            }
+            includer = self.files[file].included.map(|(file, _)| file);
+        }
+
+        let file = self.files.len();
+        let dialect = self.dialect;
+        let lines = text.lines()
+            .map(|text| if dialect.is_comment(text) { "" } else { text })
+            .enumerate()
+            .map(|(i, text)| Line { file, line: i, text: Cow::Owned(text.to_string()), calls: line.calls.clone() })
+            .collect();
+
+        self.files.push(SourceFile { name, text, included: Some((line.file, line.line)) });
+        self.process(lines, depth + 1);
+    }
+
+    fn process(&mut self, lines: Vec<Line<'a>>, depth: usize) {
+        let mut lines = lines.into_iter();
+        let mut blocks = Vec::new();
+
+        while let Some(line) = lines.next() {
+            let statements = ast::parse_line(&line.text, self.dialect);
+            let label = statements.first().and_then(ast::Statement::label);
+            let (keyword, arguments) = statements.last()
+                .and_then(ast::Statement::keyword)
+                .unwrap_or((Node { text: "", span: Span::at(0) }, Vec::new()));
+
+            if self.conditional(&mut blocks, &line, keyword, &arguments) || blocks.last().is_some_and(|block| !block.active) {
+                continue;
+            }
+
+            let statement = self.statement(&line, keyword, &arguments);
+
+            if !matches!(statement, Statement::Other) && label.is_some() {
+                // The label of a definition or of a call stays where it is
+                self.push(label_line(&line, keyword.span.start));
+            }
+
+            match statement {
+                Statement::Definition { name, parameters } => {
+                    // The body goes up to the matching .endm
+                    let mut body = Vec::new();
+                    let mut nested = 0;
+                    let mut closed = false;
+
+                    for body_line in lines.by_ref() {
+                        let keyword = keyword_of(&body_line.text, self.dialect);
+
+                        if keyword.eq_ignore_ascii_case(".macro") {
+                            nested += 1;
+                        } else if keyword.eq_ignore_ascii_case(".endm") {
+                            if nested == 0 {
+                                closed = true;
+                                break;
+                            }
+                            nested -= 1;
+                        }
+                        body.push(body_line);
+                    }
+
+                    if !closed {
+                        self.error(&line, keyword.span, AsmErrorKind::UnterminatedMacro(name));
+                    } else if !name.is_empty() {
+                        let location = line.location(&self.files);
+                        self.macros.insert(name, Macro { location, parameters, body });
+                    }
+                },
+                Statement::End => self.error(&line, keyword.span, AsmErrorKind::UnexpectedEndm),
+                Statement::Call { name, arguments } => self.expand(&line, keyword.span, &name, &arguments, depth),
+                Statement::Include { path, span } => self.include(&line, span, &path, depth),
+                Statement::Pseudo { name, body } => {
+                    let mut calls = vec![Call { name: name.to_string(), file: line.file, line: line.line }];
+                    calls.extend(line.calls.iter().cloned());
+
+                    for text in body {
+                        self.push(Line { file: line.file, line: line.line, text: Cow::Owned(text), calls: calls.clone() });
+                    }
+                },
+                Statement::Reported => { },
+                Statement::Other => self.push(line),
+            }
+        }
+
+        for block in blocks {
+            self.error(&block.line, block.keyword, AsmErrorKind::UnterminatedConditional(block.directive.to_string()));
+        }
+    }
+}
+
+/// Every line of the sources, once the includes and the macros are expanded
+pub fn preprocess<'a>(sources: &[Source<'a>], settings: &Settings) -> Preprocessed<'a> {
+    let lines = sources.iter()
+        .enumerate()
+        .flat_map(|(file, source)| source.text.lines()
+            .map(|text| if settings.dialect.is_comment(text) { "" } else { text })
+            .enumerate()
+            .map(move |(i, text)| Line { file, line: i, text: Cow::Borrowed(text), calls: Vec::new() }))
+        .collect();
+
+    let mut expander = Expander {
+        loader: settings.loader,
+        dialect: settings.dialect,
+        constants: settings.defines.iter()
+            .map(|(name, value)| (name.clone(), Constant { value: Value::number(*value), line: None }))
+            .collect(),
+        defined: settings.defines.iter().map(|(name, _)| name.clone()).collect(),
+        files: sources.iter()
+            .map(|source| SourceFile { name: source.file.to_string(), text: source.text.to_string(), included: None })
+            .collect(),
+        macros: HashMap::new(),
+        lines: Vec::new(),
+        errors: Vec::new(),
+        expansions: 0,
+    };
+    expander.process(lines, 0);
+
+    Preprocessed { lines: expander.lines, files: expander.files, errors: expander.errors }
+}
@@ -0,0 +1,141 @@
+//! Symbol files, written next to the binary images so that the tools working
+//! on an image (the disassembler...) can name its addresses.
+//!
+//! One symbol per line : its value in hexadecimal, its name, whether it is a
+//! label or a constant, its section, the line defining it (`-D` for the
+//! command line) then the lines using it, or `unused` :
+//!
+//! ```text
+//! ; value name  kind     section defined    references
+//! 0004  SIZE  constant abs     prog.asm:1 prog.asm:4
+//! 0010  START label    text    prog.asm:3 unused
+//! 0014  LOOP  label    text    prog.asm:4 prog.asm:6 prog.asm:9
+//! ```
+//!
+//! The lines starting with a `;` are comments. The files holding only the
+//! address and the name of each label (the older ones, and the ones of the
+//! linker) are read as well.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+use crate::directive::Section;
+use crate::error::{FileError, Location};
+use crate::parser::SymbolTable;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Label,
+    Constant,
+    /// A name of another object (`.extern`), its value is left to the linker
+    Import,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Label => "label",
+            Kind::Constant => "constant",
+            Kind::Import => "import",
+        }
+    }
+}
+
+/// A label or a constant of a program
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub value: u16,
+    pub kind: Kind,
+    /// The section of an address, `None` for a number (or an import)
+    pub section: Option<Section>,
+    /// `None` for the constants of the command line
+    pub defined: Option<Location>,
+    /// The lines using the symbol, in the order of the program
+    pub references: Vec<Location>,
+}
+
+/// The symbols sorted by address (then by name)
+pub fn sorted(symbols: &SymbolTable) -> Vec<(&str, u16)> {
+    let mut sorted: Vec<(&str, u16)> = symbols.iter()
+        .map(|(name, address)| (name.as_str(), *address))
+        .collect();
+    sorted.sort_by_key(|(name, address)| (*address, *name));
+
+    sorted
+}
+
+pub fn write_symbols<W: Write>(writer: &mut W, symbols: &[Symbol]) -> io::Result<()> {
+    let mut sorted: Vec<&Symbol> = symbols.iter().collect();
+    sorted.sort_by_key(|symbol| (symbol.value, &symbol.name));
+    let width = sorted.iter().map(|symbol| symbol.name.len()).max().unwrap_or(0).max(4);
+
+    writeln!(writer, "; value {:width$} kind     section defined    references", "name")?;
+    for symbol in sorted {
+        let defined = symbol.defined.as_ref().map_or("-D".to_string(), Location::to_string);
+        let references: Vec<String> = symbol.references.iter().map(Location::to_string).collect();
+        let references = if references.is_empty() { "unused".to_string() } else { references.join(" ") };
+
+        let section = match (symbol.kind, symbol.section) {
+            (Kind::Import, _) => "-",
+            (_, Some(section)) => section.name(),
+            (_, None) => "abs",
+        };
+
+        writeln!(writer, "{:04x}  {:width$} {:8} {:7} {:10} {}",
+            symbol.value, symbol.name, symbol.kind.name(), section, defined, references)?;
+    }
+
+    writer.flush()
+}
+
+pub fn write_symbol_file<P: AsRef<Path>>(path: P, symbols: &[Symbol]) -> io::Result<()> {
+    let mut file = File::create(path)?;
+
+    write_symbols(&mut file, symbols)
+}
+
+/// Only the address and the name of each label, for the programs built by the
+/// linker which knows nothing of the sources
+pub fn write_labels<W: Write>(writer: &mut W, labels: &SymbolTable) -> io::Result<()> {
+    for (name, address) in sorted(labels) {
+        writeln!(writer, "{:04x} {}", address, name)?;
+    }
+
+    writer.flush()
+}
+
+/// Read back the addresses of the labels `write_symbols` wrote, blank lines
+/// and comments are skipped
+pub fn read_symbols<R: BufRead>(reader: R) -> io::Result<SymbolTable> {
+    let mut symbols = SymbolTable::new();
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() || line.trim_start().starts_with(';') {
+            continue;
+        }
+
+        let invalid = || io::Error::from(FileError::SymbolLine { line: i + 1, text: line.clone() });
+
+        let mut fields = line.split_whitespace();
+        let (Some(address), Some(name)) = (fields.next(), fields.next()) else {
+            return Err(invalid());
+        };
+        let address = u16::from_str_radix(address, 16).map_err(|_| invalid())?;
+
+        // A constant isn't an address, an import isn't known
+        match fields.next() {
+            None | Some("label") => { symbols.insert(name.to_string(), address); },
+            Some("constant" | "import") => { },
+            Some(_) => return Err(invalid()),
+        }
+    }
+
+    Ok(symbols)
+}
+
+pub fn read_symbol_file<P: AsRef<Path>>(path: P) -> io::Result<SymbolTable> {
+    read_symbols(BufReader::new(File::open(path)?))
+}
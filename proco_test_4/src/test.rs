@@ -1,3 +1,22 @@
+use crate::cpu::{Cpu, PC, SP};
+use crate::error::{AsmError, AsmErrorKind, FileError, Location, Span};
+use crate::memory::Memory;
+use crate::directive::Section;
+use crate::object::{Export, Object, Relocation, Target};
+use crate::preprocessor::{FileLoader, Loader};
+use crate::parser::Dialect;
+use crate::catalog::{self, Language};
+use crate::{assembler, game, isa, symbols, utils};
+
+fn assemble(code: &str) -> assembler::Program {
+    assembler::assemble("test.asm", code, game::RESET_ADDR).unwrap()
+}
+
+/// Assemble `code` and boot a CPU on it, the first instruction is at RESET_ADDR
+fn setup_simple_cpu(code: &str) -> (Cpu, Memory) {
+    game::boot(&assemble(code).words())
+}
+
 #[test]
 fn jmp() {
     // add will be skipped by the first jump and the the indirect jmp will
@@ -710,3 +729,953 @@ fn lsr() {
     assert!(!cpu.get_n());
 }
 
+
+#[test]
+fn labels() {
+    let program = assemble("
+        START:  move #0, r0
+        LOOP:   add #1, r0
+                cmp #3, r0
+                bne #LOOP
+                jsr #ROUTINE
+                jmp #END
+        ROUTINE:
+                move #0xbeef, r1
+                rts
+        END:    not r2
+        ");
+    assert_eq!(program.symbols["START"], 0x10);
+    assert_eq!(program.symbols["LOOP"], 0x14);
+    // bne/jsr/jmp carry their label in an extension word
+    assert_eq!(program.symbols["ROUTINE"], 0x24);
+    assert_eq!(program.symbols["END"], 0x2a);
+
+    let (mut cpu, mut memory) = game::boot(&program.words());
+    let mem = &mut memory;
+    cpu.registers[SP] = 0xf0;
+    for _ in 0..100 {
+        if cpu.registers[PC] == program.symbols["END"] {
+            break;
+        }
+        cpu.step(mem);
+    }
+    assert_eq!(cpu.registers[PC], 0x2a);
+    assert_eq!(cpu.registers[0], 3);
+    assert_eq!(cpu.registers[1], 0xbeef);
+    assert_eq!(cpu.registers[SP], 0xf0);
+}
+
+#[test]
+fn extension_words() {
+    let program = assemble("
+        add #0xfffe, r0
+        add #3, r0
+        push @0x1234
+        jmp #0x10
+        ");
+    assert_eq!(program.words(), vec![
+        0x18c0, 0xfffe,
+        0x1883,
+        0x0f00, 0x1234,
+        0xd410,
+    ]);
+
+    let (mut cpu, mut memory) = setup_simple_cpu("
+        add #0xfffe, r0
+        add @0x1234, r0
+        ");
+    memory.write_u16(0x1234, 3);
+    cpu.step(&mut memory);
+    assert_eq!(cpu.registers[0], 0xfffe);
+    cpu.step(&mut memory);
+    assert_eq!(cpu.registers[0], 1);
+    assert!(cpu.get_c());
+}
+
+#[test]
+fn signed_immediates() {
+    let program = assemble("
+        add #-1, r0
+        add #b101, r0
+        push #-0x10
+        sub #-32768, r1
+        ");
+    assert_eq!(program.words(), vec![
+        0x18c0, 0xffff,
+        0x1885,
+        0x0e00, 0xfff0,
+        0x29c0, 0x8000,
+    ]);
+
+    let (mut cpu, mut memory) = setup_simple_cpu("add #-3, r0");
+    cpu.registers[0] = 5;
+    cpu.step(&mut memory);
+    assert_eq!(cpu.registers[0], 2);
+}
+
+#[test]
+fn long_branches() {
+    // Too far for the 8 bits displacement, still relative to the branch itself
+    for (code, pc) in [("bra #0x100", 0x110u16), ("bsr #-0x10", 0x0), ("bra #-128", 0xff90)] {
+        let (mut cpu, mut memory) = setup_simple_cpu(code);
+        cpu.registers[SP] = 0xf0;
+        cpu.step(&mut memory);
+        assert_eq!(cpu.registers[PC], pc, "{}", code);
+    }
+
+    // The displacement of a short branch is encoded from the next instruction
+    assert_eq!(assemble("bra #4").words(), vec![0x9402]);
+    assert_eq!(assemble("bra #130").words(), vec![0x9600, 0x007e]);
+}
+
+#[test]
+fn literal_range() {
+    let errors = assemble_errors("ADD #65536, R0\nADD #-32769, R0\nPUSH #0x10000");
+    let kinds: Vec<AsmErrorKind> = errors.into_iter()
+        .map(|error| error.kind)
+        .collect();
+    assert_eq!(kinds, vec![
+        AsmErrorKind::ValueOutOfRange { value: 65536, bits: 16 },
+        AsmErrorKind::ValueOutOfRange { value: -32769, bits: 16 },
+        AsmErrorKind::ValueOutOfRange { value: 0x10000, bits: 16 },
+    ]);
+}
+
+#[test]
+fn addressing_modes_come_from_the_isa() {
+    let errors = assemble_errors("ADD R0, @0x10\nPOP #1\nMOVE #1, @0x10\nRTS R0\nPUSH");
+    let messages: Vec<String> = errors.iter()
+        .map(|error| error.to_string())
+        .collect();
+    assert_eq!(messages, [
+        "test.asm:1:9: ADD can't use @0x10 as destination, its destination has to be one of Rn",
+        "test.asm:2:5: POP can't use #1 as destination, its destination has to be one of Rn, -(Rn), (Rn), (Rn)+, @x",
+        "test.asm:3:10: MOVE can't use @0x10 as destination, its destination has to be one of Rn, -(Rn), (Rn), (Rn)+",
+        "test.asm:4:5: RTS takes 0 operand(s), found 1",
+        "test.asm:5:1: PUSH takes 1 operand(s), found 0",
+    ]);
+}
+
+#[test]
+fn columns_count_characters() {
+    let errors = assemble_errors(".string \"é\", 1");
+    assert_eq!(errors[0].span.start, 14);
+    assert_eq!(errors[0].to_string(), "test.asm:1:14: .string takes 1 operand(s), found 2");
+}
+
+#[test]
+fn illegal_instruction() {
+    let (mut cpu, mut memory) = game::boot(&[0xF800]);
+    memory.write_u16(game::ILLEGAL_INSTRUCTION_VECTOR, 0x100);
+    let illegal = cpu.step(&mut memory).unwrap();
+    assert_eq!((illegal.address, illegal.word), (game::RESET_ADDR, 0xF800));
+    assert_eq!(illegal.code(), "E0034");
+    assert_eq!(cpu.registers[PC], 0x100);
+}
+
+#[test]
+fn several_files_share_their_labels() {
+    let sources = [
+        assembler::Source { file: "main.asm", text: "START: JSR #ROUTINE\nBRA #START" },
+        assembler::Source { file: "lib.asm", text: "ROUTINE: RTS" },
+    ];
+    let program = assembler::assemble_all(&sources, game::RESET_ADDR).unwrap();
+    assert_eq!(program.symbols["ROUTINE"], 0x18);
+    assert_eq!(program.items[2].file, 1);
+    assert_eq!(program.items[2].line, 0);
+
+    let sources = [
+        assembler::Source { file: "main.asm", text: "JMP #NOWHERE" },
+        assembler::Source { file: "lib.asm", text: "RTS\nFOO" },
+    ];
+    let errors: Vec<String> = assembler::assemble_all(&sources, game::RESET_ADDR).err().unwrap().iter()
+        .map(|error| error.to_string())
+        .collect();
+    assert_eq!(errors, ["main.asm:1:6: undefined label NOWHERE", "lib.asm:2:1: unknown mnemonic FOO"]);
+}
+
+#[test]
+fn directives() {
+    let program = assembler::assemble("test.asm", "
+        MOVE.L @MESSAGE, R0
+        MOVE @TABLE, R1
+        RTS
+        MESSAGE: .string \"Hi, you; \\\"xy\\\"!\"
+        .align 2
+        TABLE: .word 0xBEEF, -1, TABLE
+        BYTES: .byte 1, 255, -128
+        .space 3, 0xAA
+        .org 0x40
+        END: RTS
+        ", game::RESET_ADDR).unwrap();
+
+    assert_eq!(program.symbols["MESSAGE"], 0x1a);
+    assert_eq!(program.symbols["TABLE"], 0x2a);
+    assert_eq!(program.symbols["BYTES"], 0x30);
+    assert_eq!(program.symbols["END"], 0x40);
+
+    let bytes = program.bytes();
+    let at = |address: u16| (address - game::RESET_ADDR) as usize;
+    assert_eq!(&bytes[at(0x1a)..at(0x2a)], b"Hi, you; \"xy\"!\0\0");
+    assert_eq!(&bytes[at(0x2a)..at(0x30)], &[0xef, 0xbe, 0xff, 0xff, 0x2a, 0x00]);
+    assert_eq!(&bytes[at(0x30)..at(0x36)], &[1, 255, 128, 0xaa, 0xaa, 0xaa]);
+    assert_eq!(&bytes[at(0x36)..at(0x40)], &[0; 10]);
+
+    let (mut cpu, mut memory) = game::boot(&program.words());
+    cpu.step(&mut memory);
+    cpu.step(&mut memory);
+    assert_eq!(cpu.registers[0], u16::from(b'H'));
+    assert_eq!(cpu.registers[1], 0xbeef);
+}
+
+#[test]
+fn directive_errors() {
+    let errors: Vec<(usize, AsmErrorKind)> = assemble_errors("
+        .org 0x08
+        .byte 256
+        .word 1,
+        .string \"open
+        .space AFTER
+        .byte 1
+        RTS
+        .bogus
+        .align 2
+        AFTER: RTS")
+        .into_iter()
+        .map(|error| (error.line, error.kind))
+        .collect();
+    assert_eq!(errors, vec![
+        (2, AsmErrorKind::OrgBackwards { from: 0x10, to: 0x08 }),
+        (3, AsmErrorKind::ValueOutOfRange { value: 256, bits: 8 }),
+        (4, AsmErrorKind::BadOperand("".to_string())),
+        (5, AsmErrorKind::UnterminatedString),
+        (6, AsmErrorKind::ForwardReference("AFTER".to_string())),
+        (8, AsmErrorKind::MisalignedInstruction(0x11)),
+        (9, AsmErrorKind::UnknownDirective(".bogus".to_string())),
+    ]);
+}
+
+#[test]
+fn constants() {
+    let program = assembler::assemble("test.asm", "
+        STACK_TOP = 0xF0
+        .equ WIDTH, 4
+        HEIGHT = WIDTH + 1 ; 5
+        MOVE #STACK_TOP-2, R0
+        MOVE #(WIDTH*HEIGHT), R1
+        MOVE @BUFFER+2, R2
+        ADD #'A' - 'A' + 3, R3
+        ADD #LAST, R4
+        BUFFER: .word 0x1111, 0x2222
+        .equ LAST, END - BUFFER
+        END: .byte 'A' | 0x80, ~0 & 0x0F
+        ", game::RESET_ADDR).unwrap();
+
+    // LAST is only known once BUFFER and END are, its ADD takes an extension word
+    assert_eq!(program.symbols["BUFFER"], 0x22);
+    assert_eq!(program.words()[7..9], [0x1cc0, 0x0004]);
+    assert_eq!(program.bytes()[0x12..], [0x11, 0x11, 0x22, 0x22, 0xc1, 0x0f]);
+
+    let (mut cpu, mut memory) = game::boot(&program.words());
+    for _ in 0..5 {
+        cpu.step(&mut memory);
+    }
+    assert_eq!(cpu.registers[0..5], [0xee, 20, 0x2222, 3, 4]);
+}
+
+#[test]
+fn constant_errors() {
+    let errors: Vec<(usize, AsmErrorKind)> = assemble_errors("
+        .equ A, 1 / 0
+        B = C + 1
+        C = 2
+        .equ C, 3
+        ADD #(1 << 16), R0
+        ADD #(1 + ), R0
+        .byte 'A' << 2
+        .equ D")
+        .into_iter()
+        .map(|error| (error.line, error.kind))
+        .collect();
+    assert_eq!(errors, vec![
+        (2, AsmErrorKind::DivisionByZero),
+        (3, AsmErrorKind::ForwardReference("C + 1".to_string())),
+        (5, AsmErrorKind::DuplicateLabel { name: "C".to_string(), previous: Some(Location { file: "test.asm".to_string(), line: 4 }) }),
+        (6, AsmErrorKind::ValueOutOfRange { value: 65536, bits: 16 }),
+        (7, AsmErrorKind::BadOperand(")".to_string())),
+        (8, AsmErrorKind::ValueOutOfRange { value: 260, bits: 8 }),
+        (9, AsmErrorKind::OperandCount { mnemonic: ".equ".to_string(), expected: 2, found: 1 }),
+    ]);
+}
+
+#[test]
+fn macros() {
+    let program = assembler::assemble("test.asm", "
+        .macro SAVE first, second
+            PUSH \\first
+            PUSH \\second
+        .endm
+        .macro COUNT reg, times
+            MOVE #\\times, \\reg
+        LOOP: SUB #1, \\reg
+            BNE #LOOP
+        .endm
+        .macro BOTH
+            COUNT R0, 3
+            COUNT R1, 2
+        .endm
+        START: BOTH
+            SAVE R0, R1
+            RTS
+        ", game::RESET_ADDR).unwrap();
+
+    // Each expansion gets its own LOOP
+    assert_eq!(program.symbols["START"], game::RESET_ADDR);
+    assert_eq!(program.symbols["LOOP__2"], 0x14);
+    assert_eq!(program.symbols["LOOP__3"], 0x1e);
+    assert!(!program.symbols.contains_key("LOOP"));
+
+    // Every expanded instruction belongs to the line of the outermost call
+    let lines: Vec<usize> = program.items.iter().map(|item| item.line).collect();
+    assert_eq!(lines, [14, 14, 14, 14, 14, 14, 15, 15, 16]);
+
+    let (mut cpu, mut memory) = game::boot(&program.words());
+    cpu.registers[SP] = 0xf0;
+    for _ in 0..14 {
+        cpu.step(&mut memory);
+    }
+    assert_eq!(cpu.registers[0..2], [0, 0]);
+    assert_eq!(cpu.registers[SP], 0xec);
+}
+
+#[test]
+fn macro_errors() {
+    let errors = assemble_errors("
+        .macro BAD value
+            ADD #\\value, R8
+        .endm
+            BAD 1
+            BAD 1, 2
+        .endm
+        .macro LOOPING
+            LOOPING
+        .endm
+            LOOPING
+        .macro OPEN");
+
+    // The line of the macro, then the call
+    assert_eq!(errors[0].to_string(), "test.asm:3:21: bad operand R8\ntest.asm:5: in the expansion of BAD");
+
+    let errors: Vec<((&str, usize), AsmErrorKind)> = errors.iter()
+        .map(|error| (error.origin(), error.kind.clone()))
+        .collect();
+    assert_eq!(errors, vec![
+        (("test.asm", 5), AsmErrorKind::BadOperand("R8".to_string())),
+        (("test.asm", 6), AsmErrorKind::OperandCount { mnemonic: "BAD".to_string(), expected: 1, found: 2 }),
+        (("test.asm", 7), AsmErrorKind::UnexpectedEndm),
+        (("test.asm", 11), AsmErrorKind::MacroRecursion("LOOPING".to_string())),
+        (("test.asm", 12), AsmErrorKind::UnterminatedMacro("OPEN".to_string())),
+    ]);
+}
+
+/// Included files held in memory
+struct Files(&'static [(&'static str, &'static str)]);
+
+impl Loader for Files {
+    fn load(&self, path: &str, _from: &str) -> std::io::Result<(String, String)> {
+        self.0.iter()
+            .find(|(name, _)| *name == path)
+            .map(|(name, text)| (name.to_string(), text.to_string()))
+            .ok_or(std::io::Error::new(std::io::ErrorKind::NotFound, "no such file"))
+    }
+}
+
+#[test]
+fn includes() {
+    let files = Files(&[
+        ("consts.asm", "STACK_TOP = 0xF0\n.include \"macros.asm\""),
+        ("macros.asm", ".macro CLEAR reg\n    AND #0, \\reg\n.endm"),
+        ("routines.asm", "ROUTINE: CLEAR R1\n    RTS"),
+    ]);
+    let sources = [assembler::Source { file: "main.asm", text: ".include \"consts.asm\"\n    MOVE #STACK_TOP, R0\n    JSR #ROUTINE\n.include \"routines.asm\"" }];
+    let program = assembler::assemble_with(&sources, &assembler::Settings { origin: game::RESET_ADDR, loader: &files, defines: &[], relocatable: false, dialect: Dialect::default() }).unwrap();
+
+    let names: Vec<&str> = program.files.iter().map(|file| file.name.as_str()).collect();
+    assert_eq!(names, ["main.asm", "consts.asm", "macros.asm", "routines.asm"]);
+    assert_eq!(program.symbols["ROUTINE"], 0x18);
+
+    // The lines of an included file belong to it
+    let lines: Vec<(&str, usize)> = program.items.iter()
+        .map(|item| (names[item.file], item.line))
+        .collect();
+    assert_eq!(lines, [("main.asm", 1), ("main.asm", 2), ("routines.asm", 0), ("routines.asm", 1)]);
+}
+
+#[test]
+fn include_errors() {
+    let files = Files(&[
+        ("a.asm", ".include \"b.asm\""),
+        ("b.asm", "    ADD R0, R9\n.include \"a.asm\""),
+    ]);
+    let sources = [assembler::Source { file: "main.asm", text: ".include \"a.asm\"\n.include \"none.asm\"\n.include none.asm" }];
+    let errors: Vec<String> = match assembler::assemble_with(&sources, &assembler::Settings { origin: game::RESET_ADDR, loader: &files, defines: &[], relocatable: false, dialect: Dialect::default() }) {
+        Ok(_) => panic!("the includes should fail"),
+        Err(errors) => errors.iter().map(|error| error.to_string()).collect(),
+    };
+
+    assert_eq!(errors, [
+        "b.asm:1:13: bad operand R9",
+        "b.asm:2:10: a.asm is already being included",
+        "main.asm:2:10: can't include none.asm: no such file",
+        "main.asm:3:10: expected a string between double quotes",
+    ]);
+}
+
+#[test]
+fn include_cycle_through_a_parent_directory() {
+    let directory = std::env::temp_dir().join(format!("proco-include-{}", std::process::id()));
+    std::fs::create_dir_all(directory.join("sub")).unwrap();
+    std::fs::write(directory.join("a.asm"), ".include \"sub/b.asm\"").unwrap();
+    std::fs::write(directory.join("sub/b.asm"), ".include \"../a.asm\"").unwrap();
+
+    let file = directory.join("a.asm").display().to_string();
+    let sources = [assembler::Source { file: &file, text: ".include \"sub/b.asm\"" }];
+    let result = assembler::assemble_with(&sources, &assembler::Settings { origin: game::RESET_ADDR, loader: &FileLoader::default(), defines: &[], relocatable: false, dialect: Dialect::default() });
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    let errors: Vec<AsmErrorKind> = result.err().unwrap().into_iter().map(|error| error.kind).collect();
+    let included = directory.join("sub").join("../a.asm").display().to_string();
+    assert_eq!(errors, [AsmErrorKind::IncludeCycle(included)]);
+}
+
+#[test]
+fn conditionals() {
+    let source = "
+        .ifndef DEBUG
+        DEBUG = 0
+        .endif
+        .if DEBUG & 1
+            MOVE #1, R0
+        .elif DEBUG & 2
+            MOVE #2, R0
+        .else
+            MOVE #3, R0
+            .ifdef NOWHERE
+                not an instruction
+            .endif
+        .endif
+        .if 0
+            .bogus
+            .macro NEVER
+        .endif
+            MOVE #DEBUG, R1";
+
+    for (defines, first) in [(vec![], 3), (vec![("DEBUG".to_string(), 1)], 1), (vec![("DEBUG".to_string(), 2)], 2)] {
+        let settings = assembler::Settings { origin: game::RESET_ADDR, loader: &Files(&[]), defines: &defines, relocatable: false, dialect: Dialect::default() };
+        let program = assembler::assemble_with(&[assembler::Source { file: "test.asm", text: source }], &settings).unwrap();
+
+        let words = program.words();
+        assert_eq!(words.len(), 4, "{:?}", defines);
+        assert_eq!([words[1], words[3]], [first, defines.first().map_or(0, |(_, value)| *value as u16)]);
+    }
+}
+
+#[test]
+fn conditional_errors() {
+    let errors: Vec<(usize, AsmErrorKind)> = assemble_errors("
+        .if 1
+        .else
+        .elif 1
+        .endif
+        .endif
+        .else
+        .if NOWHERE
+        .endif
+        .ifdef 1X
+        .endif
+        .if 0
+        .elif 1 / 0
+        .endif
+        .if 1
+            RTS")
+        .into_iter()
+        .map(|error| (error.line, error.kind))
+        .collect();
+    assert_eq!(errors, vec![
+        (4, AsmErrorKind::ConditionalAfterElse(".elif".to_string())),
+        (6, AsmErrorKind::UnmatchedConditional(".endif".to_string())),
+        (7, AsmErrorKind::UnmatchedConditional(".else".to_string())),
+        (8, AsmErrorKind::UndefinedLabel("NOWHERE".to_string())),
+        (10, AsmErrorKind::BadOperand("1X".to_string())),
+        (13, AsmErrorKind::DivisionByZero),
+        (15, AsmErrorKind::UnterminatedConditional(".if".to_string())),
+    ]);
+}
+
+fn assemble_errors(code: &str) -> Vec<AsmError> {
+    match assembler::assemble("test.asm", code, game::RESET_ADDR) {
+        Ok(_) => panic!("{} should not assemble", code),
+        Err(errors) => errors,
+    }
+}
+
+#[test]
+fn label_errors() {
+    let errors = assemble_errors("BRA #NOWHERE");
+    assert_eq!(errors[0].kind, AsmErrorKind::UndefinedLabel("NOWHERE".to_string()));
+    assert_eq!(errors[0].span, Span { start: 5, end: 12 });
+
+    let errors = assemble_errors("HERE: RTS\nHERE: RTS");
+    assert_eq!(errors[0].to_string(), "test.asm:2:1: HERE is already defined at test.asm:1");
+
+    let errors: Vec<(usize, AsmErrorKind)> = assemble_errors("
+        FIRST:
+        .loop: RTS
+        SECOND:
+        .loop: RTS
+        .loop: RTS
+        1: RTS
+        1: BRA #1f
+        .macro M
+        .endm
+        .macro M
+        .endm
+        .macro ADD
+        .endm")
+        .into_iter()
+        .map(|error| (error.line, error.kind))
+        .collect();
+    let at = |line| Some(Location { file: "test.asm".to_string(), line });
+    assert_eq!(errors, vec![
+        (6, AsmErrorKind::DuplicateLabel { name: ".loop".to_string(), previous: at(5) }),
+        (8, AsmErrorKind::UndefinedLabel("1f".to_string())),
+        (11, AsmErrorKind::DuplicateLabel { name: "M".to_string(), previous: at(9) }),
+        (13, AsmErrorKind::DuplicateLabel { name: "ADD".to_string(), previous: None }),
+    ]);
+}
+
+#[test]
+fn local_labels() {
+    let program = assembler::assemble("test.asm", "
+        FIRST:  MOVE #2, R0
+        .loop:  SUB #1, R0
+                BNE #.loop
+        SECOND: MOVE #3, R1
+        .loop:  SUB #1, R1
+                BNE #.loop
+                BRA #END
+                .word FIRST.loop, .loop
+        END:    RTS
+        ", game::RESET_ADDR).unwrap();
+
+    assert!(!program.symbols.contains_key(".loop"));
+    let (first, second) = (program.symbols["FIRST.loop"], program.symbols["SECOND.loop"]);
+    assert!(first < second);
+
+    // Each .loop counts down its own register
+    let (mut cpu, mut memory) = game::boot(&program.words());
+    for _ in 0..100 {
+        if cpu.registers[PC] == program.symbols["END"] {
+            break;
+        }
+        cpu.step(&mut memory);
+    }
+    assert_eq!(cpu.registers[0..2], [0, 0]);
+
+    let words = program.words();
+    let data = (program.symbols["END"] - game::RESET_ADDR) as usize / 2 - 2;
+    assert_eq!(words[data..data + 2], [first, second]);
+}
+
+#[test]
+fn numeric_labels() {
+    let program = assembler::assemble("test.asm", "
+                MOVE #3, R0
+        1:      SUB #1, R0
+                BNE #1b
+                BRA #1f
+                MOVE #0xdead, R1
+        1:      MOVE #2, R2
+        END:    BRA #END
+        2:      .word 1b, 2f
+        2:
+        ", game::RESET_ADDR).unwrap();
+
+    let (mut cpu, mut memory) = game::boot(&program.words());
+    for _ in 0..100 {
+        if cpu.registers[PC] == program.symbols["END"] {
+            break;
+        }
+        cpu.step(&mut memory);
+    }
+    assert_eq!(cpu.registers[0..3], [0, 0, 2]);
+
+    // 1b is the last 1 before the line, 2f the next 2 after it
+    let words = program.words();
+    let data = (program.symbols["END"] - game::RESET_ADDR) as usize / 2 + 2;
+    assert_eq!(words[data..], [program.symbols["END"] - 4, words.len() as u16 * 2 + game::RESET_ADDR]);
+}
+
+#[test]
+fn every_error_is_reported() {
+    let errors = assemble_errors("ADD R0, R1
+        FOO R0, R1
+        ADD R0, R8
+        ADD R0, @0x10
+        PUSH ?R0
+        MOVE.X R0, R1
+        RTS
+        ADD @70000, R0
+        ADD R0, R1, R2");
+    let errors: Vec<(usize, usize, AsmErrorKind)> = errors.into_iter()
+        .map(|error| (error.line, error.span.start, error.kind))
+        .collect();
+    assert_eq!(errors, vec![
+        (2, 8, AsmErrorKind::UnknownMnemonic("FOO".to_string())),
+        (3, 16, AsmErrorKind::BadOperand("R8".to_string())),
+        (4, 16, AsmErrorKind::IllegalAddressingMode { mnemonic: "ADD".to_string(), operand: "@0x10".to_string(), role: "destination".to_string(), allowed: isa::REGISTER }),
+        (5, 13, AsmErrorKind::BadOperand("?R0".to_string())),
+        (6, 8, AsmErrorKind::UnknownMnemonic("MOVE.X".to_string())),
+        (8, 12, AsmErrorKind::ValueOutOfRange { value: 70000, bits: 16 }),
+        (9, 20, AsmErrorKind::OperandCount { mnemonic: "ADD".to_string(), expected: 2, found: 3 }),
+    ]);
+}
+
+#[test]
+fn symbol_map() {
+    let program = assembler::assemble("prog.asm", "\
+SIZE = 4
+        .macro WAIT
+            BNE #LOOP
+        .endm
+START:  MOVE #SIZE, R0
+LOOP:   SUB #1, R0
+        WAIT
+        WAIT
+END_ADDR = END + SIZE
+END:    RTS", game::RESET_ADDR).unwrap();
+
+    let mut map = Vec::new();
+    symbols::write_symbols(&mut map, &program.map).unwrap();
+    let map = String::from_utf8(map).unwrap();
+    assert_eq!(map, "\
+; value name     kind     section defined    references
+0004  SIZE     constant abs     prog.asm:1 prog.asm:5 prog.asm:9
+0010  START    label    text    prog.asm:5 unused
+0014  LOOP     label    text    prog.asm:6 prog.asm:7 prog.asm:8
+001e  END      label    text    prog.asm:10 prog.asm:9
+0022  END_ADDR constant text    prog.asm:9 unused
+");
+
+    // The tools only want the addresses
+    let labels = symbols::read_symbols(map.as_bytes()).unwrap();
+    assert_eq!(labels, program.symbols);
+
+    let error = symbols::read_symbols("0010 START\nSTART 0010".as_bytes()).err().unwrap();
+    assert_eq!(FileError::of(&error), Some(&FileError::SymbolLine { line: 2, text: "START 0010".to_string() }));
+}
+
+#[test]
+fn sections() {
+    let program = assembler::assemble("test.asm", "
+        MOVE @VALUE, R0
+        .data
+VALUE:  .word 7
+        .bss
+BUFFER: .space 3
+        .text
+        MOVE R0, @BUFFER
+        RTS", game::RESET_ADDR).unwrap();
+
+    // .data then .bss follow .text, on even addresses
+    let text = game::RESET_ADDR;
+    assert_eq!(program.sections, vec![
+        assembler::Placement { section: Section::Text, start: text, size: 10, alignment: 2 },
+        assembler::Placement { section: Section::Data, start: text + 10, size: 2, alignment: 2 },
+        assembler::Placement { section: Section::Bss, start: text + 12, size: 3, alignment: 2 },
+    ]);
+    assert_eq!(program.symbols["VALUE"], text + 10);
+    assert_eq!(program.symbols["BUFFER"], text + 12);
+    // .bss isn't in the image
+    assert_eq!(program.bytes().len(), 12);
+
+    let (mut cpu, mut memory) = game::boot(&program.words());
+    for _ in 0..2 {
+        cpu.step(&mut memory);
+    }
+    assert_eq!(memory.read_u16(text + 12), 7);
+}
+
+#[test]
+fn aligned_sections() {
+    let program = assemble("
+        MOVE #1, R0
+        RTS
+        .data
+        .byte 1
+        .align 4
+TABLE:  .word 0x1234
+        .bss
+        .space 1
+        .align 8
+BUFFER: .space 2
+        .text
+        MOVE @TABLE, R1");
+
+    // The sections start on a multiple of their alignments, the padding is the same in both passes
+    let table = program.symbols["TABLE"];
+    assert_eq!(table % 4, 0);
+    let offset = (table - program.origin) as usize;
+    assert_eq!(program.bytes()[offset..offset + 2], [0x34, 0x12]);
+    assert_eq!(program.words()[4], table);
+    assert_eq!(program.symbols["BUFFER"] % 8, 0);
+    assert_eq!(program.sections[2].start % 8, 0);
+}
+
+#[test]
+fn section_errors() {
+    let errors: Vec<AsmErrorKind> = assemble_errors(".data\n.org 0x100\n.bss\n.word 1\n.space 2, 0xff\n.space 2\n.align 2\n.word 0\n.space 2, 0\nRTS\n.string \"\"\n.global MISSING")
+        .into_iter()
+        .map(|error| error.kind)
+        .collect();
+    assert_eq!(errors, vec![
+        AsmErrorKind::OrgOutsideText,
+        AsmErrorKind::DataInBss,
+        AsmErrorKind::DataInBss,
+        // Even when they are worth 0
+        AsmErrorKind::DataInBss,
+        AsmErrorKind::DataInBss,
+        AsmErrorKind::DataInBss,
+        AsmErrorKind::DataInBss,
+        AsmErrorKind::UndefinedExport("MISSING".to_string()),
+    ]);
+
+    // The linker only adds an address to a word
+    let settings = assembler::Settings { origin: 0, loader: &FileLoader::default(), defines: &[], relocatable: true, dialect: Dialect::default() };
+    let source = assembler::Source { file: "test.asm", text: "START: MOVE #START * 2, R0\n.data\n.byte START" };
+    let errors: Vec<AsmErrorKind> = assembler::assemble_with(&[source], &settings).err().unwrap()
+        .into_iter()
+        .map(|error| error.kind)
+        .collect();
+    assert_eq!(errors, vec![
+        AsmErrorKind::NotRelocatable("#START * 2".to_string()),
+        AsmErrorKind::NotRelocatable("START".to_string()),
+    ]);
+}
+
+#[test]
+fn address_overflow() {
+    // The addresses stop at 0xFFFF instead of wrapping around to 0
+    for (code, lines) in [(".org 0xFFFC\nRTS\nRTS\nRTS\nRTS", vec![4, 5]), (".org 0xFFFE\nMOVE #1, R0", vec![2]), (".org 0xFFFC\nRTS\nRTS\n.data\n.word 1", vec![5])] {
+        let errors = assemble_errors(code);
+        assert!(errors.iter().all(|error| error.kind == AsmErrorKind::Overflow), "{}", code);
+        assert_eq!(errors.iter().map(|error| error.line).collect::<Vec<_>>(), lines, "{}", code);
+    }
+
+    let program = assemble(".org 0xFFFC\nRTS\nRTS");
+    assert_eq!(program.bytes().len(), 0x10000 - program.origin as usize);
+
+    // Aligned on the end of the memory, nothing fits there
+    let errors = assemble_errors(".org 0xFFFF\n.align 2\nX: RTS");
+    assert_eq!(errors[0].kind, AsmErrorKind::Overflow);
+    assert_eq!(errors[0].line, 2);
+}
+
+#[test]
+fn negative_sizes() {
+    for code in [".space -1", ".data\n.space -2, 0xff", ".align -2", ".org -1"] {
+        let errors = assemble_errors(code);
+        assert!(matches!(errors[..], [AsmError { kind: AsmErrorKind::ValueOutOfRange { value, bits: 16 }, .. }] if value < 0), "{}", code);
+    }
+}
+
+#[test]
+fn relocatable_object() {
+    let settings = assembler::Settings { origin: 0x1234, loader: &FileLoader::default(), defines: &[], relocatable: true, dialect: Dialect::default() };
+    let source = assembler::Source { file: "test.asm", text: "
+        .global START, SIZE
+        .extern PRINT
+SIZE = 16
+START:  MOVE #MESSAGE, R0
+        JSR #PRINT
+        BRA #START
+        .data
+MESSAGE: .word START" };
+    let program = assembler::assemble_with(&[source], &settings).unwrap();
+
+    let object = Object::new(&program);
+    assert_eq!(object.exports, vec![
+        Export { name: "START".to_string(), section: Some(Section::Text), value: 0 },
+        Export { name: "SIZE".to_string(), section: None, value: 16 },
+    ]);
+    assert_eq!(object.imports, vec!["PRINT".to_string()]);
+
+    let relocation = |section, offset, target| Relocation { section, offset, pc_relative: false, target };
+    assert_eq!(object.relocations, vec![
+        relocation(Section::Text, 2, Target::Section(Section::Data)),
+        relocation(Section::Text, 6, Target::Symbol("PRINT".to_string())),
+        relocation(Section::Data, 0, Target::Section(Section::Text)),
+    ]);
+}
+
+#[test]
+fn dialects() {
+    let lenient = "
+1:      Move.L\t#0x1f, r0
+        push\tR0 ; a comment
+        mOvE\t( sp )+, pc
+        bra #1B";
+    let strict = "
+1:      MOVE.L #0x1F, R0
+        PUSH R0
+        MOVE (R7)+, R6
+        BRA #1b";
+    assert_eq!(assemble(lenient).words(), assemble(strict).words());
+
+    let settings = |dialect| assembler::Settings { origin: game::RESET_ADDR, loader: &Files(&[]), defines: &[], relocatable: false, dialect };
+    let errors: Vec<AsmErrorKind> = assembler::assemble_with(&[assembler::Source { file: "test.asm", text: lenient }], &settings(Dialect::STRICT))
+        .err().unwrap()
+        .into_iter()
+        .map(|error| error.kind)
+        .collect();
+    assert_eq!(errors, vec![
+        AsmErrorKind::UnknownMnemonic("Move.L".to_string()),
+        AsmErrorKind::UnknownMnemonic("push".to_string()),
+        AsmErrorKind::UnknownMnemonic("mOvE".to_string()),
+        AsmErrorKind::UnknownMnemonic("bra".to_string()),
+    ]);
+
+    // `*` starts a comment line, and is still a product elsewhere
+    let source = assembler::Source { file: "test.asm", text: "* the header\n    * indented\nADD #2 * 3, R0" };
+    let program = assembler::assemble_with(&[source], &settings(Dialect { star_comments: true, ..Dialect::default() })).unwrap();
+    assert_eq!(program.words(), assemble("ADD #6, R0").words());
+}
+
+#[test]
+fn diagnostics() {
+    let render = |error: &AsmError| {
+        let mut output = termcolor::NoColor::new(Vec::new());
+        error.diagnostic().write(&mut output).unwrap();
+        String::from_utf8(output.into_inner()).unwrap()
+    };
+
+    let errors = assemble_errors("
+.macro SAVE reg
+    PUSH \\reg
+.endm
+        ADD R0, @0x10
+\tMVOE R1,
+        SAVE 0x20");
+    let rendered: Vec<String> = errors.iter().map(render).collect();
+    assert_eq!(rendered, vec![
+        "\
+error[E0004]: ADD can't use @0x10 as destination, its destination has to be one of Rn
+ --> test.asm:5:17
+  |
+5 |         ADD R0, @0x10
+  |                 ^^^^^
+  = help: ADD only accepts a register destination; did you mean MOVE?
+
+",
+        // A tab is 4 columns, a missing operand still gets a caret
+        "\
+error[E0001]: unknown mnemonic MVOE
+ --> test.asm:6:2
+  |
+6 |     MVOE R1,
+  |     ^^^^
+  = help: did you mean MOVE?
+
+",
+        "\
+error[E0002]: bad operand 0x20
+ --> test.asm:3:10
+  |
+3 |     PUSH 0x20
+  |          ^^^^
+  = note: in the expansion of SAVE at test.asm:7
+
+",
+    ]);
+
+    assert_eq!(isa::suggest("jsrr"), Some("JSR"));
+    assert_eq!(isa::suggest("BRANCH"), None);
+    assert_eq!(utils::ColorMode::parse("always"), Ok(utils::ColorMode::Always));
+    assert!(utils::ColorMode::parse("sometimes").is_err());
+}
+
+#[test]
+fn error_codes() {
+    let codes: Vec<&str> = assemble_errors("
+HERE: .word 1
+HERE: MVOE R0
+        ADD R0, @0x10
+        .word 1 / 0").iter().map(|error| error.kind.code()).collect();
+    assert_eq!(codes, vec!["E0001", "E0007", "E0004", "E0014"]);
+    assert_eq!(AsmErrorKind::DuplicateLabel { name: "ADD".to_string(), previous: None }.code(), "E0008");
+
+    catalog::set_language(Language::Fr);
+    let errors = assemble_errors("
+.macro SAVE reg
+    PUSH \\reg
+.endm
+        ADD R0, @0x10
+        SAVE 0x20");
+    let messages: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+    let help = errors[0].kind.help();
+    catalog::set_language(Language::En);
+
+    assert_eq!(messages, vec![
+        "test.asm:5:17: ADD ne peut pas utiliser @0x10 comme destination, les modes permis sont Rn",
+        "test.asm:3:10: opérande invalide 0x20\ntest.asm:6: dans l'expansion de SAVE",
+    ]);
+    assert_eq!(help.as_deref(), Some("ADD n'accepte qu'un registre comme destination ; vouliez-vous dire MOVE ?"));
+    assert_eq!(errors[1].to_string(), "test.asm:3:10: bad operand 0x20\ntest.asm:6: in the expansion of SAVE");
+    assert!(catalog::explain("E0007", Language::En).is_some_and(|text| text.contains("only be defined once")));
+}
+
+#[test]
+fn pseudo_instructions() {
+    let pseudo = assemble("
+START:  CLR R0
+        inc r1
+        DEC R2
+        NEG R3
+        TST R4
+        CALL START
+        SWAP R5, @0x40
+        LI SP, #0x1234
+        RET");
+    let expanded = assemble("
+START:  XOR R0, R0
+        ADD #1, R1
+        SUB #1, R2
+        NOT R3
+        ADD #1, R3
+        CMP #0, R4
+        JSR #START
+        PUSH R5
+        MOVE @0x40, R5
+        POP @0x40
+        MOVE #0x1234, SP
+        RTS");
+    assert_eq!(pseudo.words(), expanded.words());
+
+    // A swap through the stack leaves SP as it was
+    let (mut cpu, mut memory) = setup_simple_cpu("LI R1, #-2\nLI R2, #7\nSWAP R1, R2\nNEG R2");
+    let sp = cpu.registers[SP];
+    for _ in 0..7 {
+        cpu.step(&mut memory);
+    }
+    assert_eq!((cpu.registers[1], cpu.registers[2], cpu.registers[SP]), (7, 2, sp));
+
+    // The operands are checked by the expansion, `LI = 2` is still a constant
+    let errors = assemble_errors("CLR @0x10\nLI R0, 5\nRET R0\nLI = 2\n.word LI");
+    assert_eq!(errors.len(), 3);
+    assert_eq!(errors[0].to_string(), "test.asm:1:12: XOR can't use @0x10 as destination, its destination has to be one of Rn\ntest.asm:1: in the expansion of CLR");
+    assert_eq!(errors[1].kind, AsmErrorKind::BadOperand("5".to_string()));
+    assert_eq!(errors[2].kind, AsmErrorKind::OperandCount { mnemonic: "RET".to_string(), expected: 0, found: 1 });
+
+    // A macro takes the place of the pseudo-instruction
+    assert_eq!(assemble(".macro INC reg\n    ADD #2, \\reg\n.endm\nINC R0").words(), assemble("ADD #2, R0").words());
+}
@@ -23,9 +23,7 @@ impl<const N: usize> BitInt<N> {
         if N > 16 {
             panic!("BitInt cannot represent more than 16 bits");
         }
-        if N == 16 {
-            Some(BitInt::<N>(value))
-        } else if value < (1 << N) {
+        if N == 16 || value < (1 << N) {
             Some(BitInt::<N>(value))
         } else {
             None
@@ -54,14 +52,75 @@ impl<const N: usize> PartialOrd for BitInt<N> {
 }
 
 
-use std::io::{self, Write};
+use crate::catalog;
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicU8, Ordering};
 use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
 
+/// When the output is colored, set once by `--color`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorMode {
+    /// Only on a terminal, and never when NO_COLOR is set
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorMode {
+    /// `auto`, `always` or `never`
+    pub fn parse(value: &str) -> Result<ColorMode, String> {
+        match value {
+            "auto" => Ok(ColorMode::Auto),
+            "always" => Ok(ColorMode::Always),
+            "never" => Ok(ColorMode::Never),
+            _ => Err(format!("unknown color mode {}, expected auto, always or never", value)),
+        }
+    }
+}
+
+static COLOR_MODE: AtomicU8 = AtomicU8::new(ColorMode::Auto as u8);
+
+/// Color the next outputs according to `mode`
+pub fn set_color_mode(mode: ColorMode) {
+    COLOR_MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+pub fn color_mode() -> ColorMode {
+    match COLOR_MODE.load(Ordering::Relaxed) {
+        mode if mode == ColorMode::Always as u8 => ColorMode::Always,
+        mode if mode == ColorMode::Never as u8 => ColorMode::Never,
+        _ => ColorMode::Auto,
+    }
+}
+
+/// How to color a stream, `terminal` telling whether it is one
+fn color_choice(terminal: bool) -> ColorChoice {
+    // An empty NO_COLOR doesn't count, see no-color.org
+    let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+
+    match color_mode() {
+        ColorMode::Always => ColorChoice::Always,
+        ColorMode::Auto if terminal && !no_color => ColorChoice::Auto,
+        _ => ColorChoice::Never,
+    }
+}
+
+pub fn stdout() -> StandardStream {
+    StandardStream::stdout(color_choice(io::stdout().is_terminal()))
+}
+
+pub fn stderr() -> StandardStream {
+    StandardStream::stderr(color_choice(io::stderr().is_terminal()))
+}
+
+fn write_color_to(mut stream: StandardStream, str: &str, color: Color) -> io::Result<()> {
+    stream.set_color(ColorSpec::new().set_fg(Some(color)))?;
+    writeln!(&mut stream, "{}", str)?;
+    stream.reset()
+}
+
 fn write_color(str: &str, color: Color) -> io::Result<()> {
-    let mut stdout = StandardStream::stdout(ColorChoice::Always);
-    stdout.set_color(ColorSpec::new().set_fg(Some(color)))?;
-    writeln!(&mut stdout, "{}", str)?;
-    stdout.set_color(ColorSpec::new().set_fg(Some(Color::White)))
+    write_color_to(stdout(), str, color)
 }
 
 pub fn log(str: &str) {
@@ -78,6 +137,14 @@ pub fn alert(str: &str) {
     }
 }
 
+/// Same as `alert`, on stderr
+pub fn fail(str: &str) {
+    match write_color_to(stderr(), str, Color::Red) {
+        Ok(()) => {}
+        Err(err) => eprintln!("{}", err)
+    }
+}
+
 pub fn info(str: &str) {
     match write_color(str, Color::White) {
         Ok(()) => {}
@@ -92,3 +159,103 @@ pub fn shape(str: &str) {
     }
 }
 
+
+/// The part of a source line a diagnostic points at
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snippet<'a> {
+    pub file: &'a str,
+    /// Line number, 1 based
+    pub line: usize,
+    pub text: &'a str,
+    /// Bytes of `text` to underline (end excluded), a single caret when empty
+    pub start: usize,
+    pub end: usize,
+}
+
+/// A message in the style of rustc : the message, where it comes from, the
+/// line with the faulty part underlined, then the help and the notes
+///
+/// ```text
+/// error[E0004]: ADD can't use @0x10 as destination, its destination has to be one of Rn
+///  --> prog.asm:3:13
+///   |
+/// 3 |     ADD R0, @0x10
+///   |             ^^^^^
+///   = help: ADD only accepts a register destination; did you mean MOVE?
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic<'a> {
+    /// Code of the error, see `catalog`
+    pub code: Option<&'static str>,
+    pub message: String,
+    pub snippet: Option<Snippet<'a>>,
+    pub help: Option<String>,
+    pub notes: Vec<String>,
+}
+
+/// Width of `text` once its tabs are expanded, as the snippets are written
+fn width(text: &str) -> usize {
+    text.chars().map(|c| if c == '\t' { 4 } else { 1 }).sum()
+}
+
+impl Diagnostic<'_> {
+    pub fn write<W: WriteColor>(&self, writer: &mut W) -> io::Result<()> {
+        let bold = |color: Color| ColorSpec::new().set_fg(Some(color)).set_bold(true).clone();
+        let gutter = bold(Color::Blue);
+
+        writer.set_color(&bold(Color::Red))?;
+        write!(writer, "{}", catalog::text("error", &[]))?;
+        if let Some(code) = self.code {
+            write!(writer, "[{}]", code)?;
+        }
+        writer.set_color(ColorSpec::new().set_bold(true))?;
+        writeln!(writer, ": {}", self.message)?;
+        writer.reset()?;
+
+        let digits = self.snippet.as_ref().map_or(0, |snippet| snippet.line.to_string().len());
+        let margin = " ".repeat(digits);
+
+        if let Some(snippet) = &self.snippet {
+            let column = snippet.text[..snippet.start].chars().count() + 1;
+            writer.set_color(&gutter)?;
+            write!(writer, "{}--> ", margin)?;
+            writer.reset()?;
+            writeln!(writer, "{}:{}:{}", snippet.file, snippet.line, column)?;
+
+            writer.set_color(&gutter)?;
+            writeln!(writer, "{} |", margin)?;
+            write!(writer, "{} | ", snippet.line)?;
+            writer.reset()?;
+            writeln!(writer, "{}", snippet.text.replace('\t', "    "))?;
+
+            writer.set_color(&gutter)?;
+            write!(writer, "{} | ", margin)?;
+            writer.set_color(&bold(Color::Red))?;
+            let underlined = width(&snippet.text[snippet.start..snippet.end]).max(1);
+            writeln!(writer, "{}{}", " ".repeat(width(&snippet.text[..snippet.start])), "^".repeat(underlined))?;
+            writer.reset()?;
+        }
+
+        let (help, note) = (catalog::text("help", &[]), catalog::text("note", &[]));
+        let extra = self.help.iter().map(|text| (&help, text))
+            .chain(self.notes.iter().map(|text| (&note, text)));
+        for (label, text) in extra {
+            writer.set_color(&gutter)?;
+            write!(writer, "{} = ", margin)?;
+            writer.set_color(ColorSpec::new().set_bold(true))?;
+            write!(writer, "{}", label)?;
+            writer.reset()?;
+            writeln!(writer, ": {}", text)?;
+        }
+
+        // A blank line between two diagnostics
+        writeln!(writer)
+    }
+}
+
+/// Write `diagnostic` on stderr
+pub fn report(diagnostic: &Diagnostic) {
+    if let Err(err) = diagnostic.write(&mut stderr()) {
+        eprintln!("{}", err)
+    }
+}